@@ -19,6 +19,8 @@ class DataFileInfo:
         assert meta.pop("input_bool_shape") == list(game.input_bool_shape)
         assert meta.pop("input_scalar_count") == game.input_scalar_channels
         assert meta.pop("policy_shape") == list(game.policy_shape)
+        # "V1" or {"V2": {"history": n}}, the bool shape above already depends on it
+        self.input_encoding = meta.pop("input_encoding", "V1")
 
         self.game = game
         self.bin_path = bin_path
@@ -33,6 +35,13 @@ class DataFileInfo:
         self.max_simulation_length = meta.pop("max_game_length")
         self.root_wdl = meta.pop("root_wdl", None)
         self.hit_move_limit = meta.pop("hit_move_limit", None)
+        self.mean_kdl_policy = meta.pop("mean_kdl_policy", None)
+        self.mean_value_surprise_final = meta.pop("mean_value_surprise_final", None)
+        self.mean_value_surprise_zero = meta.pop("mean_value_surprise_zero", None)
+        self.mean_policy_entropy = meta.pop("mean_policy_entropy", None)
+        self.mean_visited_mv_count = meta.pop("mean_visited_mv_count", None)
+        # per game dicts with net_hash, settings, seed and start_fen, or None
+        self.game_provenance = meta.pop("game_provenance", [])
         self.includes_simulation_start_indices = meta.pop("includes_game_start_indices", False)
 
         total_move_count = self.position_count - self.includes_final_positions * self.simulation_count
@@ -10,6 +10,13 @@ from lib.games import Game
 from lib.util import DEVICE, prod, map_none, map_none_or
 
 
+def pop_wdl(scalars, prefix: str, default: List[float]) -> np.ndarray:
+    wdl = [scalars.pop(f"{prefix}_wdl_{k}", None) for k in "wdl"]
+    if any(x is None for x in wdl):
+        return np.array(default)
+    return np.array(wdl)
+
+
 @dataclass
 class Simulation:
     index: int
@@ -88,13 +95,24 @@ class Position:
         self.net_v = float(scalars.pop("net_v"))
         # print(self.net_v)
 
-        # self.final_wdl = np.array([scalars.pop("final_wdl_w"), scalars.pop("final_wdl_d"), scalars.pop("final_wdl_l")])
-        # self.zero_wdl = np.array([scalars.pop("zero_wdl_w"), scalars.pop("zero_wdl_d"), scalars.pop("zero_wdl_l")])
-        # self.net_wdl = np.array([scalars.pop("net_wdl_w"), scalars.pop("net_wdl_d"), scalars.pop("net_wdl_l")])
+        # files written before the WDL targets existed only have the values
+        final_wdl_from_v = [float(self.final_v == 1), float(self.final_v == 0), float(self.final_v == -1)]
+        self.final_wdl = pop_wdl(scalars, "final", final_wdl_from_v)
+        self.zero_wdl = pop_wdl(scalars, "zero", [np.nan] * 3)
+        self.net_wdl = pop_wdl(scalars, "net", [np.nan] * 3)
+
+        self.final_moves_left = float(scalars.pop("final_moves_left", move_count - self.move_index))
+        self.zero_moves_left = float(scalars.pop("zero_moves_left", np.nan))
+        self.net_moves_left = float(scalars.pop("net_moves_left", np.nan))
+
+        # search quality metrics, only used for logging
+        self.value_surprise_final = float(scalars.pop("value_surprise_final", np.nan))
+        self.value_surprise_zero = float(scalars.pop("value_surprise_zero", np.nan))
+        self.policy_entropy = float(scalars.pop("policy_entropy", np.nan))
+        self.visited_mv_count = int(scalars.pop("visited_mv_count", 0))
 
-        # self.final_moves_left = float(scalars.pop("final_moves_left", move_count - self.move_index))
-        # self.zero_moves_left = float(scalars.pop("zero_moves_left", np.nan))
-        # self.net_moves_left = float(scalars.pop("net_moves_left", np.nan))
+        # first 24 bits of the network hash, nan if unknown
+        self.net_id = float(scalars.pop("net_id", np.nan))
 
         if len(scalars):
             print(f"Leftover scalars: {list(scalars.keys())}")
@@ -254,15 +272,15 @@ class PositionBatch:
                 assert p.final_position is not None
                 write_input(game, final_input_full[i, :, :, :], p.final_position)
 
-            # all_wdls[i, 0:3] = torch.from_numpy(p.final_wdl)
-            # all_wdls[i, 3:6] = torch.from_numpy(p.zero_wdl)
-            # all_wdls[i, 6:9] = torch.from_numpy(p.net_wdl)
+            all_wdls[i, 0:3] = torch.from_numpy(p.final_wdl)
+            all_wdls[i, 3:6] = torch.from_numpy(p.zero_wdl)
+            all_wdls[i, 6:9] = torch.from_numpy(p.net_wdl)
             all_values[i, 0] = p.final_v
             all_values[i, 1] = p.zero_v
             all_values[i, 2] = p.net_v
-            # all_moves_left[i, 0] = p.final_moves_left
-            # all_moves_left[i, 1] = p.zero_moves_left
-            # all_moves_left[i, 2] = p.net_moves_left
+            all_moves_left[i, 0] = p.final_moves_left
+            all_moves_left[i, 1] = p.zero_moves_left
+            all_moves_left[i, 2] = p.net_moves_left
 
             policy_indices[i, : p.available_mv_count] = torch.from_numpy(
                 p.policy_indices.copy()
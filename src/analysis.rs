@@ -0,0 +1,314 @@
+use crate::{
+    boardmanager::BoardStack,
+    executor::{EvalClient, EvalRequest},
+    mcts::{extend_search, search_result},
+    mcts_trainer::{Tree, TypeRequest::NonTrainerSearch},
+    scoremodel::ScoreModel,
+    settings::{RootMoves, SearchSettings},
+    uci::{check_castling_move, standard_castling_move},
+};
+use cozy_chess::{Board, Color, Move};
+use flume::Sender;
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, time::Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+/// one line of the analysis protocol, sent by the client
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnalysisRequest {
+    pub id: u64,             // echoed in every response to this request
+    pub fen: Option<String>, // start position, defaults to the initial position
+    #[serde(default)]
+    pub moves: Vec<String>, // moves played from `fen`, in UCI notation
+    pub nodes: u32,
+    pub progress_interval: Option<u32>, // nodes between progress updates, none if not given
+    #[serde(default)]
+    pub search_moves: Vec<String>, // only search these root moves, all if empty
+    #[serde(default)]
+    pub exclude_moves: Vec<String>, // never search these root moves
+    #[serde(default)]
+    pub settings: SettingsOverrides,
+}
+
+/// search settings that can be changed per request, anything not given keeps the default
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SettingsOverrides {
+    pub fpu: Option<f32>,
+    pub c_puct: Option<f32>,
+    pub pst: Option<f32>,
+    pub score_model: Option<ScoreModel>,
+    pub mirror: Option<bool>,
+}
+
+/// one line of the analysis protocol, sent by the server
+///
+/// all values are from the point of view of the side to move in the analysed position
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AnalysisResponse {
+    Progress(SearchSummary),
+    Result(SearchSummary),
+    Error { id: Option<u64>, message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchSummary {
+    pub id: u64,
+    pub nodes: u32,
+    pub time_ms: u128,
+    pub best_move: String,
+    pub q: f32,
+    pub cp: i64,
+    pub pv: Vec<String>,
+    pub moves: Vec<MoveStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MoveStats {
+    pub mv: String,
+    pub n: u32,
+    pub q: Option<f32>,        // None if the move was not visited
+    pub p: f32,                // prior from the network
+    pub wdl: Option<[f32; 3]>, // network WDL after the move, None if it was not evaluated
+}
+
+impl SettingsOverrides {
+    pub fn apply(&self, settings: SearchSettings) -> SearchSettings {
+        SearchSettings {
+            fpu: self.fpu.unwrap_or(settings.fpu),
+            c_puct: self.c_puct.unwrap_or(settings.c_puct),
+            pst: self.pst.unwrap_or(settings.pst),
+            score_model: self.score_model.unwrap_or(settings.score_model),
+            mirror: self.mirror.unwrap_or(settings.mirror),
+            ..settings
+        }
+    }
+}
+
+// the same settings as uci and xboard, requests can override some of them
+fn default_settings(max_nodes: u32) -> SearchSettings {
+    SearchSettings::engine(max_nodes as u128, NonTrainerSearch, ScoreModel::default())
+}
+
+fn parse_position(request: &AnalysisRequest) -> Result<BoardStack, String> {
+    let board = match &request.fen {
+        Some(fen) => {
+            Board::from_fen(fen, false).map_err(|e| format!("invalid fen {}: {:?}", fen, e))?
+        }
+        None => Board::default(),
+    };
+    let mut bs = BoardStack::new(board);
+
+    for mv_str in &request.moves {
+        let mv = Move::from_str(mv_str).map_err(|_| format!("invalid move {}", mv_str))?;
+        let mv = check_castling_move(&bs, mv);
+        let mut legal_moves = Vec::new();
+        bs.board().generate_moves(|moves| {
+            // Unpack dense move set into move list
+            legal_moves.extend(moves);
+            false
+        });
+        if bs.is_terminal() || !legal_moves.contains(&mv) {
+            return Err(format!("illegal move {}", mv_str));
+        }
+        bs.play(mv);
+    }
+
+    if bs.is_terminal() {
+        return Err("position is already game over".to_string());
+    }
+    Ok(bs)
+}
+
+fn parse_root_moves(request: &AnalysisRequest, bs: &BoardStack) -> Result<RootMoves, String> {
+    let mut legal_moves = Vec::new();
+    bs.board().generate_moves(|moves| {
+        legal_moves.extend(moves);
+        false
+    });
+    let parse = |moves: &[String]| -> Result<Vec<Move>, String> {
+        moves
+            .iter()
+            .map(|mv_str| {
+                let mv = Move::from_str(mv_str).map_err(|_| format!("invalid move {}", mv_str))?;
+                let mv = check_castling_move(bs, mv);
+                if !legal_moves.contains(&mv) {
+                    return Err(format!("illegal move {}", mv_str));
+                }
+                Ok(mv)
+            })
+            .collect()
+    };
+    let root_moves = match (
+        request.search_moves.is_empty(),
+        request.exclude_moves.is_empty(),
+    ) {
+        (true, true) => RootMoves::All,
+        (false, true) => RootMoves::Only(parse(&request.search_moves)?),
+        (true, false) => RootMoves::Exclude(parse(&request.exclude_moves)?),
+        (false, false) => {
+            return Err("search_moves and exclude_moves can not both be given".to_string())
+        }
+    };
+    if !root_moves.allows_any(bs) {
+        return Err("no legal move is left to search".to_string());
+    }
+    Ok(root_moves)
+}
+
+fn summarise(tree: &Tree, id: u64, sw: Instant) -> SearchSummary {
+    let root = &tree.nodes[0];
+    let stm = tree.board.board().side_to_move();
+    // q values in the tree are from white's point of view
+    let pov = |q: f32| match stm {
+        Color::White => q,
+        Color::Black => -q,
+    };
+
+    let mut moves = Vec::new();
+    for child in root.children.clone() {
+        let node = &tree.nodes[child];
+        let mv = node.mv.expect("Error");
+        // the network WDL of a child is from the opponent's point of view
+        let wdl = if node.wdl.w.is_nan() {
+            None
+        } else {
+            Some([node.wdl.l, node.wdl.d, node.wdl.w])
+        };
+        moves.push(MoveStats {
+            mv: format!("{}", standard_castling_move(&tree.board, mv)),
+            n: node.visits,
+            q: (node.visits > 0).then(|| pov(node.get_q_val(tree.settings))),
+            p: node.policy,
+            wdl,
+        });
+    }
+    moves.sort_by_key(|m| std::cmp::Reverse(m.n));
+
+    let mut bs = tree.board.clone();
+    let mut pv = Vec::new();
+    for mv in tree.principal_variation() {
+        pv.push(format!("{}", standard_castling_move(&bs, mv)));
+        bs.play(mv);
+    }
+
+    let best_move = if root.children.is_empty() {
+        String::new()
+    } else {
+        let (mv, _, _, _, _) = search_result(tree);
+        format!("{}", standard_castling_move(&tree.board, mv))
+    };
+    let q = pov(root.get_q_val(tree.settings));
+
+    SearchSummary {
+        id,
+        nodes: root.visits,
+        time_ms: sw.elapsed().as_millis(),
+        best_move,
+        q,
+        cp: tree.settings.score_model.cp(q, None),
+        pv,
+        moves,
+    }
+}
+
+/// runs one analysis request, sending progress updates and the final result to `responses`
+pub async fn analyse(
+    request: AnalysisRequest,
+    tensor_exe_send: &Sender<EvalRequest>,
+    responses: &Sender<AnalysisResponse>,
+) {
+    let position = parse_position(&request)
+        .and_then(|bs| parse_root_moves(&request, &bs).map(|root_moves| (bs, root_moves)));
+    let (bs, root_moves) = match position {
+        Ok(position) => position,
+        Err(message) => {
+            let _ = responses
+                .send_async(AnalysisResponse::Error {
+                    id: Some(request.id),
+                    message,
+                })
+                .await;
+            return;
+        }
+    };
+
+    let settings = request.settings.apply(default_settings(request.nodes));
+    let interval = request.progress_interval.unwrap_or(request.nodes).max(1);
+    let sw = Instant::now();
+    let mut tree = Tree::new(bs, settings);
+    tree.root_moves = root_moves;
+    // stdout carries the responses, profiling output would corrupt them
+    tree.profile = false;
+    let mut client = EvalClient::for_search(tensor_exe_send.clone(), &settings);
+
+    while tree.nodes[0].visits < request.nodes {
+        let target = (tree.nodes[0].visits + interval).min(request.nodes);
+        if let Err(err) = extend_search(&mut tree, &mut client, target, sw, 0).await {
+            let _ = responses
+                .send_async(AnalysisResponse::Error {
+                    id: Some(request.id),
+                    message: err.to_string(),
+                })
+                .await;
+            return;
+        }
+        if tree.nodes[0].visits < request.nodes {
+            let progress = AnalysisResponse::Progress(summarise(&tree, request.id, sw));
+            if responses.send_async(progress).await.is_err() {
+                return; // client is gone
+            }
+        }
+    }
+
+    let _ = responses
+        .send_async(AnalysisResponse::Result(summarise(&tree, request.id, sw)))
+        .await;
+}
+
+/// serves line-delimited JSON requests from `reader`, each request is searched concurrently on its own task
+///
+/// responses of different requests can be interleaved, use the request id to tell them apart
+pub async fn serve_analysis<R, W>(reader: R, mut writer: W, tensor_exe_send: Sender<EvalRequest>)
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (response_send, response_recv) = flume::unbounded::<AnalysisResponse>();
+
+    let write_task = tokio::spawn(async move {
+        while let Ok(response) = response_recv.recv_async().await {
+            let mut serialised = serde_json::to_string(&response).expect("serialisation failed");
+            serialised += "\n";
+            if writer.write_all(serialised.as_bytes()).await.is_err() {
+                break;
+            }
+            let _ = writer.flush().await;
+        }
+    });
+
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<AnalysisRequest>(&line) {
+            Ok(request) => {
+                let tensor_exe_send = tensor_exe_send.clone();
+                let response_send = response_send.clone();
+                tokio::spawn(async move {
+                    analyse(request, &tensor_exe_send, &response_send).await;
+                });
+            }
+            Err(err) => {
+                let _ = response_send.send(AnalysisResponse::Error {
+                    id: None,
+                    message: format!("error deserialising request: {}", err),
+                });
+            }
+        }
+    }
+
+    // the writer finishes once all running requests have sent their result
+    drop(response_send);
+    let _ = write_task.await;
+}
@@ -0,0 +1,75 @@
+use tch::{Device, Kind, Tensor};
+
+use crate::encoder::{EncodedInput, InputEncoder};
+
+/// reusable input batch of the executor, positions are written straight into their slot
+///
+/// the storage is allocated once on the cpu, pinned for `device` when the batches go to a cuda device
+pub struct BatchBuffer {
+    storage: Tensor, // [capacity, channels, 8, 8]
+    slot_len: usize,
+    capacity: usize,
+    len: usize,
+}
+
+impl BatchBuffer {
+    pub fn new(capacity: usize, encoder: InputEncoder, device: Device) -> Self {
+        let storage = Tensor::zeros(
+            [capacity as i64, encoder.input_channels() as i64, 8, 8],
+            (Kind::Float, Device::Cpu),
+        );
+        let storage = if device.is_cuda() {
+            storage.pin_memory(device)
+        } else {
+            storage
+        };
+        Self {
+            storage,
+            slot_len: encoder.input_len(),
+            capacity,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity
+    }
+
+    /// writes `input` into the next free slot and returns its index in the batch
+    pub fn push(&mut self, input: &EncodedInput) -> usize {
+        assert!(!self.is_full(), "batch buffer is full");
+        let index = self.len;
+        let slot = index * self.slot_len..(index + 1) * self.slot_len;
+        input.write_planes(&mut self.data_mut()[slot]);
+        self.len += 1;
+        index
+    }
+
+    /// the filled part of the buffer, shares memory with the buffer so it has to be dropped before the next `push`
+    pub fn batch(&self) -> Tensor {
+        self.storage.narrow(0, 0, self.len as i64)
+    }
+
+    /// empties the buffer, keeping the storage for the next batch
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn data_mut(&mut self) -> &mut [f32] {
+        // the storage is a contiguous float tensor on the cpu that only this buffer writes to
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.storage.data_ptr() as *mut f32,
+                self.capacity * self.slot_len,
+            )
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::{env, thread};
+use tokio::{io::BufReader, net::TcpListener};
+use tz_rust::{
+    analysis::serve_analysis,
+    encoder::InputEncoder,
+    executor::{executor_static, EvalRequest, Message},
+};
+
+#[tokio::main]
+async fn main() {
+    // line-delimited JSON analysis, over stdin/stdout or TCP if an address is given
+    // usage: analysismain [net path] [address]
+    env::set_var("RUST_BACKTRACE", "1");
+    let args: Vec<String> = env::args().collect();
+    let net_path = args.get(1).cloned().unwrap_or("./tz_6515.pt".to_string());
+
+    // all requests and connections share one executor
+    let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<EvalRequest>(1);
+    let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
+    thread::Builder::new()
+        .name("executor".to_string())
+        .spawn(move || {
+            // the analysis searches use the default encoder
+            executor_static(net_path, tensor_exe_recv, ctrl_recv, 1, InputEncoder::default())
+        })
+        .unwrap();
+
+    match args.get(2) {
+        Some(address) => {
+            let listener = TcpListener::bind(address).await.unwrap();
+            println!("listening on {}", address);
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        eprintln!("error accepting connection: {}", e);
+                        continue;
+                    }
+                };
+                let (read_half, write_half) = stream.into_split();
+                let tensor_exe_send = tensor_exe_send.clone();
+                tokio::spawn(async move {
+                    serve_analysis(BufReader::new(read_half), write_half, tensor_exe_send).await
+                });
+            }
+        }
+        None => {
+            serve_analysis(
+                BufReader::new(tokio::io::stdin()),
+                tokio::io::stdout(),
+                tensor_exe_send,
+            )
+            .await;
+        }
+    }
+
+    let _ = ctrl_sender.send(Message::StopServer());
+}
@@ -4,15 +4,20 @@ use tokio::runtime::Runtime;
 use std::{env, io, panic, str::FromStr};
 use tz_rust::{
     boardmanager::BoardStack,
+    encoder::InputEncoder,
     executor::{
-        executor_static,
+        ExecutorPool,
         Message::{self, StopServer},
-        Packet,
     },
     mcts::get_move,
     mcts_trainer::TypeRequest::NonTrainerSearch,
+    netconfig::NetConfig,
+    pgn::{append_pgn, result_string, search_comment, write_pgn, PgnMove},
+    scoremodel::ScoreModel,
     settings::SearchSettings,
 };
+// file to save the game to, None to not save it
+const PGN_OUTPUT: Option<&str> = None;
 
 fn get_input(bs: &BoardStack) -> Move {
     let mut mv;
@@ -85,15 +90,17 @@ fn main() {
         eps: 0.0,
         search_type: NonTrainerSearch,
         pst: 0.0,
+        score_model: ScoreModel::Piecewise,
+        encoder: InputEncoder::default(),
+        mirror: false,
     };
-    let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<Packet>(1);
+    let mut pool = ExecutorPool::new(1, NetConfig::default());
+    let tensor_exe_send = pool.add_network("bot", "nets/tz_5524.pt", settings.encoder);
     let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
     let _ = thread::scope(|s| {
         s.builder()
             .name("executor".to_string())
-            .spawn(move |_| {
-                executor_static("nets/tz_5524.pt".to_string(), tensor_exe_recv, ctrl_recv, 1)
-            })
+            .spawn(move |_| pool.run(ctrl_recv))
             .unwrap();
 
         println!("Player (p) or bot (b) first: ");
@@ -101,26 +108,55 @@ fn main() {
             .read_line(&mut input)
             .expect("Failed to read line");
         let input = input.replace("\r\n", "");
+        let mut pgn_moves = Vec::new();
+        // the bot's moves are annotated with its search
+        let bot_move = |bs: &BoardStack, pgn_moves: &mut Vec<PgnMove>| {
+            let rt = Runtime::new().unwrap();
+            let (mv, v_p, _, search_data, visits) = match rt.block_on(async {get_move(bs.clone(), tensor_exe_send.clone(), settings.clone()).await}) {
+                Ok(result) => result,
+                Err(err) => {
+                    eprintln!("search failed: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let comment = search_comment(bs.board(), visits as u64, &search_data, &v_p);
+            pgn_moves.push(PgnMove { mv, comment: Some(comment) });
+            mv
+        };
         if input == "p".to_string() {
             while bs.status() == GameStatus::Ongoing {
                 let mv = get_input(&bs);
+                pgn_moves.push(PgnMove { mv, comment: None });
                 bs.play(mv);
-                let rt = Runtime::new().unwrap();
-                let (mv, _, _, _, _) = rt.block_on(async {get_move(bs.clone(), tensor_exe_send.clone(), settings.clone()).await});
+                let mv = bot_move(&bs, &mut pgn_moves);
                 println!("{:#}", mv);
                 bs.play(mv);
             }
         } else if input == "b".to_string() {
             // bot plays first
             while bs.status() == GameStatus::Ongoing {
-                let rt = Runtime::new().unwrap();
-                let (mv, _, _, _, _) = rt.block_on(async {get_move(bs.clone(), tensor_exe_send.clone(), settings.clone()).await});
+                let mv = bot_move(&bs, &mut pgn_moves);
                 bs.play(mv);
                 println!("{:#}", mv);
                 let mv = get_input(&bs);
+                pgn_moves.push(PgnMove { mv, comment: None });
                 bs.play(mv);
             }
         }
+        if let Some(path) = PGN_OUTPUT {
+            let (white, black) = if input == "p" { ("player", "bot") } else { ("bot", "player") };
+            let result = result_string(&bs);
+            let headers = [
+                ("Event", "battle".to_string()),
+                ("Site", "?".to_string()),
+                ("Date", "????.??.??".to_string()),
+                ("Round", "?".to_string()),
+                ("White", white.to_string()),
+                ("Black", black.to_string()),
+                ("Result", result.to_string()),
+            ];
+            append_pgn(path, &write_pgn(&headers, &Board::default(), &pgn_moves, result)).unwrap();
+        }
         ctrl_sender.send(StopServer()).unwrap();
     });
 }
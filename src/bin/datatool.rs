@@ -0,0 +1,81 @@
+use std::{env, process};
+
+use cozy_chess::Color;
+use tz_rust::{
+    dataset::{rewrite, DatasetOptions},
+    fileformat::BinaryInput,
+};
+
+const USAGE: &str = "usage: datatool <output path without extension> [options] <input path without extension>...
+options:
+    --split <games>         write at most this many games per file, named <output>_0, <output>_1, ...
+    --shuffle <seed>        shuffle the games
+    --dedup                 drop games that are identical to an earlier game
+    --min-length <moves>    drop games shorter than this
+    --max-length <moves>    drop games longer than this
+    --result <white|black|draw>    keep only games with this result, can be repeated
+    --net <hash prefix>     keep only games played by this network
+    --full-search-only      drop games with positions that were not searched fully";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+fn parse<T: std::str::FromStr>(value: Option<String>) -> T {
+    value
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn main() {
+    // merge, split, shuffle and filter data files, without options all inputs are merged into one file
+    let mut args = env::args().skip(1);
+    let output = args.next().unwrap_or_else(|| usage());
+
+    let mut options = DatasetOptions::default();
+    let mut input_paths = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--split" => options.games_per_file = Some(parse(args.next())),
+            "--shuffle" => options.shuffle_seed = Some(parse(args.next())),
+            "--dedup" => options.dedup = true,
+            "--min-length" => options.filter.min_length = Some(parse(args.next())),
+            "--max-length" => options.filter.max_length = Some(parse(args.next())),
+            "--result" => {
+                let result = match args.next().as_deref() {
+                    Some("white") => Some(Color::White),
+                    Some("black") => Some(Color::Black),
+                    Some("draw") => None,
+                    _ => usage(),
+                };
+                options.filter.results.push(result);
+            }
+            "--net" => options.filter.net_hash = Some(args.next().unwrap_or_else(|| usage())),
+            "--full-search-only" => options.filter.full_search_only = true,
+            _ if arg.starts_with("--") => usage(),
+            _ => input_paths.push(arg),
+        }
+    }
+    if input_paths.is_empty() || options.games_per_file == Some(0) {
+        usage();
+    }
+
+    let inputs: Vec<BinaryInput> = input_paths
+        .iter()
+        .map(|path| BinaryInput::new(path).unwrap())
+        .collect();
+    let summary = rewrite(&inputs, &output, &options).unwrap();
+
+    println!(
+        "read {} games, filtered {}, duplicates {}, wrote {} games to {} files",
+        summary.games_read,
+        summary.games_filtered,
+        summary.games_duplicate,
+        summary.games_written,
+        summary.outputs.len()
+    );
+    for path in summary.outputs {
+        println!("{}", path.display());
+    }
+}
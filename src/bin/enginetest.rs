@@ -1,7 +1,7 @@
-use cozy_chess::{Board, Color, GameStatus, Move};
+use cozy_chess::{Board, Color, GameStatus};
 use crossbeam::thread;
 use flume::{Receiver, Sender};
-use rand::seq::SliceRandom;
+use rand::{seq::SliceRandom, Rng};
 use tokio::runtime::Runtime;
 use std::{
     env,
@@ -12,12 +12,43 @@ use std::{
 use tz_rust::{
     boardmanager::BoardStack,
     elo::elo_wld,
-    executor::{executor_static, Message, Packet},
+    encoder::InputEncoder,
+    executor::{EvalError, EvalRequest, ExecutorPool, Message},
     mcts::get_move,
     mcts_trainer::TypeRequest::NonTrainerSearch,
+    netconfig::NetConfig,
+    pgn::{append_pgn, result_string, search_comment, write_pgn, PgnMove},
+    polyglot::Book,
+    scoremodel::ScoreModel,
     selfplay::CollectorMessage,
     settings::SearchSettings,
 };
+// Polyglot book to draw openings from instead of the epd file
+const OPENING_BOOK: Option<&str> = None;
+// file to save the match games to, None to not save them
+const PGN_OUTPUT: Option<&str> = None;
+
+enum Openings {
+    Epd(Vec<String>),
+    Book(Book),
+}
+
+impl Openings {
+    fn load() -> Self {
+        match OPENING_BOOK {
+            Some(path) => Openings::Book(Book::load(path).unwrap()),
+            None => Openings::Epd(read_epd_file("./8moves_v3.epd").unwrap()),
+        }
+    }
+
+    fn choose<R: Rng>(&self, rng: &mut R) -> String {
+        match self {
+            Openings::Epd(fens) => fens.choose(rng).unwrap().clone(),
+            Openings::Book(book) => book.play_opening(rng).board().to_string(),
+        }
+    }
+}
+
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
     // panic::set_hook(Box::new(|panic_info| {
@@ -35,29 +66,19 @@ fn main() {
     let engine_1: String = "./chess_16x128_gen3634.pt".to_string(); // old engine
     let engine_0_clone = engine_0.clone();
     let engine_1_clone = engine_1.clone();
-    let num_executors = 2; // always be 2, 2 players, one each (one for each neural net)
     let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
+    // both engines are served by one executor, every game thread can have a request in flight for either
+    let mut pool = ExecutorPool::new(num_threads, NetConfig::default());
+    let tensor_exe_send_0 = pool.add_network("engine_0", &engine_0, InputEncoder::default());
+    let tensor_exe_send_1 = pool.add_network("engine_1", &engine_1, InputEncoder::default());
 
     thread::scope(|s| {
         // let mut selfplay_masters: Vec<DataGen> = Vec::new();
         // commander
 
-        let mut vec_communicate_exe_send: Vec<Sender<String>> = Vec::new();
-        let mut vec_communicate_exe_recv: Vec<Receiver<String>> = Vec::new();
-
-        assert!(num_executors == 2);
-
-        for _ in 0..num_executors {
-            let (communicate_exe_send, communicate_exe_recv) = flume::bounded::<String>(1);
-            vec_communicate_exe_send.push(communicate_exe_send);
-            vec_communicate_exe_recv.push(communicate_exe_recv);
-        }
-
         // send-recv pair between commander and collector
 
         // selfplay threads
-        let (tensor_exe_send_0, tensor_exe_recv_0) = flume::bounded::<Packet>(1); // mcts to executor
-        let (tensor_exe_send_1, tensor_exe_recv_1) = flume::bounded::<Packet>(1); // mcts to executor
         for n in 0..num_threads {
             // // executor
             // sender-receiver pair to communicate for each thread instance to the executor
@@ -65,6 +86,7 @@ fn main() {
             // let mut selfplay_master = DataGen { iterations: 1 };
             let tensor_exe_send_clone_0 = tensor_exe_send_0.clone();
             let tensor_exe_send_clone_1 = tensor_exe_send_1.clone();
+            let engine_names = [engine_0.clone(), engine_1.clone()];
             // let nps_sender = game_sender.clone();
             s.builder()
                 .name(format!("generator_{}", n.to_string()))
@@ -73,6 +95,7 @@ fn main() {
                         &sender_clone,
                         tensor_exe_send_clone_0,
                         tensor_exe_send_clone_1,
+                        engine_names,
                     )
                 })
                 .unwrap();
@@ -94,34 +117,9 @@ fn main() {
             })
             .unwrap();
         // executor
-        // send/recv pair between executor and commander
-        let ctrl_recv_0 = ctrl_recv.clone();
-        let ctrl_recv_1 = ctrl_recv.clone();
-
-        let engine_0_clone = engine_0.clone();
-        let engine_1_clone = engine_1.clone();
         s.builder()
-            .name("executor_0".to_string())
-            .spawn(move |_| {
-                executor_static(
-                    engine_0_clone,
-                    tensor_exe_recv_0,
-                    ctrl_recv_0,
-                    num_threads / num_executors,
-                )
-            })
-            .unwrap();
-
-        s.builder()
-            .name("executor_1".to_string())
-            .spawn(move |_| {
-                executor_static(
-                    engine_1_clone,
-                    tensor_exe_recv_1,
-                    ctrl_recv_1,
-                    num_threads / num_executors,
-                )
-            })
+            .name("executor".to_string())
+            .spawn(move |_| pool.run(ctrl_recv))
             .unwrap();
     })
     .unwrap();
@@ -138,8 +136,9 @@ fn read_epd_file(file_path: &str) -> io::Result<Vec<String>> {
 
 fn generator_main(
     sender_collector: &Sender<CollectorMessage>,
-    tensor_exe_send_0: Sender<Packet>,
-    tensor_exe_send_1: Sender<Packet>,
+    tensor_exe_send_0: Sender<EvalRequest>,
+    tensor_exe_send_1: Sender<EvalRequest>,
+    engine_names: [String; 2],
 ) {
     let settings: SearchSettings = SearchSettings {
         fpu: 0.0,
@@ -151,36 +150,70 @@ fn generator_main(
         eps: 0.0,
         search_type: NonTrainerSearch,
         pst: 0.0,
+        score_model: ScoreModel::Piecewise,
+        encoder: InputEncoder::default(),
+        mirror: false,
     };
 
-    let openings = read_epd_file("./8moves_v3.epd").unwrap();
+    let openings = Openings::load();
     let engines = vec![tensor_exe_send_0.clone(), tensor_exe_send_1.clone()];
-    loop {
+    'openings: loop {
         let mut swap_count = 0; // so that each engine can play same opening as black and white
 
-        let fen = openings.choose(&mut rand::thread_rng()).unwrap();
+        let fen = openings.choose(&mut rand::thread_rng());
         for (engine_idx, engine) in engines.iter().enumerate() {
-            let board = Board::from_fen(fen, false).unwrap();
+            let board = Board::from_fen(&fen, false).unwrap();
             // println!("starting fen: {}", fen);
             let mut bs = BoardStack::new(board);
+            let mut pgn_moves = Vec::new();
 
             let mut counter = 0;
             let rt = Runtime::new().unwrap();
             while bs.status() == GameStatus::Ongoing {
-                let mv: Move;
-                if counter % 2 == 0 {
+                let to_move = if counter % 2 == 0 {
                     // white
-                    (mv, _, _, _, _) = rt.block_on(async {get_move(bs.clone(), engine.clone(), settings.clone()).await});
+                    engine.clone()
                 } else {
                     // swap the engine for black
-                    let opponent_engine = engines[(engine_idx + 1) % engines.len()].clone();
-
-                    (mv, _, _, _, _) = rt.block_on(async {get_move(bs.clone(), opponent_engine, settings.clone()).await});
+                    engines[(engine_idx + 1) % engines.len()].clone()
+                };
+                let result = rt.block_on(async {get_move(bs.clone(), to_move, settings.clone()).await});
+                let (mv, v_p, _, search_data, visits) = match result {
+                    Ok(result) => result,
+                    // the executor is gone, no more games can be played
+                    Err(EvalError::Disconnected) => return,
+                    // the other game of the opening is dropped too, so both engines play it equally often
+                    Err(err) => {
+                        eprintln!("dropped the games of opening {}: {}", fen, err);
+                        continue 'openings;
+                    }
+                };
+                if PGN_OUTPUT.is_some() {
+                    let comment = search_comment(bs.board(), visits as u64, &search_data, &v_p);
+                    pgn_moves.push(PgnMove { mv, comment: Some(comment) });
                 }
                 bs.play(mv);
                 counter += 1;
             }
 
+            if PGN_OUTPUT.is_some() {
+                // engine_idx plays white
+                let result = result_string(&bs);
+                let headers = [
+                    ("Event", "enginetest".to_string()),
+                    ("Site", "?".to_string()),
+                    ("Date", "????.??.??".to_string()),
+                    ("Round", "?".to_string()),
+                    ("White", engine_names[engine_idx].clone()),
+                    ("Black", engine_names[(engine_idx + 1) % engines.len()].clone()),
+                    ("Result", result.to_string()),
+                    ("SetUp", "1".to_string()),
+                    ("FEN", fen.clone()),
+                ];
+                let pgn = write_pgn(&headers, &Board::from_fen(&fen, false).unwrap(), &pgn_moves, result);
+                sender_collector.send(CollectorMessage::GamePgn(pgn)).unwrap();
+            }
+
             let outcome: Option<Color> = match bs.status() {
                 GameStatus::Drawn => None,
                 GameStatus::Won => Some(!bs.board().side_to_move()),
@@ -229,6 +262,11 @@ fn collector_main(
             CollectorMessage::ExecutorStatistics(_) => {
                 panic!("not possible! this is to test engine changes");
             }
+            CollectorMessage::GamePgn(pgn) => {
+                if let Some(path) = PGN_OUTPUT {
+                    append_pgn(path, &pgn).unwrap();
+                }
+            }
             CollectorMessage::GameResult(result) => {
                 if counter == games {
                     // print elo stats
@@ -0,0 +1,45 @@
+use std::env;
+
+use tz_rust::{fileformat::BinaryInput, scoremodel::fit_calibration};
+
+// collect (net value, final outcome) pairs from the point of view of the side to move
+fn collect_samples(path: &str, samples: &mut Vec<(f32, f32)>) {
+    let input = BinaryInput::new(path).unwrap();
+
+    for pi in 0..input.position_count() {
+        let position = input.position(pi).unwrap();
+        let scalars = &position.scalars;
+        if scalars.is_final_position || scalars.net_values.is_nan() {
+            continue;
+        }
+
+        // board scalar 1 is set when black is to move, net_v is from white's point of view
+        let black_to_move = position.board_scalars[1] == 1.0;
+        let value = if black_to_move {
+            -scalars.net_values
+        } else {
+            scalars.net_values
+        };
+        samples.push((value, scalars.final_values));
+    }
+}
+
+fn main() {
+    // fit the centipawn calibration used by the UCI ScoreModel option
+    // usage: fitscore <output.json> <data path without extension>...
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: {} <output.json> <data path without extension>...", args[0]);
+        std::process::exit(1);
+    }
+
+    let mut samples = Vec::new();
+    for path in &args[2..] {
+        collect_samples(path, &mut samples);
+    }
+    println!("fitting on {} positions", samples.len());
+
+    let calibration = fit_calibration(&samples);
+    println!("{:?}", calibration);
+    calibration.save(&args[1]).unwrap();
+}
@@ -5,13 +5,15 @@ use tokio::runtime::Runtime;
 use std::{env, panic};
 use tz_rust::{
     boardmanager::BoardStack,
+    encoder::InputEncoder,
     executor::{
-        executor_static,
+        EvalRequest, ExecutorPool,
         Message::{self, StopServer},
-        Packet,
     },
     mcts::get_move,
     mcts_trainer::TypeRequest::NonTrainerSearch,
+    netconfig::NetConfig,
+    scoremodel::ScoreModel,
     settings::SearchSettings,
 };
 
@@ -25,18 +27,12 @@ fn main() {
 
         std::process::exit(1);
     }));
-    let (tensor_exe_send_1, tensor_exe_recv_1) = flume::bounded::<Packet>(1);
-    let (tensor_exe_send_0, tensor_exe_recv_0) = flume::bounded::<Packet>(1);
-
     let mut scores = (0, 0, 0); // Win, Draw, Loss (White POV)
 
     let mut games_count = 0;
 
     let target_games = 10;
 
-    // set up executor and sender pairs
-
-    let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
     let settings: SearchSettings = SearchSettings {
         fpu: 0.0,
         wdl: None,
@@ -47,41 +43,28 @@ fn main() {
         eps: 0.0,
         search_type: NonTrainerSearch,
         pst: 0.0,
+        score_model: ScoreModel::Piecewise,
+        encoder: InputEncoder::default(),
+        mirror: false,
     };
+
+    // both networks are served by one executor
+    let mut pool = ExecutorPool::new(1, NetConfig::default());
+    // or "chess_16x128_gen3634.pt"
+    let tensor_exe_send_0 = pool.add_network("net_0", "./nets/tz_5521.pt", settings.encoder);
+    // or "./nets/tz_4483.pt"
+    let tensor_exe_send_1 = pool.add_network("net_1", "chess_16x128_gen3634.pt", settings.encoder);
+    let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
     thread::scope(|s| {
+        s.builder()
+            .name("executor".to_string())
+            .spawn(move |_| pool.run(ctrl_recv))
+            .unwrap();
         while games_count < target_games {
             let board = Board::default();
             let mut bs = BoardStack::new(board);
-            let tensor_exe_recv_clone_0 = tensor_exe_recv_0.clone();
-            let ctrl_recv_clone = ctrl_recv.clone();
-            s.builder()
-                .name("executor_net_0".to_string())
-                .spawn(move |_| {
-                    executor_static(
-                        "./nets/tz_5521.pt".to_string(),
-                        // "chess_16x128_gen3634.pt".to_string(),
-                        tensor_exe_recv_clone_0,
-                        ctrl_recv_clone,
-                        1,
-                    )
-                })
-                .unwrap();
-            let tensor_exe_recv_clone_1 = tensor_exe_recv_1.clone();
-            let ctrl_recv_clone = ctrl_recv.clone();
-            s.builder()
-                .name("executor_net_1".to_string())
-                .spawn(move |_| {
-                    executor_static(
-                        "chess_16x128_gen3634.pt".to_string(),
-                        // "./nets/tz_4483.pt".to_string(),
-                        tensor_exe_recv_clone_1,
-                        ctrl_recv_clone,
-                        1,
-                    )
-                })
-                .unwrap();
             let mut counter = 0;
-            let mut tensor_exe_send: Sender<Packet>;
+            let mut tensor_exe_send: Sender<EvalRequest>;
             while bs.status() == GameStatus::Ongoing {
                 if counter % 2 == 0 {
                     tensor_exe_send = tensor_exe_send_0.clone();
@@ -89,7 +72,13 @@ fn main() {
                     tensor_exe_send = tensor_exe_send_1.clone();
                 }
                 let rt = Runtime::new().unwrap();
-                let (mv, _, _, _, _) = rt.block_on(async {get_move(bs.clone(), tensor_exe_send.clone(), settings.clone()).await});
+                let (mv, _, _, _, _) = match rt.block_on(async {get_move(bs.clone(), tensor_exe_send.clone(), settings.clone()).await}) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        eprintln!("search failed: {}", err);
+                        std::process::exit(1);
+                    }
+                };
                 bs.play(mv);
                 println!("{:#}", mv);
 
@@ -4,13 +4,15 @@ use tokio::runtime::Runtime;
 use std::{env, panic, time::Instant};
 use tz_rust::{
     boardmanager::BoardStack,
+    encoder::InputEncoder,
     executor::{
         executor_static,
         Message::{self, StopServer},
-        Packet,
+        EvalRequest,
     },
     mcts::get_move,
     mcts_trainer::TypeRequest::NonTrainerSearch,
+    scoremodel::ScoreModel,
     settings::SearchSettings,
 };
 
@@ -43,9 +45,23 @@ fn main() {
     });
 
     let total_moves = move_list.len();
-    // set up executor and sender pairs
+    let settings: SearchSettings = SearchSettings {
+        fpu: 0.0,
+        wdl: None,
+        moves_left: None,
+        c_puct: 2.0,
+        max_nodes: 1,
+        alpha: 0.0,
+        eps: 0.0,
+        search_type: NonTrainerSearch,
+        pst: 0.0,
+        score_model: ScoreModel::Piecewise,
+        encoder: InputEncoder::default(),
+        mirror: false,
+    };
 
-    let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<Packet>(1);
+    // set up executor and sender pairs
+    let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<EvalRequest>(1);
     let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
     thread::scope(|s| {
         s.builder()
@@ -58,6 +74,7 @@ fn main() {
                     tensor_exe_recv,
                     ctrl_recv,
                     1,
+                    settings.encoder,
                 )
             })
             .unwrap();
@@ -65,19 +82,14 @@ fn main() {
         println!("Number of legal moves: {}", total_moves);
         let bs = BoardStack::new(board);
         let sw = Instant::now();
-        let settings: SearchSettings = SearchSettings {
-            fpu: 0.0,
-            wdl: None,
-            moves_left: None,
-            c_puct: 2.0,
-            max_nodes: 1,
-            alpha: 0.0,
-            eps: 0.0,
-            search_type: NonTrainerSearch,
-            pst: 0.0,
-        };
         let rt = Runtime::new().unwrap();
-        let (best_move, nn_data, _, _, _) = rt.block_on(async {get_move(bs, tensor_exe_send.clone(), settings.clone()).await});
+        let (best_move, nn_data, _, _, _) = match rt.block_on(async {get_move(bs, tensor_exe_send.clone(), settings.clone()).await}) {
+            Ok(result) => result,
+            Err(err) => {
+                eprintln!("search failed: {}", err);
+                std::process::exit(1);
+            }
+        };
         for (mv, score) in move_list.iter().zip(nn_data.policy.iter()) {
             println!("{:#}, {}", mv, score);
         }
@@ -1,3 +1,4 @@
+use cozy_chess::Board;
 use crossbeam::thread;
 use flume::{Receiver, Sender};
 use rand::prelude::*;
@@ -6,30 +7,52 @@ use std::{
     fs::{self, File},
     io::{self, BufRead, BufReader, Read, Write},
     net::TcpStream,
-    panic,
     path::Path,
+    sync::{Arc, RwLock},
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::task::spawn;
 use tz_rust::{
     dummyreq::{send_request, send_request_async},
-    executor::{executor_main, Packet},
+    encoder::InputEncoder,
+    executor::{executor_main, EvalError, EvalRequest, NetUpdate},
+    boardmanager::BoardStack,
     fileformat::BinaryOutput,
     mcts_trainer::TypeRequest::TrainerSearch,
+    netconfig::{NetConfig, NetDevice, SwapPolicy},
     message_types::{DataFileType, Entity, MessageServer, MessageType, Statistics},
+    pgn::{append_pgn, simulation_to_pgn},
+    polyglot::{Book, BookBuilder, DEFAULT_MAX_PLY},
+    provenance::hash_net_bytes,
+    scoremodel::ScoreModel,
     selfplay::{CollectorMessage, DataGen},
     settings::SearchSettings,
+    verify::find_data_files,
 };
+// where to write a Polyglot book built from the generated games, None to not build one
+const BOOK_OUTPUT: Option<&str> = None;
+// where to save the generated games as annotated PGN, None to not save them
+const PGN_OUTPUT: Option<&str> = None;
+// network input encoding, used for the search, the executors and the stored training data
+const INPUT_ENCODER: InputEncoder = InputEncoder::V1;
+// device, torch threads and precision of the executors
+const NET_CONFIG: NetConfig = NetConfig {
+    device: NetDevice::Auto,
+    intra_op_threads: None,
+    inter_op_threads: None,
+    half_precision: false,
+    no_grad: true,
+    swap_policy: SwapPolicy::FinishBatch,
+};
+// recover the games of crashed runs from the games folder, files another collector is still writing are skipped
+const RECOVER_ON_STARTUP: bool = true;
+
 #[tokio::main]
 async fn main() {
     env::set_var("RUST_BACKTRACE", "1");
 
-    panic::set_hook(Box::new(|panic_info| {
-        // print panic information
-        eprintln!("Panic occurred: {:?}", panic_info);
-        // exit the program immediately
-        std::process::exit(1);
-    }));
+    // the torch thread counts are global, they are set once before any network is loaded
+    NET_CONFIG.apply_threads().expect("Error");
     // connect to python-rust server
     let mut stream = loop {
         match TcpStream::connect("127.0.0.1:38475") {
@@ -54,14 +77,16 @@ async fn main() {
     let num_generators = num_executors * batch_size * 4;
     let (game_sender, game_receiver) =
         flume::bounded::<CollectorMessage>(num_executors * batch_size);
+    // hash of the network the executors have loaded, recorded in every generated game
+    let current_net_hash: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
     thread::scope(|s| {
         // commander
         
-        let mut vec_communicate_exe_send: Vec<Sender<String>> = Vec::new(); // commander to executor, send net
-        let mut vec_communicate_exe_recv: Vec<Receiver<String>> = Vec::new(); // commander to executor, send net
+        let mut vec_communicate_exe_send: Vec<Sender<NetUpdate>> = Vec::new(); // commander to executor, send net
+        let mut vec_communicate_exe_recv: Vec<Receiver<NetUpdate>> = Vec::new(); // commander to executor, send net
 
         for _ in 0..num_executors {
-            let (communicate_exe_send, communicate_exe_recv) = flume::bounded::<String>(4 * num_generators);
+            let (communicate_exe_send, communicate_exe_recv) = flume::bounded::<NetUpdate>(4 * num_generators);
             vec_communicate_exe_send.push(communicate_exe_send);
             vec_communicate_exe_recv.push(communicate_exe_recv);
         }
@@ -86,7 +111,7 @@ async fn main() {
         
         
         // selfplay threads
-        let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<Packet>(num_executors * num_generators); // mcts to executor
+        let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<EvalRequest>(num_executors * num_generators); // mcts to executor
         let spam = tensor_exe_send.clone();
         
          // executor
@@ -95,6 +120,7 @@ async fn main() {
             // send/recv pair between executor and commander
             let eval_per_sec_sender = game_sender.clone();
             let tensor_exe_recv_clone = tensor_exe_recv.clone();
+            let loaded_net_hash = current_net_hash.clone();
             let _ = s
                 .builder()
                 .name(format!("executor_{}", n.to_string()))
@@ -104,6 +130,9 @@ async fn main() {
                         tensor_exe_recv_clone,
                         batch_size,
                         eval_per_sec_sender,
+                        INPUT_ENCODER,
+                        NET_CONFIG,
+                        loaded_net_hash,
                     )
                 })
                 .unwrap();
@@ -116,6 +145,7 @@ async fn main() {
         //     let mut selfplay_master = DataGen { iterations: 1 };
         //     let tensor_exe_send_clone = tensor_exe_send.clone();
         //     let nps_sender = game_sender.clone();
+        //     let current_net_hash = current_net_hash.clone();
 
         //     let handle = spawn(async move {
         //         generator_main(
@@ -123,6 +153,7 @@ async fn main() {
         //             selfplay_master,
         //             tensor_exe_send_clone,
         //             nps_sender,
+        //             current_net_hash,
         //             n,
         //         )
         //         .await
@@ -164,8 +195,9 @@ async fn main() {
 async fn generator_main(
     sender_collector: Sender<CollectorMessage>,
     datagen: DataGen,
-    tensor_exe_send: Sender<Packet>,
+    tensor_exe_send: Sender<EvalRequest>,
     nps_sender: Sender<CollectorMessage>,
+    current_net_hash: Arc<RwLock<Option<String>>>,
     id: usize,
 ) {
     let settings: SearchSettings = SearchSettings {
@@ -179,12 +211,53 @@ async fn generator_main(
         eps: 0.3,
         search_type: TrainerSearch(None),
         pst: 1.2,
+        score_model: ScoreModel::Piecewise,
+        encoder: INPUT_ENCODER,
+        mirror: false,
     };
+    // Polyglot book to start games from, None starts from the initial position
+    let opening_book: Option<&str> = None;
+    let book = opening_book.map(|path| Book::load(path).unwrap());
+    // how long to wait after a failed game, doubled for every failure in a row, a missing
+    // network fails every game until the next one is loaded
+    const RETRY_DELAY: Duration = Duration::from_millis(100);
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+    let mut retry_delay: Option<Duration> = None; // set while games keep failing
     loop {
+        // the seed covers both the opening and the move sampling during the game
+        let seed: u64 = thread_rng().gen();
+        let start = match &book {
+            Some(book) => book.play_opening(&mut StdRng::seed_from_u64(seed)),
+            None => BoardStack::new(Board::default()),
+        };
+        // the network may be swapped during the game, we record the one it started with
+        let net_hash = current_net_hash.read().unwrap().clone();
         let sim = datagen
             // .fast_data(&tensor_exe_send, &nps_sender, &settings, id)
-            .play_game(&tensor_exe_send, &nps_sender, &settings, id)
+            .play_game_from(start, seed, &tensor_exe_send, &nps_sender, &settings, id)
             .await;
+        let mut sim = match sim {
+            Ok(sim) => sim,
+            // the executors are gone, no more games can be played
+            Err(EvalError::Disconnected) => return,
+            Err(err) => {
+                let delay = match retry_delay {
+                    // only the first failure in a row is printed, the rest are most likely the same
+                    None => {
+                        eprintln!("generator {} dropped a game, retrying: {}", id, err);
+                        RETRY_DELAY
+                    }
+                    Some(delay) => (delay * 2).min(MAX_RETRY_DELAY),
+                };
+                retry_delay = Some(delay);
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        };
+        retry_delay = None;
+        if let Some(provenance) = &mut sim.provenance {
+            provenance.net_hash = net_hash;
+        }
 
         // match settings.search_type {
         //     TrainerSearch(expansiontype) => match expansiontype {
@@ -204,7 +277,7 @@ async fn generator_main(
         //                     settings.clone(),
         //                 );
         //                 sender_collector
-        //                     .send(CollectorMessage::FinishedGame(sim))
+        //                     .send(CollectorMessage::FinishedGame(Box::new(sim)))
         //                     .unwrap();
         //             }
         //         }
@@ -216,12 +289,55 @@ async fn generator_main(
         // }
 
         sender_collector
-            .send_async(CollectorMessage::FinishedGame(sim))
+            .send_async(CollectorMessage::FinishedGame(Box::new(sim)))
             .await
             .unwrap();
     }
 }
 
+/// sends a finished data file to the server and deletes it
+fn send_data_file(server_handle: &mut TcpStream, path: &str) {
+    let files = [".bin", ".off", ".json"];
+    let mut file_data: Vec<Vec<u8>> = Vec::new();
+    for file in files {
+        let file_path = format!("{}{}", path, file);
+        let data = serialise_file_to_bytes(&file_path.to_owned())
+            .unwrap()
+            .clone();
+        file_data.push(data);
+    }
+
+    let (bin_file, off_file, metadata) = (
+        file_data[0].clone(),
+        file_data[1].clone(),
+        file_data[2].clone(),
+    );
+
+    let message = MessageServer {
+        // purpose: MessageType::JobSendPath(path.clone().to_string()),
+        purpose: MessageType::JobSendData(vec![
+            DataFileType::BinFile(bin_file),
+            DataFileType::OffFile(off_file),
+            DataFileType::MetaDataFile(metadata),
+        ]),
+    };
+    let mut serialised = serde_json::to_string(&message).expect("serialisation failed");
+    serialised += "\n";
+    server_handle.write_all(serialised.as_bytes()).unwrap();
+    for file in files {
+        let file_path = format!("{}{}", path, file);
+        if Path::new(&file_path).is_file() {
+            // delete sent files
+            match fs::remove_file(&file_path) {
+                Ok(_) => {
+                    println!("Deleted file {}", file_path);
+                }
+                Err(e) => println!("Error deleting the file: {}", e),
+            }
+        }
+    }
+}
+
 fn serialise_file_to_bytes(file_path: &str) -> io::Result<Vec<u8>> {
     let mut file = File::open(file_path)?;
 
@@ -259,66 +375,57 @@ fn collector_main(
     let mut counter = 0;
     let id = id_recv.recv().unwrap();
 
+    // finish the files of a previous run that crashed and send the games up to their last checkpoint
+    if RECOVER_ON_STARTUP {
+        for file in find_data_files(folder_name).unwrap() {
+            if !file.with_extension("progress").exists() {
+                continue;
+            }
+            match BinaryOutput::recover(&file) {
+                Ok(games) => {
+                    println!("recovered {} games from {}", games, file.display());
+                    send_data_file(server_handle, file.to_str().unwrap());
+                }
+                // still being written by another collector
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => println!("Error recovering {}: {}", file.display(), e),
+            }
+        }
+    }
+
     let mut path = format!("games/gen_{}_games_{}", id, counter);
-    let mut bin_output = BinaryOutput::new(path.clone(), "chess").unwrap();
+    let mut bin_output =
+        BinaryOutput::with_encoder(path.clone(), "chess", INPUT_ENCODER).unwrap();
     let mut nps_start_time = Instant::now();
     let mut nps_vec: Vec<f32> = Vec::new();
     let mut evals_start_time = Instant::now();
     let mut evals_vec: Vec<f32> = Vec::new();
-    let files = [".bin", ".off", ".json"];
-    let mut file_data: Vec<Vec<u8>> = Vec::new();
+    let mut book_builder = BookBuilder::new(DEFAULT_MAX_PLY);
     loop {
         let msg = receiver.recv().unwrap();
         match msg {
             CollectorMessage::FinishedGame(sim) => {
                 bin_output.append(&sim).unwrap();
+                bin_output.checkpoint().unwrap();
+                if BOOK_OUTPUT.is_some() {
+                    book_builder.add_simulation(&sim);
+                }
+                if let Some(pgn_path) = PGN_OUTPUT {
+                    let headers = [("Event", format!("tz selfplay gen {}", id))];
+                    append_pgn(pgn_path, &simulation_to_pgn(&sim, &headers)).unwrap();
+                }
                 if bin_output.game_count() >= 100 {
                     bin_output.finish().unwrap();
-
-                    for file in files {
-                        let file_path = format!("{}{}", path, file);
-                        let data = serialise_file_to_bytes(&file_path.to_owned())
-                            .unwrap()
-                            .clone();
-                        file_data.push(data);
+                    if let Some(book_path) = BOOK_OUTPUT {
+                        book_builder.build().save(book_path).unwrap();
                     }
 
-                    let (bin_file, off_file, metadata) = (
-                        file_data[0].clone(),
-                        file_data[1].clone(),
-                        file_data[2].clone(),
-                    );
-
-                    let message = MessageServer {
-                        // purpose: MessageType::JobSendPath(path.clone().to_string()),
-                        purpose: MessageType::JobSendData(vec![
-                            DataFileType::BinFile(bin_file),
-                            DataFileType::OffFile(off_file),
-                            DataFileType::MetaDataFile(metadata),
-                        ]),
-                    };
-                    let mut serialised =
-                        serde_json::to_string(&message).expect("serialisation failed");
-                    serialised += "\n";
-                    server_handle.write_all(serialised.as_bytes()).unwrap();
-                    for file_path in files {
-                        let exists_file = Path::new(file_path).is_file();
-                        if exists_file {
-                            // delete sent files
-                            // Attempt to delete the file
-                            match fs::remove_file(format!("{}{}", path, file_path)) {
-                                Ok(_) => {
-                                    println!("Deleted file {}", format!("{}{}", path, file_path));
-                                }
-                                Err(e) => println!("Error deleting the file: {}", e),
-                            }
-                        } else {
-                        }
-                    }
+                    send_data_file(server_handle, &path);
                     // println!("{}, {}", thread_name, counter);
                     counter += 1;
                     path = format!("games/gen_{}_games_{}", id, counter);
-                    bin_output = BinaryOutput::new(path.clone(), "chess").unwrap();
+                    bin_output =
+                        BinaryOutput::with_encoder(path.clone(), "chess", INPUT_ENCODER).unwrap();
                 }
             }
             CollectorMessage::GeneratorStatistics(nps) => {
@@ -360,21 +467,25 @@ fn collector_main(
                 }
             }
             CollectorMessage::GameResult(_) => {}
+            CollectorMessage::GamePgn(_) => {}
         }
     }
 }
 
 fn commander_main(
-    vec_exe_sender: Vec<Sender<String>>,
+    vec_exe_sender: Vec<Sender<NetUpdate>>,
     server_handle: &mut TcpStream,
     id_sender: Sender<usize>,
 ) {
     let mut curr_net = String::new();
     let mut is_initialised = false;
     let mut net_path = String::new(); // initialize net_path with an empty string
+    let mut net_hash: Option<String> = None; // hash of `net_path`
     let mut cloned_handle = server_handle.try_clone().unwrap();
     let mut reader = BufReader::new(server_handle);
     let mut net_path_counter = 0;
+    // one generation per network, the same for every executor so responses can be compared
+    let mut net_generation = 0;
     let mut generator_id: usize = 0;
     loop {
         let mut recv_msg = String::new();
@@ -412,6 +523,7 @@ fn commander_main(
                     );
                     let mut file = File::create(net_path.clone()).expect("Unable to create file");
                     file.write_all(&data).expect("Unable to write data");
+                    net_hash = Some(hash_net_bytes(&data));
                     net_path_counter += 1;
                 }
             }
@@ -452,8 +564,14 @@ fn commander_main(
                         Err(e) => eprintln!("Error deleting the file: {}", e),
                     }
                 }
+                net_generation += 1;
 for exe_sender in &vec_exe_sender {
-                exe_sender.send(net_path.clone()).unwrap();
+                    let update = NetUpdate {
+                        path: net_path.clone(),
+                        generation: net_generation,
+                        net_hash: net_hash.clone(),
+                    };
+                exe_sender.send(update).unwrap();
 // println!("SENT!");
                 }
 
@@ -0,0 +1,40 @@
+use std::{env, fs};
+
+use tz_rust::{
+    fileformat::BinaryOutput,
+    pgn::{game_to_simulation, parse_pgn},
+};
+
+fn main() {
+    // convert PGN games into training data, the policy target is the played move and the value target the result
+    // usage: pgnimport <output path without extension> <policy smoothing> <pgn file>...
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "usage: {} <output path without extension> <policy smoothing> <pgn file>...",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let policy_smoothing: f32 = args[2]
+        .parse()
+        .expect("policy smoothing should be a number, 0.0 gives a one-hot policy");
+
+    let mut output = BinaryOutput::new(&args[1], "chess").unwrap();
+    let mut skipped = 0;
+    for path in &args[3..] {
+        // PGN files are not always valid UTF-8, player names in particular
+        let text = String::from_utf8_lossy(&fs::read(path).unwrap()).into_owned();
+        for (i, game) in parse_pgn(&text).into_iter().enumerate() {
+            match game.and_then(|game| game_to_simulation(&game, policy_smoothing)) {
+                Ok((sim, outcome)) => output.append_with_outcome(&sim, outcome).unwrap(),
+                Err(e) => {
+                    eprintln!("skipping game {} of {}: {}", i + 1, path, e);
+                    skipped += 1;
+                }
+            }
+        }
+    }
+    output.finish().unwrap();
+    println!("wrote {} games, skipped {}", output.game_count(), skipped);
+}
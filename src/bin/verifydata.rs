@@ -0,0 +1,91 @@
+use std::{env, io, path::Path, process};
+
+use tz_rust::{
+    fileformat::BinaryOutput,
+    verify::{find_data_files, quarantine, verify_file},
+};
+
+// number of problems to print per file
+const MAX_PRINTED_ERRORS: usize = 10;
+
+fn main() {
+    // checks data files written by BinaryOutput, optionally moving bad files out of the way
+    // --recover first finishes files of crashed writers up to their last checkpoint
+    let mut quarantine_dir = None;
+    let mut recover = false;
+    let mut paths = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quarantine" => quarantine_dir = args.next(),
+            "--recover" => recover = true,
+            _ if Path::new(&arg).is_dir() => paths.extend(find_data_files(&arg).unwrap()),
+            _ => paths.push(arg.into()),
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("usage: verifydata [--quarantine <dir>] [--recover] <path without extension or directory>...");
+        process::exit(1);
+    }
+
+    if recover {
+        for path in &paths {
+            if path.with_extension("progress").exists() {
+                match BinaryOutput::recover(path) {
+                    Ok(games) => println!("recovered {} games of {}", games, path.display()),
+                    // a collector is still writing it
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        println!("{} is in use, skipped", path.display())
+                    }
+                    Err(e) => println!("error recovering {}: {}", path.display(), e),
+                }
+            }
+        }
+    }
+
+    let mut bad_files = 0;
+    let mut in_progress = 0;
+    for path in &paths {
+        let report = verify_file(path);
+        if report.in_progress {
+            in_progress += 1;
+            println!(
+                "in progress {}: still being written, skipped",
+                path.display()
+            );
+            continue;
+        }
+        if report.is_ok() {
+            println!(
+                "ok {}: {} games, {} positions",
+                path.display(),
+                report.games,
+                report.positions
+            );
+            continue;
+        }
+
+        bad_files += 1;
+        println!("BAD {}: {} problems", path.display(), report.errors.len());
+        for error in report.errors.iter().take(MAX_PRINTED_ERRORS) {
+            println!("    {}", error);
+        }
+        if report.errors.len() > MAX_PRINTED_ERRORS {
+            println!("    ...");
+        }
+        if let Some(dir) = &quarantine_dir {
+            quarantine(path, dir).unwrap();
+            println!("    moved to {}", dir);
+        }
+    }
+
+    println!(
+        "{} of {} files ok, {} in progress",
+        paths.len() - bad_files - in_progress,
+        paths.len(),
+        in_progress
+    );
+    if bad_files > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,7 @@
+use std::env;
+use tz_rust::xboard::run_xboard;
+
+fn main() {
+    env::set_var("RUST_BACKTRACE", "2");
+    run_xboard(r"./tz_6515.pt");
+}
@@ -1,21 +1,49 @@
 use cozy_chess::*;
 
-#[derive(PartialEq, Clone, Debug)]
+/// number of previous boards kept for the history planes of the input encoding
+pub const MAX_HISTORY: usize = 8;
+
+#[derive(Clone, Debug)]
 pub struct BoardStack {
     board: Board,
     move_stack: Vec<u64>,
+    history: Vec<Board>, // the last `history_len` boards before the current one, oldest first
+    history_len: usize,
     status: GameStatus,
 }
 
+// two stacks are the same position whether or not they keep the boards for the history planes
+impl PartialEq for BoardStack {
+    fn eq(&self, other: &Self) -> bool {
+        self.board == other.board
+            && self.move_stack == other.move_stack
+            && self.status == other.status
+    }
+}
+
 impl BoardStack {
+    /// a stack without history, see `with_history` for encoders with history planes
     pub fn new(board: Board) -> Self {
         Self {
             status: board.status(),
             board,
             move_stack: Vec::new(),
+            history: Vec::new(),
+            history_len: 0,
         }
     }
 
+    /// keeps the last `len` boards from now on, older boards that are already kept are dropped
+    ///
+    /// use `InputEncoder::history_len` of the encoder the positions are encoded with
+    pub fn with_history(mut self, len: usize) -> Self {
+        assert!(len <= MAX_HISTORY, "at most {} previous boards are kept", MAX_HISTORY);
+        let excess = self.history.len().saturating_sub(len);
+        self.history.drain(..excess);
+        self.history_len = len;
+        self
+    }
+
     // get number of repetitions for decoder.rs
 
     pub fn get_reps(&self) -> usize {
@@ -28,6 +56,12 @@ impl BoardStack {
     pub fn play(&mut self, mv: Move) {
         assert!(self.status == GameStatus::Ongoing); // check if prev board is valid (can play a move)
         self.move_stack.push(self.board.hash());
+        if self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.remove(0);
+            }
+            self.history.push(self.board.clone());
+        }
         self.board.play(mv);
         let is_all_gone = self.board.occupied().len() == 2;
         let is_sure_draw = self.board.occupied().len() <= 3
@@ -53,6 +87,16 @@ impl BoardStack {
         &self.board
     }
 
+    /// the boards before the current one, most recent last
+    pub fn history(&self) -> &[Board] {
+        &self.history
+    }
+
+    /// whether the last `len` boards are available, or all of them if fewer moves were played
+    pub fn has_history(&self, len: usize) -> bool {
+        self.history.len() >= len.min(self.move_stack.len())
+    }
+
     pub fn status(&self) -> GameStatus {
         let is_all_gone = self.board.occupied().len() == 2;
         let is_sure_draw = self.board.occupied().len() <= 3
@@ -2,13 +2,19 @@
 
 use cozy_chess::Move;
 
-use crate::boardmanager::BoardStack;
+use crate::{boardmanager::BoardStack, mcts_trainer::Wdl, provenance::Provenance};
 
 #[derive(Debug, Clone)]
 pub struct ZeroEvaluation {
     /// The (normalized) values.
     pub values: f32, // stole it from https://github.com/KarelPeeters/kZero/blob/master/rust/kz-core/src/network/mod.rs#L23
 
+    /// The win/draw/loss probabilities, from white's point of view like `values`.
+    pub wdl: Wdl,
+
+    /// The expected number of moves until the end of the game.
+    pub moves_left: f32,
+
     /// The (normalized) policy "vector", only containing the available moves in the order they are yielded by `available_moves`.
     pub policy: Vec<f32>,
 }
@@ -31,6 +37,8 @@ pub struct Simulation {
     pub positions: Vec<Position>,
     // can be non-terminal if the game was stopped by the length limit
     pub final_board: BoardStack,
+    // None for games that were not generated by selfplay
+    pub provenance: Option<Provenance>,
 }
 
 impl Simulation {
@@ -0,0 +1,184 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+use cozy_chess::Color;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::fileformat::{BinaryInput, BinaryOutput, StoredGame};
+
+/// which games to keep, games are always kept or dropped as a whole
+#[derive(Debug, Clone, Default)]
+pub struct GameFilter {
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub results: Vec<Option<Color>>, // winners to keep, None for draws, empty keeps all results
+    pub net_hash: Option<String>, // prefix of the network hash, games without a known network are dropped
+    pub full_search_only: bool,   // drop games with any position that was not searched fully
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DatasetOptions {
+    pub filter: GameFilter,
+    pub dedup: bool,
+    pub shuffle_seed: Option<u64>,
+    pub games_per_file: Option<usize>, // None writes everything to a single file
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DatasetSummary {
+    pub games_read: usize,
+    pub games_filtered: usize,
+    pub games_duplicate: usize,
+    pub games_written: usize,
+    pub outputs: Vec<PathBuf>,
+}
+
+impl GameFilter {
+    pub fn keep(&self, game: &StoredGame) -> io::Result<bool> {
+        let length = game.length();
+        if self.min_length.is_some_and(|min| length < min)
+            || self.max_length.is_some_and(|max| length > max)
+        {
+            return Ok(false);
+        }
+        if !self.results.is_empty() && !self.results.contains(&game.outcome()?) {
+            return Ok(false);
+        }
+        if let Some(prefix) = &self.net_hash {
+            let net_hash = game.provenance.as_ref().and_then(|p| p.net_hash.as_ref());
+            if !net_hash.is_some_and(|hash| hash.starts_with(prefix.as_str())) {
+                return Ok(false);
+            }
+        }
+        if self.full_search_only
+            && game
+                .positions
+                .iter()
+                .any(|p| !p.scalars.is_final_position && !p.scalars.is_full_search)
+        {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// hash of the boards of a game, games with the same hash are considered identical
+pub fn game_hash(game: &StoredGame) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for position in &game.positions {
+        position.board_bools.hash(&mut hasher);
+        for x in &position.board_scalars {
+            x.to_bits().hash(&mut hasher);
+        }
+        position.scalars.played_mv.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+// true if both paths (without extension) name the same existing data file
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (
+        fs::canonicalize(a.with_extension("bin")),
+        fs::canonicalize(b.with_extension("bin")),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// merges the games of `inputs` into new files at `output`, applying the filter, deduplication and shuffle
+///
+/// when splitting, the files are named `{output}_0`, `{output}_1`, ... none of them may be one of the inputs
+pub fn rewrite(
+    inputs: &[BinaryInput],
+    output: impl AsRef<Path>,
+    options: &DatasetOptions,
+) -> io::Result<DatasetSummary> {
+    let output = output.as_ref();
+    let (game_name, encoder) = match inputs.first() {
+        Some(input) => (input.meta().game.clone(), input.meta().input_encoding),
+        None => return Ok(DatasetSummary::default()),
+    };
+    if let Some(input) = inputs.iter().find(|i| i.meta().input_encoding != encoder) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "can not merge input encodings {} and {}",
+                encoder,
+                input.meta().input_encoding
+            ),
+        ));
+    }
+
+    let mut games: Vec<(usize, usize)> = inputs
+        .iter()
+        .enumerate()
+        .flat_map(|(i, input)| (0..input.game_count()).map(move |g| (i, g)))
+        .collect();
+    if let Some(seed) = options.shuffle_seed {
+        games.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+
+    let mut summary = DatasetSummary::default();
+    let mut seen: HashSet<u64> = HashSet::new();
+    let mut current: Option<BinaryOutput> = None;
+
+    for (input, game) in games {
+        let game = inputs[input].game(game)?;
+        summary.games_read += 1;
+
+        if !options.filter.keep(&game)? {
+            summary.games_filtered += 1;
+            continue;
+        }
+        if options.dedup && !seen.insert(game_hash(&game)) {
+            summary.games_duplicate += 1;
+            continue;
+        }
+
+        // outputs are only created once they get a game, so there are no empty files
+        let writer = match &mut current {
+            Some(writer) => writer,
+            None => {
+                let path = match options.games_per_file {
+                    Some(_) => {
+                        let mut name = output.as_os_str().to_owned();
+                        name.push(format!("_{}", summary.outputs.len()));
+                        PathBuf::from(name)
+                    }
+                    None => output.to_path_buf(),
+                };
+                // creating the output truncates it, which would pull the data from under an input
+                if let Some(input) = inputs.iter().find(|i| same_file(i.path(), &path)) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("output {} is also an input", input.path().display()),
+                    ));
+                }
+                summary.outputs.push(path.clone());
+                current.insert(BinaryOutput::with_encoder(path, &game_name, encoder)?)
+            }
+        };
+        writer.append_stored(&game)?;
+        summary.games_written += 1;
+
+        if options
+            .games_per_file
+            .is_some_and(|n| writer.game_count() >= n)
+        {
+            writer.finish()?;
+            current = None;
+        }
+    }
+
+    if let Some(mut writer) = current {
+        writer.finish()?;
+    }
+
+    Ok(summary)
+}
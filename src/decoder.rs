@@ -2,41 +2,52 @@ use std::time::Instant;
 
 use crate::{
     boardmanager::BoardStack,
+    encoder::InputEncoder,
+    mapper::{ChessStdMapper, MoveMapper},
     mcts_trainer::{Net, Node, Tree, Wdl},
-    mvs::get_contents,
 };
-use cozy_chess::{Color, Move, Piece, Rank, Square};
-use tch::{IValue, Kind, Tensor};
+use cozy_chess::{Board, BoardBuilder, Color, File, Move, Piece, Rank, Square};
+use tch::{Device, IValue, Kind, Tensor};
 
 pub fn eval_state(board: Tensor, net: &Net) -> anyhow::Result<(Tensor, Tensor)> {
     // reshape the model (originally from 1D)
     let sw = Instant::now();
     let b = board;
     // let b = b.unsqueeze(0);
-    let b = b.reshape([-1, 21, 8, 8]);
+    let b = b.reshape([-1, net.encoder.input_channels() as i64, 8, 8]);
     // println!("{:?}", b.size());
-    let b: Tensor = b.to(net.device);
+    let b: Tensor = b.to(net.device).to_kind(net.kind());
     let board = IValue::Tensor(b);
     // let elapsed = sw.elapsed().as_nanos() as f32 / 1e9;
     // println!("            preprocessing data {}s", elapsed);
     let sw = Instant::now();
-    let output = net.net.forward_is(&[board])?;
+    let output = if net.config.no_grad {
+        tch::no_grad(|| net.net.forward_is(&[board]))?
+    } else {
+        net.net.forward_is(&[board])?
+    };
     // let elapsed = sw.elapsed().as_nanos() as f32 / 1e9;
     // println!("            elapsed NN forward pass {}s", elapsed);
+    let (board_eval, policy) = split_outputs(output)?;
+    // half precision outputs are decoded in f32
+    Ok((board_eval.to_kind(Kind::Float), policy.to_kind(Kind::Float)))
+}
+
+/// number of board evaluation outputs per position: value, 3 wdl logits and moves left
+pub const BOARD_EVAL_LEN: usize = 5;
+
+/// splits the output of a forward pass into the board evaluation and the policy
+pub fn split_outputs(output: IValue) -> anyhow::Result<(Tensor, Tensor)> {
     let output_tensor = match output {
         IValue::Tuple(b) => b,
-        a => panic!("the output is not a IValue {:?}", a),
-    };
-    let (board_eval, policy) = (&output_tensor[0], &output_tensor[1]);
-    let board_eval = match board_eval {
-        IValue::Tensor(b) => b,
-        a => panic!("the output is not a Tensor {:?}", a),
+        a => anyhow::bail!("the output is not a tuple {:?}", a),
     };
-    let policy = match policy {
-        IValue::Tensor(b) => b,
-        a => panic!("the output is not a Tensor {:?}", a),
-    };
-    Ok((board_eval.clone(board_eval), policy.clone(policy)))
+    match &output_tensor[..] {
+        [IValue::Tensor(board_eval), IValue::Tensor(policy)] => {
+            Ok((board_eval.shallow_clone(), policy.shallow_clone()))
+        }
+        a => anyhow::bail!("the output is not a pair of tensors {:?}", a),
+    }
 }
 
 pub fn board_data(bs: &BoardStack) -> (Vec<f32>, Vec<bool>) {
@@ -70,22 +81,7 @@ pub fn board_data(bs: &BoardStack) -> (Vec<f32>, Vec<bool>) {
     scalar_data[6] = bs.get_reps() as f32;
     scalar_data[7] = bs.board().halfmove_clock() as f32;
 
-    let mut counter = 0;
-    let mut pieces_sqs: Vec<bool> = vec![false; 64 * 12];
-    for colour in li {
-        for piece in Piece::ALL {
-            for tile in bs.board().colored_pieces(colour, piece) {
-                if li[0] == Color::Black {
-                    pieces_sqs[(63 - (tile.rank() as usize * 8 + (7 - tile.file() as usize)))
-                        + (64 * counter)] = true;
-                } else {
-                    pieces_sqs[(tile.rank() as usize * 8 + tile.file() as usize) + 64 * counter] =
-                        true;
-                }
-            }
-            counter += 1
-        }
-    }
+    let mut pieces_sqs = piece_planes(bs.board(), us);
 
     let is_ep = bs.board().en_passant();
     // let fenstr = format!("{}", bs.board());
@@ -111,6 +107,79 @@ pub fn board_data(bs: &BoardStack) -> (Vec<f32>, Vec<bool>) {
     (scalar_data, pieces_sqs)
 }
 
+/// the 12 piece planes of `board` from the point of view of `us`, our pieces first
+///
+/// the planes are mirrored vertically when `us` is black
+pub fn piece_planes(board: &Board, us: Color) -> Vec<bool> {
+    let mut counter = 0;
+    let mut pieces_sqs: Vec<bool> = vec![false; 64 * 12];
+    for colour in [us, !us] {
+        for piece in Piece::ALL {
+            for tile in board.colored_pieces(colour, piece) {
+                if us == Color::Black {
+                    pieces_sqs[(63 - (tile.rank() as usize * 8 + (7 - tile.file() as usize)))
+                        + (64 * counter)] = true;
+                } else {
+                    pieces_sqs[(tile.rank() as usize * 8 + tile.file() as usize) + 64 * counter] =
+                        true;
+                }
+            }
+            counter += 1
+        }
+    }
+    pieces_sqs
+}
+
+/// inverse of `board_data`, None if the data does not describe a valid board
+///
+/// castling rights are assumed to be standard chess and the fullmove number is not stored, so it is set to 1
+pub fn decode_board(board_scalars: &[f32], board_bools: &[bool]) -> Option<Board> {
+    if board_scalars.len() != 8 || board_bools.len() < 64 * 13 {
+        return None;
+    }
+    let us = if board_scalars[1] == 1.0 {
+        Color::Black
+    } else {
+        Color::White
+    };
+    let li = [us, !us];
+
+    // the planes of black to move are mirrored vertically
+    let square = |index: usize| {
+        let rank = Rank::index(index / 8);
+        let rank = if us == Color::Black {
+            rank.flip()
+        } else {
+            rank
+        };
+        Square::new(File::index(index % 8), rank)
+    };
+
+    let mut builder = BoardBuilder::empty();
+    builder.side_to_move = us;
+    for (c, &color) in li.iter().enumerate() {
+        for (p, &piece) in Piece::ALL.iter().enumerate() {
+            let plane = &board_bools[64 * (6 * c + p)..64 * (6 * c + p + 1)];
+            for index in (0..64).filter(|&i| plane[i]) {
+                *builder.square_mut(square(index)) = Some((piece, color));
+            }
+        }
+        let rights = builder.castle_rights_mut(color);
+        rights.long = (board_scalars[2 + 2 * c] == 1.0).then_some(File::A);
+        rights.short = (board_scalars[3 + 2 * c] == 1.0).then_some(File::H);
+    }
+
+    let ep_plane = &board_bools[64 * 12..64 * 13];
+    builder.en_passant = ep_plane
+        .iter()
+        .position(|&b| b)
+        .map(|index| Square::new(File::index(index % 8), Rank::Sixth.relative_to(us)));
+    builder.halfmove_clock = board_scalars[7] as u8;
+    builder.fullmove_number = 1;
+
+    builder.build().ok()
+}
+
 pub fn convert_board(bs: &BoardStack) -> Tensor {
     // FULL LIST HERE:
     // sq1 - white's turn
@@ -127,20 +196,7 @@ pub fn convert_board(bs: &BoardStack) -> Tensor {
     // it seems that creating a Vec, processing everything first is faster than doing Tensor::zeros() and then stacking them
     // so i instead work with Vecs, get all of them together and convert them into a single Tensor at the end
 
-    let mut all_data: Vec<f32> = Vec::new();
-
-    let (scalar_data, pieces_sqs) = board_data(bs);
-
-    for v in &scalar_data {
-        for _ in 0..64 {
-            all_data.push(*v);
-        }
-    }
-
-    all_data.extend(pieces_sqs.iter().map(|&x| x as u8 as f32));
-
-    let all_data = Tensor::from_slice(&all_data);
-    all_data // all_data is 1d
+    InputEncoder::V1.encode(bs) // 1d
 }
 
 pub fn eval_board(
@@ -149,58 +205,209 @@ pub fn eval_board(
     tree: &mut Tree,
     selected_node_idx: &usize,
 ) -> Vec<usize> {
-    let output = get_evaluation(bs, net);
-    process_board_output(output, selected_node_idx, tree, &bs)
+    let (legal_moves, idx_li) = legal_policy_indices(bs);
+    let (board_eval, policy) = get_evaluation(bs, net);
+    let output = decode_outputs(&board_eval, &policy, std::slice::from_ref(&idx_li))
+        .expect("Error")
+        .remove(0);
+    process_board_output(&output, &legal_moves, selected_node_idx, tree, bs);
+    idx_li
 }
 
 pub fn get_evaluation(bs: &BoardStack, net: &Net) -> (Tensor, Tensor) {
-    let b = convert_board(bs);
+    let b = net.encoder.encode(bs);
 
     eval_state(b, &net).expect("Error")
 }
 
+/// the evaluation of a single position, from the point of view of the side to move
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetOutput {
+    pub value: f32,
+    pub wdl: [f32; 3],
+    pub moves_left: f32,
+    pub policy: Vec<f32>, // softmax over the legal moves, in the order of the indices sent with the job
+}
+
+impl NetOutput {
+    /// checks for NaN or infinite outputs, a sign of a broken network
+    pub fn check_finite(&self) -> Result<(), String> {
+        let scalars = [
+            self.value,
+            self.wdl[0],
+            self.wdl[1],
+            self.wdl[2],
+            self.moves_left,
+        ];
+        if scalars.iter().chain(&self.policy).all(|x| x.is_finite()) {
+            Ok(())
+        } else {
+            Err(format!(
+                "network output is not finite: value {}, wdl {:?}, moves left {}",
+                self.value, self.wdl, self.moves_left
+            ))
+        }
+    }
+
+    /// the mean of two evaluations of equivalent positions, the priors have to be for the same moves
+    pub fn average(&self, other: &NetOutput) -> NetOutput {
+        assert_eq!(self.policy.len(), other.policy.len());
+        let mean = |a: f32, b: f32| (a + b) / 2.0;
+        NetOutput {
+            value: mean(self.value, other.value),
+            wdl: [0, 1, 2].map(|i| mean(self.wdl[i], other.wdl[i])),
+            moves_left: mean(self.moves_left, other.moves_left),
+            policy: self
+                .policy
+                .iter()
+                .zip(&other.policy)
+                .map(|(&a, &b)| mean(a, b))
+                .collect(),
+        }
+    }
+}
+
+/// the legal moves of the position and their policy indices
+pub fn legal_policy_indices(bs: &BoardStack) -> (Vec<Move>, Vec<usize>) {
+    let mapper = ChessStdMapper::get();
+    let mut legal_moves: Vec<Move> = Vec::new();
+    bs.board().generate_moves(|moves| {
+        // Unpack dense move set into move list
+        legal_moves.extend(moves);
+        false
+    });
+    let idx_li: Vec<usize> = legal_moves
+        .iter()
+        .map(|&mv| {
+            mapper
+                .move_to_index(bs.board(), mv)
+                .expect("legal move missing from the policy layout")
+        })
+        .collect();
+    (legal_moves, idx_li)
+}
+
+/// turns the raw network outputs of a batch into values and priors over the legal moves
+///
+/// the policy logits are gathered at `legal_indices` (one list per position) and a masked softmax
+/// is done for the whole batch at once
+///
+/// fails if the outputs do not have the expected shape
+pub fn decode_outputs(
+    board_eval: &Tensor,
+    policy: &Tensor,
+    legal_indices: &[impl AsRef<[usize]>],
+) -> Result<Vec<NetOutput>, String> {
+    let legal_indices: Vec<&[usize]> = legal_indices.iter().map(|li| li.as_ref()).collect();
+    let batch_size = legal_indices.len();
+    if board_eval.numel() != batch_size * BOARD_EVAL_LEN {
+        return Err(format!(
+            "expected value, 3 wdl logits and moves left for {} positions, got shape {:?}",
+            batch_size,
+            board_eval.size()
+        ));
+    }
+    let policy_len = policy.numel() / batch_size.max(1);
+    if policy.numel() != batch_size * policy_len {
+        return Err(format!(
+            "policy of shape {:?} does not split into {} positions",
+            policy.size(),
+            batch_size
+        ));
+    }
+    if let Some(&index) = legal_indices
+        .iter()
+        .flat_map(|li| li.iter())
+        .find(|&&i| i >= policy_len)
+    {
+        return Err(format!(
+            "policy index {} is out of range for a policy of {}",
+            index, policy_len
+        ));
+    }
+    let board_eval = board_eval.to(Device::Cpu).reshape([batch_size as i64, -1]);
+    let policy = policy.to(Device::Cpu).reshape([batch_size as i64, -1]);
+
+    let values: Vec<f32> = Vec::try_from(board_eval.select(1, 0).tanh()).expect("Error");
+    let wdls: Vec<f32> = Vec::try_from(
+        board_eval
+            .narrow(1, 1, 3)
+            .softmax(1, Kind::Float)
+            .flatten(0, -1),
+    )
+    .expect("Error");
+    let moves_left: Vec<f32> = Vec::try_from(board_eval.select(1, 4)).expect("Error");
+
+    // positions have different numbers of legal moves, the padding is masked out before the softmax
+    let max_moves = legal_indices
+        .iter()
+        .map(|li| li.len())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let mut gather_indices = vec![0i64; batch_size * max_moves];
+    let mut mask = vec![f32::NEG_INFINITY; batch_size * max_moves];
+    for (i, li) in legal_indices.iter().enumerate() {
+        for (j, &idx) in li.iter().enumerate() {
+            gather_indices[i * max_moves + j] = idx as i64;
+            mask[i * max_moves + j] = 0.0;
+        }
+    }
+    let shape = [batch_size as i64, max_moves as i64];
+    let gather_indices = Tensor::from_slice(&gather_indices).reshape(shape);
+    let mask = Tensor::from_slice(&mask).reshape(shape);
+    let priors = (policy.gather(1, &gather_indices, false) + mask).softmax(1, Kind::Float);
+    let priors: Vec<f32> = Vec::try_from(priors.flatten(0, -1)).expect("Error");
+
+    Ok(legal_indices
+        .iter()
+        .enumerate()
+        .map(|(i, li)| NetOutput {
+            value: values[i],
+            wdl: [wdls[3 * i], wdls[3 * i + 1], wdls[3 * i + 2]],
+            moves_left: moves_left[i],
+            policy: priors[i * max_moves..i * max_moves + li.len()].to_vec(),
+        })
+        .collect())
+}
+
+/// adds the children of the selected node with the priors of `output`, `legal_moves` are in the order of the priors
 pub fn process_board_output(
-    output: (Tensor, Tensor),
+    output: &NetOutput,
+    legal_moves: &[Move],
     selected_node_idx: &usize,
     tree: &mut Tree,
     bs: &BoardStack,
-) -> Vec<usize> {
-    let (wdl, moves_left, value, legal_moves, idx_li, pol_list) = extract_from_tensor(output, bs);
+) {
     let mut counter = 0;
 
-    tree.nodes[*selected_node_idx].moves_left = moves_left;
+    // the network sees the board from the side to move, the tree stores values from white's point of view
+    let value = match bs.board().side_to_move() {
+        Color::Black => -output.value,
+        Color::White => output.value,
+    };
+
+    tree.nodes[*selected_node_idx].moves_left = output.moves_left;
     tree.nodes[*selected_node_idx].eval_score = value;
     tree.nodes[*selected_node_idx].wdl = Wdl {
-        w: wdl[0],
-        d: wdl[1],
-        l: wdl[2],
+        w: output.wdl[0],
+        d: output.wdl[1],
+        l: output.wdl[2],
     };
 
     // tree.nodes[*selected_node_idx].eval_score = 0.0;
     let ct = tree.nodes.len();
-    for (mv, pol) in legal_moves.iter().zip(pol_list.iter()) {
+    for (mv, pol) in legal_moves.iter().zip(output.policy.iter()) {
         // // println!("VAL {}", value);
-        let fm: Move;
-        if bs.board().side_to_move() == Color::Black {
-            // flip move
-            fm = Move {
-                from: mv.from.flip_rank(),
-                to: mv.to.flip_rank(),
-                promotion: mv.promotion,
-            };
-        } else {
-            fm = *mv;
-        }
         // FLAT POLICY VER
-        // let child = Node::new(1.0/legal_moves.len() as f32, Some(*selected_node_idx), Some(fm));
-        let child = Node::new(*pol, Some(*selected_node_idx), Some(fm));
+        // let child = Node::new(1.0/legal_moves.len() as f32, Some(*selected_node_idx), Some(*mv));
+        let child = Node::new(*pol, Some(*selected_node_idx), Some(*mv));
         // // println!("{:?}, {:?}, {:?}", mv, child.policy, child.eval_score);
         tree.nodes.push(child); // push child to the tree Vec<Node>
         counter += 1
     }
     tree.nodes[*selected_node_idx].children = ct..ct + counter; // push numbers
                                                                 // // println!("{:?}", tree.nodes.len());
-    idx_li
 }
 
 pub fn extract_from_tensor(
@@ -208,88 +415,21 @@ pub fn extract_from_tensor(
     bs: &BoardStack,
 ) -> (Vec<f32>, f32, f32, Vec<Move>, Vec<usize>, Vec<f32>) {
     let (board_eval, policy) = output;
-    // check policy, eval ordering!
-
-    let board_eval = board_eval.squeeze();
-    let contents = get_contents();
-
-    let board_evals: Vec<f32> = Vec::try_from(board_eval).expect("Error");
-
-    let value: f32 = board_evals[0].tanh();
-
-    let wdl_logits: Tensor = Tensor::from_slice(&board_evals[1..4]);
-
-    let wdl = Tensor::softmax(&wdl_logits, 0, Kind::Float);
-
-    let wdl: Vec<f32> = Vec::try_from(wdl).expect("Error");
-
-    let moves_left = board_evals[4];
-
-    let policy = policy.squeeze();
-    let policy: Vec<f32> = Vec::try_from(policy).expect("Error");
-    let value = f32::try_from(value).expect("Error");
+    let (legal_moves, idx_li) = legal_policy_indices(bs);
+    let output = decode_outputs(&board_eval, &policy, std::slice::from_ref(&idx_li))
+        .expect("Error")
+        .remove(0);
 
     let value = match bs.board().side_to_move() {
-        Color::Black => -value,
-        Color::White => value,
+        Color::Black => -output.value,
+        Color::White => output.value,
     };
-
-    // step 1 - get the corresponding idx for legal moves
-
-    let mut legal_moves: Vec<Move> = Vec::new();
-    bs.board().generate_moves(|moves| {
-        // Unpack dense move set into move list
-        legal_moves.extend(moves);
-        false
-    });
-
-    let mut fm: Vec<Move> = Vec::new();
-    if bs.board().side_to_move() == Color::Black {
-        // flip move
-        for mv in &legal_moves {
-            fm.push(Move {
-                from: mv.from.flip_rank(),
-                to: mv.to.flip_rank(),
-                promotion: mv.promotion,
-            })
-        }
-    } else {
-        fm = legal_moves.clone();
-    }
-
-    legal_moves = fm;
-
-    let mut idx_li: Vec<usize> = Vec::new();
-
-    for mov in &legal_moves {
-        // let mov = format!("{}", mov);
-        if let Some(idx) = contents.iter().position(|x| mov == x) {
-            idx_li.push(idx as usize);
-        }
-    }
-
-    // step 2 - using the idx in step 1, index all the policies involved
-    let mut pol_list: Vec<f32> = Vec::new();
-    for id in &idx_li {
-        pol_list.push(policy[*id]);
-    }
-
-    // // println!("{:?}", pol_list);
-
-    // step 3 - softmax
-
-    let sm = Tensor::from_slice(&pol_list);
-
-    let sm = Tensor::softmax(&sm, 0, Kind::Float);
-
-    let pol_list: Vec<f32> = Vec::try_from(sm).expect("Error");
-
-    // // println!("{:?}", pol_list);
-
-    // // println!("        V={}", &value);
-
-    // println!("        Value={}", &value);
-
-    // step 4 - iteratively append nodes into class
-    (wdl, moves_left, value, legal_moves, idx_li, pol_list)
+    (
+        output.wdl.to_vec(),
+        output.moves_left,
+        value,
+        legal_moves,
+        idx_li,
+        output.policy,
+    )
 }
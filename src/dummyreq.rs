@@ -1,7 +1,8 @@
 use crate::{
     boardmanager::BoardStack,
-    decoder::convert_board,
-    executor::{Packet, ReturnMessage},
+    decoder::legal_policy_indices,
+    encoder::InputEncoder,
+    executor::{EvalRequest, EvalResponse},
 };
 use cozy_chess::Board;
 use flume::Sender;
@@ -10,17 +11,19 @@ use rand::{Rng, SeedableRng};
 use std::thread::sleep;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn send_request(sender: Sender<Packet>, id: usize) {
+pub fn send_request(sender: Sender<EvalRequest>, id: usize) {
     let board = Board::default();
     let bs = BoardStack::new(board);
-    let input_tensor = convert_board(&bs);
-    let (resender_send, recv) = flume::bounded::<ReturnMessage>(1); // mcts to executor
+    let input_tensor = InputEncoder::default().encode_packed(&bs);
+    let (_, legal_indices) = legal_policy_indices(&bs);
+    let (resender_send, recv) = flume::bounded::<EvalResponse>(1); // mcts to executor
 
     loop {
-        let pack = Packet {
-            job: input_tensor.clone(&input_tensor),
+        let pack = EvalRequest {
+            id: 0,
+            input: input_tensor.clone(),
+            legal_indices: legal_indices.clone(),
             resender: resender_send.clone(),
-            id: "dummy-req".to_string(),
         };
         // println!("HIIIIII");
         sender.send(pack).unwrap();
@@ -44,20 +47,22 @@ pub fn send_request(sender: Sender<Packet>, id: usize) {
         }
     }
 }
-pub async fn send_request_async(sender: Sender<Packet>, id: usize) {
+pub async fn send_request_async(sender: Sender<EvalRequest>, id: usize) {
     let board = Board::default();
 
     let bs = BoardStack::new(board);
 
-    let input_tensor = convert_board(&bs);
+    let input_tensor = InputEncoder::default().encode_packed(&bs);
+    let (_, legal_indices) = legal_policy_indices(&bs);
 
-    let (resender_send, recv) = flume::bounded::<ReturnMessage>(1); // mcts to executor
+    let (resender_send, recv) = flume::bounded::<EvalResponse>(1); // mcts to executor
     let mut rng = StdRng::from_entropy();
     loop {
-        let pack = Packet {
-            job: input_tensor.clone(&input_tensor),
+        let pack = EvalRequest {
+            id: 0,
+            input: input_tensor.clone(),
+            legal_indices: legal_indices.clone(),
             resender: resender_send.clone(),
-            id: "dummy-req".to_string(),
         };
         // println!("HIIIIII");
         sender.send_async(pack).await.unwrap();
@@ -0,0 +1,162 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tch::{CModule, IValue, Tensor};
+
+use crate::{
+    boardmanager::BoardStack,
+    decoder::{board_data, piece_planes},
+};
+
+/// how a position is turned into network inputs
+///
+/// the encoder is written into the metadata of data files and checked when a network is loaded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputEncoder {
+    /// 8 scalar planes, 12 piece planes and the en passant plane, see `board_data`
+    #[default]
+    V1,
+    /// V1 followed by the piece planes of the last `history` positions
+    V2 { history: usize },
+}
+
+/// a position as scalars and bit-packed bool planes, expanded into the batch buffer of the executor
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodedInput {
+    pub scalars: Vec<f32>,
+    pub bools: Vec<u8>, // packed, 8 bools per byte with the first bool in the lowest bit
+}
+
+impl EncodedInput {
+    /// writes the network input planes into `out`, scalars are broadcast to full planes
+    pub fn write_planes(&self, out: &mut [f32]) {
+        let (scalar_planes, bool_planes) = out.split_at_mut(64 * self.scalars.len());
+        assert_eq!(
+            bool_planes.len(),
+            8 * self.bools.len(),
+            "slot size does not match the input"
+        );
+        for (plane, &v) in scalar_planes.chunks_exact_mut(64).zip(&self.scalars) {
+            plane.fill(v);
+        }
+        for (values, &byte) in bool_planes.chunks_exact_mut(8).zip(&self.bools) {
+            for (j, v) in values.iter_mut().enumerate() {
+                *v = ((byte >> j) & 1) as f32;
+            }
+        }
+    }
+}
+
+/// packs bools into bytes, the number of bools has to be a multiple of 8
+pub fn pack_bools(bools: &[bool]) -> Vec<u8> {
+    assert_eq!(bools.len() % 8, 0);
+    bools
+        .chunks_exact(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (j, &b)| byte | ((b as u8) << j))
+        })
+        .collect()
+}
+
+impl fmt::Display for InputEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputEncoder::V1 => write!(f, "v1"),
+            InputEncoder::V2 { history } => write!(f, "v2 with {} history positions", history),
+        }
+    }
+}
+
+impl InputEncoder {
+    pub fn version(&self) -> i64 {
+        match self {
+            InputEncoder::V1 => 1,
+            InputEncoder::V2 { .. } => 2,
+        }
+    }
+
+    /// number of previous boards the `BoardStack` has to keep for this encoder
+    pub fn history_len(&self) -> usize {
+        match self {
+            InputEncoder::V1 => 0,
+            InputEncoder::V2 { history } => *history,
+        }
+    }
+
+    pub fn input_scalar_count(&self) -> usize {
+        8 // move turn, castling, repetitions and the halfmove clock
+    }
+
+    pub fn input_bool_shape(&self) -> Vec<usize> {
+        match self {
+            InputEncoder::V1 => vec![13, 8, 8], // pieces + EP
+            InputEncoder::V2 { history } => vec![13 + 12 * history, 8, 8],
+        }
+    }
+
+    /// number of 8x8 planes the network gets
+    pub fn input_channels(&self) -> usize {
+        self.input_scalar_count() + self.input_bool_shape()[0]
+    }
+
+    /// the scalars and bool planes that are stored in the data files
+    pub fn board_data(&self, bs: &BoardStack) -> (Vec<f32>, Vec<bool>) {
+        let (scalar_data, mut bool_data) = board_data(bs);
+        if let InputEncoder::V2 { history } = *self {
+            assert!(
+                bs.has_history(history),
+                "the BoardStack does not keep enough boards, create it with `with_history({})`",
+                history
+            );
+            // previous boards are seen from the side to move now, planes before the start of the game are empty
+            let us = bs.board().side_to_move();
+            let boards = bs.history();
+            for i in 0..history {
+                match boards.len().checked_sub(i + 1) {
+                    Some(index) => bool_data.extend(piece_planes(&boards[index], us)),
+                    None => bool_data.extend([false; 64 * 12]),
+                }
+            }
+        }
+        (scalar_data, bool_data)
+    }
+
+    /// the compact input for a single position, as sent to the executor
+    pub fn encode_packed(&self, bs: &BoardStack) -> EncodedInput {
+        let (scalars, bools) = self.board_data(bs);
+        EncodedInput {
+            scalars,
+            bools: pack_bools(&bools),
+        }
+    }
+
+    /// number of floats the input of a single position takes in a batch
+    pub fn input_len(&self) -> usize {
+        64 * self.input_channels()
+    }
+
+    /// the network input for a single position as a flat tensor, scalars are broadcast to full planes
+    pub fn encode(&self, bs: &BoardStack) -> Tensor {
+        let mut all_data = vec![0.0; self.input_len()];
+        self.encode_packed(bs).write_planes(&mut all_data);
+        Tensor::from_slice(&all_data)
+    }
+
+    /// checks the input encoding version a network declares with an `input_encoding_version` method
+    ///
+    /// networks without the method pass, their input shape is checked by `netinfo::probe`
+    pub fn check_network(&self, net: &CModule) -> Result<(), String> {
+        if let Ok(IValue::Int(version)) = net.method_is::<IValue>("input_encoding_version", &[]) {
+            if version != self.version() {
+                return Err(format!(
+                    "network expects input encoding v{}, the encoder is {}",
+                    version, self
+                ));
+            }
+        }
+        Ok(())
+    }
+}
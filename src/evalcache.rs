@@ -0,0 +1,147 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use crate::{decoder::NetOutput, encoder::EncodedInput};
+
+/// the exact network input of a position, the scalars are stored as bits so the key can be hashed
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    scalars: Vec<u32>,
+    bools: Vec<u8>,
+}
+
+impl CacheKey {
+    pub fn new(input: &EncodedInput) -> Self {
+        Self {
+            scalars: input.scalars.iter().map(|s| s.to_bits()).collect(),
+            bools: input.bools.clone(),
+        }
+    }
+
+    /// the key of the canonical orientation of a position, and whether that is the mirrored one
+    ///
+    /// `mirrored` is the input of the mirrored position if it is equivalent, the smaller input is canonical
+    pub fn canonical(input: &EncodedInput, mirrored: Option<&EncodedInput>) -> (Self, bool) {
+        match mirrored {
+            Some(mirrored) if mirrored.bools < input.bools => (Self::new(mirrored), true),
+            _ => (Self::new(input), false),
+        }
+    }
+}
+
+struct CacheEntry {
+    value: f32,
+    wdl: [f32; 3],
+    moves_left: f32,
+    priors: Vec<(usize, f32)>, // by policy index in the canonical orientation, sorted
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    order: VecDeque<CacheKey>, // oldest first, evicted when the cache is full
+    generation: u64,           // the network generation of the entries
+}
+
+/// most entries of the cache of a single search, long searches keep the most recent positions
+const SEARCH_CACHE_LIMIT: usize = 1 << 16;
+
+/// network evaluations by input, the oldest entry is dropped when the cache is full
+///
+/// priors are stored by policy index of the key's orientation. clients with mirror evaluation key
+/// positions by their canonical orientation, so a castling-free position and its mirror image
+/// share an entry. the entries of an old network are dropped when the first evaluation of a newer
+/// one is stored
+pub struct EvalCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+impl EvalCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// a cache for a single search, which evaluates at most one position per node
+    pub fn for_search(max_nodes: u128) -> Self {
+        Self::new(max_nodes.min(SEARCH_CACHE_LIMIT as u128) as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the cached evaluation with priors for the moves at `policy_indices` of the canonical orientation
+    pub fn get(&self, key: &CacheKey, policy_indices: &[usize]) -> Option<NetOutput> {
+        let state = self.state.lock().unwrap();
+        let entry = state.entries.get(key)?;
+        let policy = policy_indices
+            .iter()
+            .map(|index| {
+                let i = entry.priors.binary_search_by_key(index, |&(i, _)| i).ok()?;
+                Some(entry.priors[i].1)
+            })
+            .collect::<Option<Vec<f32>>>()?;
+        Some(NetOutput {
+            value: entry.value,
+            wdl: entry.wdl,
+            moves_left: entry.moves_left,
+            policy,
+        })
+    }
+
+    /// stores `output` of the network `generation`, its priors belong to the moves at `policy_indices`
+    /// of the canonical orientation
+    pub fn insert(
+        &self,
+        key: CacheKey,
+        output: &NetOutput,
+        policy_indices: &[usize],
+        generation: u64,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut priors: Vec<(usize, f32)> = policy_indices
+            .iter()
+            .copied()
+            .zip(output.policy.iter().copied())
+            .collect();
+        priors.sort_unstable_by_key(|&(i, _)| i);
+        let entry = CacheEntry {
+            value: output.value,
+            wdl: output.wdl,
+            moves_left: output.moves_left,
+            priors,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if generation < state.generation {
+            return; // a late result of a network that has been replaced
+        }
+        if generation > state.generation {
+            state.entries.clear();
+            state.order.clear();
+            state.generation = generation;
+        }
+        if state.entries.contains_key(&key) {
+            return;
+        }
+        if state.entries.len() >= self.capacity {
+            if let Some(oldest) = state.order.pop_front() {
+                state.entries.remove(&oldest);
+            }
+        }
+        state.order.push_back(key.clone());
+        state.entries.insert(key, entry);
+    }
+}
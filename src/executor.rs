@@ -1,36 +1,341 @@
-use crate::{decoder::eval_state, mcts_trainer::Net, selfplay::CollectorMessage, superluminal::{CL_RED, CL_BLUE, CL_ORANGE}};
-use flume::{Receiver, RecvError, Selector, Sender};
+use crate::{batchbuffer::BatchBuffer, boardmanager::BoardStack, decoder::{decode_outputs, eval_state, legal_policy_indices, NetOutput}, encoder::{EncodedInput, InputEncoder}, evalcache::{CacheKey, EvalCache}, mcts_trainer::Net, mirror::{can_mirror, mirror_input, mirrored_policy_indices}, netconfig::{NetConfig, SwapPolicy}, selfplay::CollectorMessage, settings::SearchSettings, superluminal::{CL_RED, CL_BLUE, CL_ORANGE, CL_GREEN, CL_PINK}};
+use flume::{Receiver, RecvError, Selector, Sender, TryRecvError};
 use superluminal_perf::{begin_event_with_color, end_event};
 use std::{
-    cmp::min,
-    collections::VecDeque,
+    cmp::{self, min},
+    fmt,
+    sync::{Arc, RwLock},
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tch::Tensor;
 
-pub struct Packet {
-    pub job: Tensor,
-    pub resender: Sender<ReturnMessage>,
-    pub id: String,
+/// request for the evaluation of a single position
+pub struct EvalRequest {
+    pub id: u64, // echoed in the response
+    pub input: EncodedInput,
+    pub legal_indices: Vec<usize>, // policy indices of the legal moves, the priors are returned in this order
+    pub resender: Sender<EvalResponse>,
 }
 
-pub struct ReturnPacket {
-    pub packet: (Tensor, Tensor),
-    pub id: String,
+pub struct EvalResponse {
+    pub id: u64,
+    pub generation: u64, // the `NetUpdate::generation` of the network that answered, 0 for errors
+    pub result: Result<NetOutput, EvalError>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// the executor stopped before answering
+    Disconnected,
+    /// a response to a request that is not pending arrived, or one request was answered twice
+    UnexpectedResponse { expected: u64, got: u64 },
+    /// the network could not be loaded, the executor has nothing to evaluate with
+    NoNetwork(String),
+    /// the forward pass failed or the outputs have the wrong shape
+    Network(String),
+    /// the outputs for this position contain NaN or infinite values
+    InvalidOutput(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Disconnected => write!(f, "the executor is gone"),
+            EvalError::UnexpectedResponse { expected, got } => write!(
+                f,
+                "expected the response to request {}, got {}",
+                expected, got
+            ),
+            EvalError::NoNetwork(e) => write!(f, "no network loaded: {}", e),
+            EvalError::Network(e) => write!(f, "network evaluation failed: {}", e),
+            EvalError::InvalidOutput(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// a network for `executor_main` to swap in
+pub struct NetUpdate {
+    pub path: String,
+    pub generation: u64, // assigned by the sender, the responses of this network are tagged with it
+    pub net_hash: Option<String>, // published by `executor_main` once the network is loaded
 }
 
 pub enum Message {
-    NewNetwork(Result<String, RecvError>),
-    JobTensor(Result<Packet, RecvError>), // (converted) tensor from mcts search that needs NN evaluation
+    NewNetwork(Result<NetUpdate, RecvError>),
+    JobTensor(Result<EvalRequest, RecvError>), // position from mcts search that needs NN evaluation
 
     StopServer(), // end the executor process
 }
 
-pub enum ReturnMessage {
-    ReturnMessage(Result<ReturnPacket, RecvError>),
+/// sends positions to an executor and waits for the results
+///
+/// responses come back on a single channel that is reused for every request,
+/// so each search task has its own client
+pub struct EvalClient {
+    sender: Sender<EvalRequest>,
+    response_send: Sender<EvalResponse>,
+    response_recv: Receiver<EvalResponse>,
+    next_id: u64,
+    encoder: InputEncoder,
+    mirror: bool,
+    cache: Option<Arc<EvalCache>>,
+    generation: u64, // the newest network generation that answered
 }
 
-fn handle_new_graph(network: &mut Option<Net>, graph: Option<String>, thread_name: &str) {
+impl EvalClient {
+    /// `encoder` has to match the encoder of the executor behind `sender`
+    pub fn new(sender: Sender<EvalRequest>, encoder: InputEncoder) -> Self {
+        // room for the two requests of a mirrored evaluation
+        let (response_send, response_recv) = flume::bounded(2);
+        Self {
+            sender,
+            response_send,
+            response_recv,
+            next_id: 0,
+            encoder,
+            mirror: false,
+            cache: None,
+            generation: 0,
+        }
+    }
+
+    /// a client for one search, the cache catches positions the search reaches more than once
+    pub fn for_search(sender: Sender<EvalRequest>, settings: &SearchSettings) -> Self {
+        Self::new(sender, settings.encoder)
+            .with_mirror(settings.mirror)
+            .with_cache(Arc::new(EvalCache::for_search(settings.max_nodes)))
+    }
+
+    /// also evaluate the mirror image of positions without castling rights and average the results
+    pub fn with_mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// look up and store evaluations in `cache`, which can be shared between clients of the same network
+    pub fn with_cache(mut self, cache: Arc<EvalCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// the newest network generation that answered this client, 0 if none did or if the executor
+    /// never swaps its network
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// the network evaluation of `bs`, with priors for the legal moves in generation order
+    ///
+    /// never returns the result of a network older than one that already answered this client
+    pub async fn evaluate(&mut self, bs: &BoardStack) -> Result<NetOutput, EvalError> {
+        let (legal_moves, legal_indices) = legal_policy_indices(bs);
+        let input = self.encoder.encode_packed(bs);
+        // without mirror evaluation the position is cached as it is, so it never gets the
+        // averaged result of its mirror image
+        let mirrored = if self.mirror && can_mirror(bs.board()) {
+            let indices = mirrored_policy_indices(bs.board(), &legal_moves);
+            Some((mirror_input(&input), indices))
+        } else {
+            None
+        };
+
+        let (key, canonical_is_mirrored) =
+            CacheKey::canonical(&input, mirrored.as_ref().map(|(input, _)| input));
+        let canonical_indices = match &mirrored {
+            Some((_, indices)) if canonical_is_mirrored => indices.clone(),
+            _ => legal_indices.clone(),
+        };
+        if let Some(cache) = &self.cache {
+            if let Some(output) = cache.get(&key, &canonical_indices) {
+                return Ok(output);
+            }
+        }
+
+        let (output, generation) = loop {
+            let (output, generation) = match &mirrored {
+                Some((mirrored_input, mirrored_indices)) => {
+                    // both are sent before waiting so they can end up in the same batch
+                    let ids = [
+                        self.send(input.clone(), legal_indices.clone()).await?,
+                        self.send(mirrored_input.clone(), mirrored_indices.clone())
+                            .await?,
+                    ];
+                    let mut outputs = self.receive(&ids).await?;
+                    let (mirrored_output, mirrored_generation) = outputs.pop().unwrap();
+                    let (output, generation) = outputs.pop().unwrap();
+                    // the network was swapped in between, only the result of the new one is kept
+                    match generation.cmp(&mirrored_generation) {
+                        cmp::Ordering::Equal => (output.average(&mirrored_output), generation),
+                        cmp::Ordering::Less => (mirrored_output, mirrored_generation),
+                        cmp::Ordering::Greater => (output, generation),
+                    }
+                }
+                None => {
+                    let id = self.send(input.clone(), legal_indices.clone()).await?;
+                    self.receive(&[id]).await?.remove(0)
+                }
+            };
+            // a newer network already answered, so an executor that has not swapped yet answered
+            // this one. the result is dropped and the position is sent again
+            if generation >= self.generation {
+                break (output, generation);
+            }
+        };
+
+        self.generation = self.generation.max(generation);
+        if let Some(cache) = &self.cache {
+            cache.insert(key, &output, &canonical_indices, generation);
+        }
+        Ok(output)
+    }
+
+    async fn send(&mut self, input: EncodedInput, legal_indices: Vec<usize>) -> Result<u64, EvalError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = EvalRequest {
+            id,
+            input,
+            legal_indices,
+            resender: self.response_send.clone(),
+        };
+
+        begin_event_with_color("send_req", CL_GREEN);
+        let sent = self.sender.send_async(request).await;
+        end_event();
+        sent.map_err(|_| EvalError::Disconnected)?;
+        Ok(id)
+    }
+
+    /// waits for the responses to `ids` with the generation of the network that answered,
+    /// they can arrive in any order
+    ///
+    /// responses to older requests, left over from an evaluation that failed before reading all of
+    /// its responses, are dropped
+    async fn receive(&self, ids: &[u64]) -> Result<Vec<(NetOutput, u64)>, EvalError> {
+        let oldest = *ids.iter().min().unwrap();
+        let mut results: Vec<Option<Result<(NetOutput, u64), EvalError>>> = vec![None; ids.len()];
+        while results.iter().any(|r| r.is_none()) {
+            begin_event_with_color("recv_request", CL_PINK);
+            let response = self.response_recv.recv_async().await;
+            end_event();
+            // the client holds a sender itself, so the channel never disconnects
+            let response = response.expect("response channel is owned by the client");
+            if response.id < oldest {
+                continue;
+            }
+            match ids.iter().position(|&id| id == response.id) {
+                Some(i) if results[i].is_none() => {
+                    let generation = response.generation;
+                    results[i] = Some(response.result.map(|output| (output, generation)));
+                }
+                _ => {
+                    let expected = ids[results.iter().position(|r| r.is_none()).unwrap()];
+                    return Err(EvalError::UnexpectedResponse {
+                        expected,
+                        got: response.id,
+                    });
+                }
+            }
+        }
+        results.into_iter().map(|result| result.unwrap()).collect()
+    }
+}
+
+/// a request in the current batch, answered with `EvalError::Disconnected` if it is dropped unanswered
+struct PendingEval {
+    id: u64,
+    legal_indices: Vec<usize>,
+    resender: Option<Sender<EvalResponse>>,
+}
+
+impl PendingEval {
+    fn new(request: EvalRequest) -> (EncodedInput, Self) {
+        let pending = PendingEval {
+            id: request.id,
+            legal_indices: request.legal_indices,
+            resender: Some(request.resender),
+        };
+        (request.input, pending)
+    }
+
+    fn respond(mut self, result: Result<NetOutput, EvalError>, generation: u64) {
+        self.send(result, generation);
+    }
+
+    fn send(&mut self, result: Result<NetOutput, EvalError>, generation: u64) {
+        if let Some(resender) = self.resender.take() {
+            // the client waits for exactly this response, if it is gone the search was dropped
+            let _ = resender.try_send(EvalResponse {
+                id: self.id,
+                generation,
+                result,
+            });
+        }
+    }
+}
+
+impl Drop for PendingEval {
+    fn drop(&mut self) {
+        self.send(Err(EvalError::Disconnected), 0);
+    }
+}
+
+/// runs the network on a batch, errors are returned instead of panicking so they can be sent to the searches
+fn run_batch(
+    network: &Net,
+    input: Tensor,
+    pending: &[PendingEval],
+) -> Result<Vec<NetOutput>, EvalError> {
+    let legal_indices: Vec<&[usize]> = pending.iter().map(|p| &p.legal_indices[..]).collect();
+    evaluate_batch(network, input, &legal_indices)
+}
+
+fn evaluate_batch(
+    network: &Net,
+    input: Tensor,
+    legal_indices: &[&[usize]],
+) -> Result<Vec<NetOutput>, EvalError> {
+    let (board_eval, policy) =
+        eval_state(input, network).map_err(|e| EvalError::Network(e.to_string()))?;
+    decode_outputs(&board_eval, &policy, legal_indices).map_err(EvalError::Network)
+}
+
+/// answers every request of the batch, positions with broken outputs get an error of their own
+fn answer_batch(
+    pending: &mut Vec<PendingEval>,
+    outputs: Result<Vec<NetOutput>, EvalError>,
+    generation: u64,
+    thread_name: &str,
+) {
+    match outputs {
+        Ok(outputs) => {
+            for (request, output) in pending.drain(..).zip(outputs) {
+                let result = match output.check_finite() {
+                    Ok(()) => Ok(output),
+                    Err(e) => Err(EvalError::InvalidOutput(e)),
+                };
+                request.respond(result, generation);
+            }
+        }
+        Err(e) => {
+            eprintln!("{} batch failed: {}", thread_name, e);
+            for request in pending.drain(..) {
+                request.respond(Err(e.clone()), 0);
+            }
+        }
+    }
+}
+
+/// replaces the network, on failure the error is returned and there is no network
+fn handle_new_graph(
+    network: &mut Option<Net>,
+    graph: Option<String>,
+    encoder: InputEncoder,
+    config: NetConfig,
+    thread_name: &str,
+) -> Result<(), String> {
     // drop previous network if any to save GPU memory
     if let Some(network) = network.take() {
         // // // println!("{} dropping network", thread_name);
@@ -38,26 +343,79 @@ fn handle_new_graph(network: &mut Option<Net>, graph: Option<String>, thread_nam
     }
 
     // load the new network if any
-    *network = graph.map(|graph| Net::new(&graph[..]));
+    if let Some(graph) = graph {
+        match Net::try_with_config(&graph[..], encoder, config) {
+            Ok(net) => *network = Some(net),
+            Err(e) => {
+                eprintln!("{} failed to load network: {}", thread_name, e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
 }
 
+/// replaces the network of `executor_main`, the queued requests are handled according to the swap policy
+///
+/// `generation` is the generation of `network`, it only changes if the new network loads. if it
+/// fails to load, the queued requests are answered with the load error
+#[allow(clippy::too_many_arguments)]
+fn swap_network(
+    network: &mut Option<Net>,
+    generation: &mut u64,
+    update: NetUpdate,
+    input_buffer: &mut BatchBuffer,
+    pending: &mut Vec<PendingEval>,
+    encoder: InputEncoder,
+    config: NetConfig,
+    thread_name: &str,
+) -> Result<(), String> {
+    if config.swap_policy == SwapPolicy::FinishBatch && !pending.is_empty() {
+        if let Some(old_network) = network.as_ref() {
+            // the queued requests were sent while the old network was in use
+            let outputs = run_batch(old_network, input_buffer.batch(), pending);
+            answer_batch(pending, outputs, *generation, thread_name);
+            input_buffer.clear();
+        }
+    }
+    let loaded = handle_new_graph(network, Some(update.path), encoder, config, thread_name);
+    if loaded.is_ok() {
+        *generation = update.generation;
+    }
+    if let Err(e) = &loaded {
+        for request in pending.drain(..) {
+            request.respond(Err(EvalError::NoNetwork(e.clone())), 0);
+        }
+        input_buffer.clear();
+    }
+    loaded
+}
+
+/// the responses are tagged with the `NetUpdate::generation` of the network that evaluated them
+///
+/// `loaded_net_hash` is set to the `NetUpdate::net_hash` of every network that loads
 pub fn executor_main(
-    net_receiver: Receiver<String>,
-    tensor_receiver: Receiver<Packet>, // receive tensors from mcts
+    net_receiver: Receiver<NetUpdate>,
+    tensor_receiver: Receiver<EvalRequest>, // receive tensors from mcts
     num_threads: usize,
     evals_per_sec_sender: Sender<CollectorMessage>,
+    encoder: InputEncoder, // has to match the encoder of the searches sending tensors
+    config: NetConfig,
+    loaded_net_hash: Arc<RwLock<Option<String>>>,
 ) {
     let max_batch_size = min(1024, num_threads);
     let mut graph_disconnected = false;
     let mut network: Option<Net> = None;
+    let mut net_generation = 0; // the generation of `network`
     let thread_name = std::thread::current()
         .name()
         .unwrap_or("unnamed-executor")
         .to_owned();
-    let mut input_vec: VecDeque<Tensor> = VecDeque::new();
+    // pinned memory only helps when the batches are copied to a cuda device
+    let mut input_buffer = BatchBuffer::new(max_batch_size, encoder, config.device.device());
     let mut debug_counter = 0;
-    let mut output_senders = VecDeque::new(); // collect senders
-    let mut id_vec = VecDeque::new(); // collect senders
+    let mut pending: Vec<PendingEval> = Vec::new(); // the requests in the batch
+    let mut load_error: Option<String> = None; // set while the last network failed to load
                                       // // println!("num_threads (generator): {}", num_threads);
 
     let mut waiting_batch: Instant = Instant::now(); // time spent idling (total for each batch)
@@ -73,7 +431,7 @@ pub fn executor_main(
         // // println!("thread {} loop {}:", thread_name, debug_counter);
         // // println!("    thread {} number of requests made from mcts: {}, {}", thread_name, output_senders.len(), num_threads);
         // // println!("    thread {} graph_disconnected: {}", thread_name, graph_disconnected);
-        assert!(network.is_some() || !graph_disconnected);
+        assert!(network.is_some() || load_error.is_some() || !graph_disconnected);
 
         let mut selector = Selector::new();
 
@@ -82,11 +440,9 @@ pub fn executor_main(
         }
 
         // register all tensor receivers in the selector
-        match network {
-            Some(_) => {
-                selector = selector.recv(&tensor_receiver, |res| Message::JobTensor(res));
-            }
-            None => (),
+        // without a network, requests are only taken to answer them with the load error
+        if network.is_some() || load_error.is_some() {
+            selector = selector.recv(&tensor_receiver, |res| Message::JobTensor(res));
         }
         
         let now_end = SystemTime::now();
@@ -99,9 +455,23 @@ pub fn executor_main(
     // println!("RECV SIZE {} NUM SENDERS {} RECV {}", tensor_receiver.len(), tensor_receiver.sender_count(), tensor_receiver.receiver_count());
         match message {
             Message::StopServer() => break,
-            Message::NewNetwork(Ok(graph)) => {
+            Message::NewNetwork(Ok(mut update)) => {
                 // // println!("    NEW NET!");
-                handle_new_graph(&mut network, Some(graph), &thread_name);
+                let net_hash = update.net_hash.take();
+                load_error = swap_network(
+                    &mut network,
+                    &mut net_generation,
+                    update,
+                    &mut input_buffer,
+                    &mut pending,
+                    encoder,
+                    config,
+                    &thread_name,
+                )
+                .err();
+                if load_error.is_none() {
+                    *loaded_net_hash.write().unwrap() = net_hash;
+                }
             }
             Message::JobTensor(job) => {
                 // println!("EXEC ID {} CHANNEL_LEN {}", thread_name, tensor_receiver.len());
@@ -113,13 +483,23 @@ pub fn executor_main(
                 // println!("{} {} {} waiting_for_job", epoch_seconds_start_job, epoch_seconds_end, thread_name);
 
                 let job = job.expect("JobTensor should be available");
+                if let Some(e) = &load_error {
+                    let _ = job.resender.send(EvalResponse {
+                        id: job.id,
+                        generation: 0,
+                        result: Err(EvalError::NoNetwork(e.clone())),
+                    });
+                    continue;
+                }
 
-                input_vec.push_back(job.job);
-                output_senders.push_back(job.resender);
-                id_vec.push_back(job.id);
+                begin_event_with_color("write_input", CL_RED);
+                let (input, job) = PendingEval::new(job);
+                input_buffer.push(&input);
+                end_event();
+                pending.push(job);
                 // evaluate batches
                 waiting_job = Instant::now();
-                while input_vec.len() >= max_batch_size {
+                if input_buffer.is_full() {
                     let now_end = SystemTime::now();
                     let since_epoch = now_end
                         .duration_since(UNIX_EPOCH)
@@ -134,9 +514,8 @@ pub fn executor_main(
                     let elapsed = waiting_batch.elapsed().as_nanos() as f32 / 1e6;
                     // println!("loop {} time taken for buffer to fill: {}ms", debug_counter, elapsed);
                     let sw_tensor_prep = Instant::now();
-                    let batch_size = min(max_batch_size, input_vec.len());
-                    let i_v = input_vec.make_contiguous();
-                    let input_tensors = Tensor::cat(&i_v[..batch_size], 0);
+                    let batch_size = input_buffer.len();
+                    let input_tensors = input_buffer.batch();
                     let elapsed = sw_tensor_prep.elapsed().as_nanos() as f32 / 1e6;
 
                     // println!("loop {} prepping tensors: {}ms", debug_counter, elapsed);
@@ -150,8 +529,7 @@ pub fn executor_main(
                         .expect("Time went backwards");
                     let epoch_seconds_start_evals = since_epoch_evals.as_nanos();
                     begin_event_with_color("eval", CL_BLUE);
-                    let (board_eval, policy) =
-                        eval_state(input_tensors, network).expect("Evaluation failed");
+                    let outputs = run_batch(network, input_tensors, &pending);
                     end_event();
                     let now_end_evals = SystemTime::now();
                     let since_epoch_evals = now_end_evals
@@ -179,21 +557,7 @@ pub fn executor_main(
                         .expect("Time went backwards");
                     let epoch_seconds_start_packing = since_epoch_packing.as_nanos();
                     begin_event_with_color("packing", CL_RED);
-                    for i in 0..batch_size {
-                        let sender = output_senders
-                            .pop_front()
-                            .expect("There should be a sender for each job");
-                        let id = id_vec
-                            .pop_front()
-                            .expect("There should be an ID for each job");
-                        let result = (board_eval.get(i as i64), policy.get(i as i64));
-                        // // println!("            thread {}, SENT! {:?}", i, &result);
-                        let return_pack = ReturnPacket { packet: result, id };
-                        sender
-                            .send(ReturnMessage::ReturnMessage(Ok(return_pack)))
-                            .expect("Should be able to send the result");
-                        // let _ = sender.send(ReturnMessage::ReturnMessage(Ok(return_pack)));
-                    }
+                    answer_batch(&mut pending, outputs, net_generation, &thread_name);
                     end_event();
                     let now_end_packing = SystemTime::now();
                     let since_epoch_packing = now_end_packing
@@ -208,7 +572,7 @@ pub fn executor_main(
 
                     let packing_elapsed = packing_time.elapsed().as_nanos() as f32 / 1e6;
                     // println!("loop {} packing time {}ms", debug_counter, packing_elapsed);
-                    drop(input_vec.drain(0..batch_size));
+                    input_buffer.clear();
                     waiting_batch = Instant::now();
                     now_start = SystemTime::now();
                     let since_epoch = now_start
@@ -221,7 +585,7 @@ pub fn executor_main(
             Message::NewNetwork(Err(RecvError::Disconnected)) => {
                 // // println!("DISCONNECTED NET!");
                 graph_disconnected = true;
-                if network.is_none() && input_vec.is_empty() {
+                if network.is_none() && pending.is_empty() {
                     break; // exit if no network and no ongoing jobs
                 }
             }
@@ -235,9 +599,29 @@ pub fn executor_main(
 
 pub fn executor_static(
     net_path: String,
-    tensor_receiver: Receiver<Packet>, // receive tensors from mcts
+    tensor_receiver: Receiver<EvalRequest>, // receive tensors from mcts
     ctrl_receiver: Receiver<Message>,  // receive control messages
     num_threads: usize,
+    encoder: InputEncoder, // has to match the encoder of the searches sending tensors
+) {
+    executor_static_with_config(
+        net_path,
+        tensor_receiver,
+        ctrl_receiver,
+        num_threads,
+        encoder,
+        NetConfig::default(),
+    )
+}
+
+/// `executor_static` with the network loaded according to `config`
+pub fn executor_static_with_config(
+    net_path: String,
+    tensor_receiver: Receiver<EvalRequest>, // receive tensors from mcts
+    ctrl_receiver: Receiver<Message>,  // receive control messages
+    num_threads: usize,
+    encoder: InputEncoder, // has to match the encoder of the searches sending tensors
+    config: NetConfig,
 ) {
     let max_batch_size = min(1024, num_threads);
     let mut network: Option<Net> = None;
@@ -245,12 +629,18 @@ pub fn executor_static(
         .name()
         .unwrap_or("unnamed-executor")
         .to_owned();
-    let mut input_vec: VecDeque<Tensor> = VecDeque::new();
+    let mut input_buffer = BatchBuffer::new(max_batch_size, encoder, config.device.device());
     let mut debug_counter = 0;
-    let mut output_senders: VecDeque<Sender<ReturnMessage>> = VecDeque::new();
-    let mut id_vec: VecDeque<String> = VecDeque::new();
+    let mut pending: Vec<PendingEval> = Vec::new();
 
-    handle_new_graph(&mut network, Some(net_path), thread_name.as_str());
+    let load_error = handle_new_graph(
+        &mut network,
+        Some(net_path),
+        encoder,
+        config,
+        thread_name.as_str(),
+    )
+    .err();
 
     loop {
         let sw = Instant::now();
@@ -271,36 +661,32 @@ pub fn executor_static(
             }
             Message::JobTensor(job) => {
                 let job = job.expect("JobTensor should be available");
-                let network = network.as_mut().expect("Network should be available");
+                let network = match (&network, &load_error) {
+                    (Some(network), _) => network,
+                    (None, e) => {
+                        let e = e.clone().unwrap_or_default();
+                        let _ = job.resender.send(EvalResponse {
+                            id: job.id,
+                            generation: 0,
+                            result: Err(EvalError::NoNetwork(e)),
+                        });
+                        continue;
+                    }
+                };
 
-                input_vec.push_back(job.job);
-                output_senders.push_back(job.resender);
-                id_vec.push_back(job.id);
+                let (input, job) = PendingEval::new(job);
+                input_buffer.push(&input);
+                pending.push(job);
 
-                while input_vec.len() >= max_batch_size {
-                    let batch_size = min(max_batch_size, input_vec.len());
-                    let i_v = input_vec.make_contiguous();
-                    let input_tensors = Tensor::cat(&i_v[..batch_size], 0);
+                if input_buffer.is_full() {
+                    let input_tensors = input_buffer.batch();
 
                     let sw_inference = Instant::now();
-                    let (board_eval, policy) =
-                        eval_state(input_tensors, network).expect("Evaluation failed");
+                    let outputs = run_batch(network, input_tensors, &pending);
                     let elapsed = sw_inference.elapsed().as_nanos() as f32 / 1e9;
 
-                    for i in 0..batch_size {
-                        let sender = output_senders
-                            .pop_front()
-                            .expect("There should be a sender for each job");
-                        let id = id_vec
-                            .pop_front()
-                            .expect("There should be an ID for each job");
-                        let result = (board_eval.get(i as i64), policy.get(i as i64));
-                        let return_pack = ReturnPacket { packet: result, id };
-                        sender
-                            .send(ReturnMessage::ReturnMessage(Ok(return_pack)))
-                            .expect("Should be able to send the result");
-                    }
-                    drop(input_vec.drain(0..batch_size));
+                    answer_batch(&mut pending, outputs, 0, &thread_name);
+                    input_buffer.clear();
                 }
             }
         }
@@ -309,3 +695,252 @@ pub fn executor_static(
     }
     // Return the senders to avoid them being dropped and disconnected
 }
+
+/// a network of an `ExecutorPool` before it is loaded
+struct PoolNetwork {
+    id: String,
+    path: String,
+    encoder: InputEncoder,
+    receiver: Receiver<EvalRequest>,
+}
+
+/// the network of a pool queue together with its input batch
+pub(crate) trait PoolBackend: Send {
+    fn len(&self) -> usize;
+    fn is_full(&self) -> bool;
+    fn push(&mut self, input: &EncodedInput);
+    /// evaluates and empties the batch, `legal_indices` belong to the queued positions in order
+    fn run(&mut self, legal_indices: &[&[usize]]) -> Result<Vec<NetOutput>, EvalError>;
+}
+
+/// a loaded network with its reusable input buffer
+struct LoadedNet {
+    network: Net,
+    input_buffer: BatchBuffer,
+}
+
+impl PoolBackend for LoadedNet {
+    fn len(&self) -> usize {
+        self.input_buffer.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.input_buffer.is_full()
+    }
+
+    fn push(&mut self, input: &EncodedInput) {
+        self.input_buffer.push(input);
+    }
+
+    fn run(&mut self, legal_indices: &[&[usize]]) -> Result<Vec<NetOutput>, EvalError> {
+        let outputs = evaluate_batch(&self.network, self.input_buffer.batch(), legal_indices);
+        self.input_buffer.clear();
+        outputs
+    }
+}
+
+/// the requests of one network of the pool
+pub(crate) struct NetQueue {
+    receiver: Receiver<EvalRequest>,
+    backend: Result<Box<dyn PoolBackend>, String>, // the load error if the network did not load
+    pending: Vec<PendingEval>,
+    open: bool, // false once every sender for this network is dropped
+}
+
+impl NetQueue {
+    pub(crate) fn new(
+        receiver: Receiver<EvalRequest>,
+        backend: Result<Box<dyn PoolBackend>, String>,
+    ) -> Self {
+        Self {
+            receiver,
+            backend,
+            pending: Vec::new(),
+            open: true,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.backend.as_ref().map_or(0, |backend| backend.len())
+    }
+
+    fn is_full(&self) -> bool {
+        self.backend.as_ref().is_ok_and(|backend| backend.is_full())
+    }
+
+    fn push(&mut self, job: EvalRequest) {
+        let backend = match &mut self.backend {
+            Ok(backend) => backend,
+            Err(e) => {
+                let _ = job.resender.send(EvalResponse {
+                    id: job.id,
+                    generation: 0,
+                    result: Err(EvalError::NoNetwork(e.clone())),
+                });
+                return;
+            }
+        };
+        let (input, job) = PendingEval::new(job);
+        backend.push(&input);
+        self.pending.push(job);
+    }
+
+    fn run(&mut self, thread_name: &str) {
+        let backend = self
+            .backend
+            .as_mut()
+            .expect("only requests for loaded networks are queued");
+        let legal_indices: Vec<&[usize]> =
+            self.pending.iter().map(|p| &p.legal_indices[..]).collect();
+        let outputs = backend.run(&legal_indices);
+        answer_batch(&mut self.pending, outputs, 0, thread_name);
+    }
+}
+
+/// the batch to run next given the size of every batch, the first non-empty one in turn order starting at `next`
+///
+/// a full batch does not jump the queue, otherwise a busy network could keep a partial batch of another
+/// one waiting for as long as it has requests
+pub(crate) fn pick_batch(batches: &[usize], next: usize) -> Option<usize> {
+    (0..batches.len())
+        .map(|i| (next + i) % batches.len())
+        .find(|&i| batches[i] > 0)
+}
+
+enum PoolEvent {
+    Job(usize, Result<EvalRequest, RecvError>),
+    Stop,
+}
+
+/// serves several networks on one device from a single thread, for matches and gauntlets
+///
+/// every network has its own request channel and batch. the networks with queued requests take
+/// turns, so a busy network can not starve the others. partial batches are run too, searches can
+/// switch networks (like the two sides of a match) so batches are not guaranteed to fill up
+pub struct ExecutorPool {
+    networks: Vec<PoolNetwork>,
+    max_batch_size: usize,
+    config: NetConfig,
+}
+
+impl ExecutorPool {
+    pub fn new(max_batch_size: usize, config: NetConfig) -> Self {
+        Self {
+            networks: Vec::new(),
+            max_batch_size: min(1024, max_batch_size),
+            config,
+        }
+    }
+
+    /// adds the network at `path` as `id`, searches that should use it send their requests to the returned sender
+    pub fn add_network(
+        &mut self,
+        id: &str,
+        path: &str,
+        encoder: InputEncoder,
+    ) -> Sender<EvalRequest> {
+        assert!(
+            self.networks.iter().all(|network| network.id != id),
+            "network id {} is used twice",
+            id
+        );
+        let (sender, receiver) = flume::bounded(self.max_batch_size);
+        self.networks.push(PoolNetwork {
+            id: id.to_string(),
+            path: path.to_string(),
+            encoder,
+            receiver,
+        });
+        sender
+    }
+
+    /// loads the networks and serves requests until a control message arrives or every sender is dropped
+    ///
+    /// a network that fails to load answers its requests with `EvalError::NoNetwork`, the others keep working
+    pub fn run(self, ctrl_receiver: Receiver<Message>) {
+        let ExecutorPool {
+            networks,
+            max_batch_size,
+            config,
+        } = self;
+        let thread_name = std::thread::current()
+            .name()
+            .unwrap_or("unnamed-executor")
+            .to_owned();
+        let device = config.device.device();
+        let queues: Vec<NetQueue> = networks
+            .into_iter()
+            .map(|network| {
+                let mut net = None;
+                let backend = handle_new_graph(
+                    &mut net,
+                    Some(network.path),
+                    network.encoder,
+                    config,
+                    &thread_name,
+                )
+                .map(|()| {
+                    Box::new(LoadedNet {
+                        network: net.expect("the network was loaded"),
+                        input_buffer: BatchBuffer::new(max_batch_size, network.encoder, device),
+                    }) as Box<dyn PoolBackend>
+                })
+                .map_err(|e| format!("network {}: {}", network.id, e));
+                NetQueue::new(network.receiver, backend)
+            })
+            .collect();
+        serve_pool(queues, ctrl_receiver, &thread_name);
+    }
+}
+
+/// the request loop of `ExecutorPool::run`
+pub(crate) fn serve_pool(
+    mut queues: Vec<NetQueue>,
+    ctrl_receiver: Receiver<Message>,
+    thread_name: &str,
+) {
+    let count = queues.len();
+    let mut next = 0; // the network whose turn it is
+
+    loop {
+        match ctrl_receiver.try_recv() {
+            Err(TryRecvError::Empty) => {}
+            _ => break,
+        }
+
+        // take the waiting requests, a channel is only left non-empty when its batch is full
+        for queue in queues.iter_mut().filter(|queue| queue.open) {
+            while !queue.is_full() {
+                match queue.receiver.try_recv() {
+                    Ok(job) => queue.push(job),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        queue.open = false;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let batches: Vec<usize> = queues.iter().map(|queue| queue.len()).collect();
+        if let Some(i) = pick_batch(&batches, next) {
+            queues[i].run(thread_name);
+            next = (i + 1) % count;
+            continue;
+        }
+
+        if queues.iter().all(|queue| !queue.open) {
+            break; // nothing queued and nobody left to send requests
+        }
+        let mut selector = Selector::new();
+        for (i, queue) in queues.iter().enumerate().filter(|(_, queue)| queue.open) {
+            selector = selector.recv(&queue.receiver, move |job| PoolEvent::Job(i, job));
+        }
+        selector = selector.recv(&ctrl_receiver, |_| PoolEvent::Stop);
+        match selector.wait() {
+            PoolEvent::Stop => break,
+            PoolEvent::Job(i, Ok(job)) => queues[i].push(job),
+            PoolEvent::Job(i, Err(RecvError::Disconnected)) => queues[i].open = false,
+        }
+    }
+}
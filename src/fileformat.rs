@@ -1,45 +1,69 @@
 use bytemuck::cast_slice;
-use cozy_chess::{Color, GameStatus, Move};
-use serde::Serialize;
+use cozy_chess::{Color, GameStatus};
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
 use std::{
     cmp::{max, min},
-    fs::File,
-    io::{self, BufWriter, Seek, Write},
+    fs::{self, File, TryLockError},
+    io::{self, BufReader, BufWriter, Seek, Write},
+    ops::Range,
     path::{Path, PathBuf},
 };
 
 use crate::{
     boardmanager::BoardStack,
     dataformat::{Position, Simulation},
-    decoder::{board_data, convert_board},
-    mvs::get_contents,
+    encoder::{pack_bools, InputEncoder},
+    mapper::{ChessStdMapper, MoveMapper},
+    mcts_trainer::Wdl,
+    provenance::{net_id, Provenance},
 };
 
-#[derive(Serialize)]
-struct MetaData<'a> {
-    game: &'a str,
-
-    input_bool_shape: &'a [usize],
-    input_scalar_count: usize,
-    policy_shape: &'a [usize],
-
-    game_count: usize,
-    position_count: usize,
-    includes_terminal_positions: bool,
-    includes_game_start_indices: bool,
-
-    max_game_length: i32,
-    min_game_length: i32,
-    root_wdl: [f32; 3],
-    hit_move_limit: f32,
-
-    scalar_names: &'static [&'static str],
+/// contents of the .json file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MetaData {
+    pub game: String,
+
+    pub input_bool_shape: Vec<usize>,
+    pub input_scalar_count: usize,
+    pub policy_shape: Vec<usize>,
+    #[serde(default)] // files from before encoders were versioned are v1
+    pub input_encoding: InputEncoder,
+
+    pub game_count: usize,
+    pub position_count: usize,
+    pub includes_terminal_positions: bool,
+    pub includes_game_start_indices: bool,
+
+    pub max_game_length: i32,
+    pub min_game_length: i32,
+    pub root_wdl: [f32; 3],
+    pub hit_move_limit: f32,
+
+    // averages of the per-position search metrics, None if no position had a valid value
+    #[serde(default)]
+    pub mean_kdl_policy: Option<f32>,
+    #[serde(default)]
+    pub mean_value_surprise_final: Option<f32>,
+    #[serde(default)]
+    pub mean_value_surprise_zero: Option<f32>,
+    #[serde(default)]
+    pub mean_policy_entropy: Option<f32>,
+    #[serde(default)]
+    pub mean_visited_mv_count: Option<f32>,
+
+    // network, settings, seed and start position of each game, indexed by game_id
+    #[serde(default)]
+    pub game_provenance: Vec<Option<Provenance>>,
+
+    pub scalar_names: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct BinaryOutput {
     game: String,
     path: PathBuf,
+    encoder: InputEncoder,
 
     bin_write: BufWriter<File>,
     off_write: BufWriter<File>,
@@ -54,32 +78,128 @@ pub struct BinaryOutput {
     total_root_wdl: [u64; 3],
     hit_move_limit_count: u64,
 
+    total_kdl_policy: MetricTotal,
+    total_value_surprise_final: MetricTotal,
+    total_value_surprise_zero: MetricTotal,
+    total_policy_entropy: MetricTotal,
+    total_visited_mv_count: MetricTotal,
+
+    game_provenance: Vec<Option<Provenance>>,
+
     next_offset: u64,
     game_start_indices: Vec<u64>,
 
+    // created and locked at the first checkpoint, the lock keeps `recover` away while we are alive
+    progress_write: Option<File>,
+    checkpointed_games: usize,
+
     finished: bool,
 }
 
+#[derive(Debug, Clone)]
+pub struct Scalars {
+    pub game_id: usize,
+    pub pos_index: usize,
+    pub game_length: usize,
+    pub zero_visits: u64,
+    pub is_full_search: bool,
+    pub is_final_position: bool,
+    pub is_terminal: bool,
+    pub hit_move_limit: bool,
+    pub available_mv_count: usize,
+    pub played_mv: isize,
+    pub kdl_policy: f32,
+    pub final_values: f32, // z
+    pub final_wdl: [f32; 3],
+    pub final_moves_left: f32,
+    pub zero_values: f32, // q
+    pub zero_wdl: [f32; 3],
+    pub zero_moves_left: f32,
+    pub net_values: f32, // v
+    pub net_wdl: [f32; 3],
+    pub net_moves_left: f32,
+    pub value_surprise_final: f32, // |z - v|
+    pub value_surprise_zero: f32,  // |q - v|
+    pub policy_entropy: f32,       // entropy of the search policy
+    pub visited_mv_count: usize,
+    pub net_id: f32, // first 24 bits of the network hash, NaN if it is not known
+}
+
+/// running sum of a metric, skipping NaN and infinite values
+#[derive(Debug, Default, Clone, Copy)]
+struct MetricTotal {
+    sum: f64,
+    count: u64,
+}
+
+impl MetricTotal {
+    fn add(&mut self, value: f32) {
+        if value.is_finite() {
+            self.sum += value as f64;
+            self.count += 1;
+        }
+    }
+
+    fn mean(&self) -> Option<f32> {
+        if self.count == 0 {
+            None
+        } else {
+            Some((self.sum / self.count as f64) as f32)
+        }
+    }
+}
+
+/// a line of the .progress file, appended by `BinaryOutput::checkpoint`
+///
+/// only the games since the previous checkpoint are listed, so a checkpoint costs the same at the
+/// end of a long run as at the start
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Progress {
+    bin_len: u64,
+    game_start_indices: Vec<u64>,
+    game_provenance: Vec<Option<Provenance>>,
+    meta: MetaData, // without the provenance of the games
+}
+
+/// a single position as it is stored in the .bin file
+#[derive(Debug, Clone)]
+pub struct StoredPosition {
+    pub scalars: Scalars,
+    pub board_bools: Vec<u8>, // packed, 8 bools per byte with the first bool in the lowest bit
+    pub board_scalars: Vec<f32>,
+    pub policy_indices: Vec<u32>,
+    pub policy_values: Vec<f32>,
+}
+
+/// all positions of a game, including the final position
+#[derive(Debug, Clone)]
+pub struct StoredGame {
+    pub positions: Vec<StoredPosition>,
+    pub provenance: Option<Provenance>,
+}
+
+/// reads the files written by `BinaryOutput`
 #[derive(Debug)]
-struct Scalars {
-    game_id: usize,
-    pos_index: usize,
-    game_length: usize,
-    zero_visits: u64,
-    is_full_search: bool,
-    is_final_position: bool,
-    is_terminal: bool,
-    hit_move_limit: bool,
-    available_mv_count: usize,
-    played_mv: isize,
-    kdl_policy: f32,
-    final_values: f32, // z
-    zero_values: f32,  // q
-    net_values: f32,   // v
+pub struct BinaryInput {
+    path: PathBuf,
+    meta: MetaData,
+    scalar_columns: Vec<Option<usize>>, // where each of `Scalars::NAMES` is in the file
+    bin: Mmap,
+    offsets: Vec<u64>,
+    game_start_indices: Vec<u64>,
 }
 
 impl BinaryOutput {
     pub fn new(path: impl AsRef<Path>, game: &str) -> io::Result<Self> {
+        Self::with_encoder(path, game, InputEncoder::default())
+    }
+
+    /// output that stores the inputs of `encoder`, which should match the network that played the games
+    pub fn with_encoder(
+        path: impl AsRef<Path>,
+        game: &str,
+        encoder: InputEncoder,
+    ) -> io::Result<Self> {
         let path = path.as_ref().to_path_buf();
         assert!(
             path.extension().is_none(),
@@ -93,6 +213,7 @@ impl BinaryOutput {
 
         Ok(BinaryOutput {
             game: game.to_string(),
+            encoder,
 
             bin_write,
             off_write,
@@ -109,34 +230,26 @@ impl BinaryOutput {
             total_root_wdl: [0, 0, 0],
             hit_move_limit_count: 0,
 
+            total_kdl_policy: MetricTotal::default(),
+            total_value_surprise_final: MetricTotal::default(),
+            total_value_surprise_zero: MetricTotal::default(),
+            total_policy_entropy: MetricTotal::default(),
+            total_visited_mv_count: MetricTotal::default(),
+
+            game_provenance: vec![],
+
             next_offset: 0,
             game_start_indices: vec![],
 
+            progress_write: None,
+            checkpointed_games: 0,
+
             finished: false,
         })
     }
 
     pub fn append(&mut self, simulation: &Simulation) -> io::Result<()> {
-        let Simulation {
-            positions,
-            final_board,
-        } = simulation;
-
-        // collect metadata statistics
-        let game_id = self.game_count;
-        let game_length = positions.len();
-
-        self.game_start_indices.push(self.position_count as u64);
-
-        self.game_count += 1;
-        self.position_count += 1 + game_length;
-
-        self.max_game_length = Some(max(game_length as i32, self.max_game_length.unwrap_or(-1)));
-        self.min_game_length = Some(min(
-            game_length as i32,
-            self.min_game_length.unwrap_or(i32::MAX),
-        ));
-
+        let final_board = &simulation.final_board;
         // let outcome = final_board.outcome().unwrap_or(Outcome::Draw);
 
         let outcome: Option<Color> = match final_board.status() {
@@ -144,6 +257,27 @@ impl BinaryOutput {
             GameStatus::Won => Some(!final_board.board().side_to_move()),
             GameStatus::Ongoing => panic!("Game is still ongoing!"),
         };
+        self.append_with_outcome(simulation, outcome)
+    }
+
+    /// `append` for games that did not end on the board, for example by resignation or adjudication
+    pub fn append_with_outcome(
+        &mut self,
+        simulation: &Simulation,
+        outcome: Option<Color>,
+    ) -> io::Result<()> {
+        let Simulation {
+            positions,
+            final_board,
+            provenance,
+        } = simulation;
+
+        let game_length = positions.len();
+        let game_id = self.start_game(game_length, provenance, outcome);
+        let net_id = provenance
+            .as_ref()
+            .and_then(|p| p.net_hash.as_deref())
+            .map_or(f32::NAN, net_id);
 
         // write the positions
         for (pos_index, position) in positions.iter().enumerate() {
@@ -159,27 +293,18 @@ impl BinaryOutput {
             let (available_mv_count, policy_indices) = collect_policy_indices(board);
             assert_eq!(available_mv_count, zero_evaluation.policy.len());
             assert_eq!(available_mv_count, net_evaluation.policy.len());
-            let mut played_mv = played_mv;
-            if board.board().side_to_move() == Color::Black {
-                played_mv = Move {
-                    from: played_mv.from.flip_rank(),
-                    to: played_mv.to.flip_rank(),
-                    promotion: played_mv.promotion,
-                };
-            }
-            // get all idx from mvs.rs
-            let all_idx = get_contents();
-
-            let played_mv_index = all_idx.iter().position(|&x| x == played_mv).unwrap();
-
-            // let played_mv_index = self.mapper.move_to_index(board, played_mv);
-            // let kdl_policy = kdl_divergence(&zero_evaluation.policy, &net_evaluation.policy);
-            let kdl_policy = f32::NAN;
+            let played_mv_index = ChessStdMapper::get()
+                .move_to_index(board.board(), played_mv)
+                .unwrap();
+            // the net policy holds the raw priors, without the temperature and noise of the search
+            let kdl_policy = kdl_divergence(&zero_evaluation.policy, &net_evaluation.policy);
+            // number of positions until the end of the game, counting this one and the final position
             let moves_left = game_length + 1 - pos_index;
             let stored_policy = &zero_evaluation.policy;
+            let stm = board.board().side_to_move();
             let final_values = match outcome {
                 Some(outcome) => {
-                    if outcome == board.board().side_to_move() {
+                    if outcome == stm {
                         1.0
                     } else {
                         -1.0
@@ -187,6 +312,17 @@ impl BinaryOutput {
                 }
                 None => 0.0,
             };
+
+            // zero_v and net_v are from white's point of view, final_v from the side to move
+            let net_values_stm = match stm {
+                Color::White => net_evaluation.values,
+                Color::Black => -net_evaluation.values,
+            };
+            let value_surprise_final = (final_values - net_values_stm).abs();
+            let value_surprise_zero = (zero_evaluation.values - net_evaluation.values).abs();
+            let policy_entropy = entropy(stored_policy);
+            let visited_mv_count = stored_policy.iter().filter(|&&p| p > 0.0).count();
+
             let scalars = Scalars {
                 game_id,
                 pos_index,
@@ -200,10 +336,22 @@ impl BinaryOutput {
                 played_mv: played_mv_index as isize,
                 kdl_policy,
                 final_values,
+                final_wdl: value_to_wdl(final_values),
+                final_moves_left: moves_left as f32,
                 zero_values: zero_evaluation.values,
+                zero_wdl: wdl_to_stm(zero_evaluation.wdl, stm),
+                zero_moves_left: zero_evaluation.moves_left,
                 net_values: net_evaluation.values,
+                net_wdl: wdl_to_stm(net_evaluation.wdl, stm),
+                net_moves_left: net_evaluation.moves_left,
+                value_surprise_final,
+                value_surprise_zero,
+                policy_entropy,
+                visited_mv_count,
+                net_id,
             };
 
+            self.record_metrics(&scalars);
             self.append_position(board, &scalars, &policy_indices, stored_policy)?;
         }
         let final_values = match outcome {
@@ -229,9 +377,20 @@ impl BinaryOutput {
             played_mv: -1,
             kdl_policy: f32::NAN,
             final_values,
+            final_wdl: value_to_wdl(final_values),
+            final_moves_left: 1.0,
             zero_values: f32::NAN,
+            zero_wdl: [f32::NAN; 3],
+            zero_moves_left: f32::NAN,
             //TODO in theory we could ask the network, but this is only really meaningful for muzero
             net_values: f32::NAN,
+            net_wdl: [f32::NAN; 3],
+            net_moves_left: f32::NAN,
+            value_surprise_final: f32::NAN,
+            value_surprise_zero: f32::NAN,
+            policy_entropy: f32::NAN,
+            visited_mv_count: 0,
+            net_id,
         };
 
         self.append_position(&final_board, &scalars, &[], &[])?;
@@ -239,6 +398,85 @@ impl BinaryOutput {
         Ok(())
     }
 
+    /// copies a game read by `BinaryInput`, renumbering it to the next game id in this file
+    pub fn append_stored(&mut self, game: &StoredGame) -> io::Result<()> {
+        let bool_bytes = self.encoder.input_bool_shape().iter().product::<usize>() / 8;
+        if game
+            .positions
+            .iter()
+            .any(|p| p.board_bools.len() != bool_bytes)
+        {
+            return Err(invalid_data(format!(
+                "game was not stored with input encoding {}",
+                self.encoder
+            )));
+        }
+        let outcome = game.outcome()?;
+
+        let game_length = game.length();
+        let game_id = self.start_game(game_length, &game.provenance, outcome);
+
+        for position in &game.positions {
+            let mut scalars = position.scalars.clone();
+            scalars.game_id = game_id;
+
+            self.record_metrics(&scalars);
+            self.write_position(
+                &scalars,
+                &position.board_bools,
+                &position.board_scalars,
+                &position.policy_indices,
+                &position.policy_values,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// collects the metadata statistics of a new game and returns its game id
+    fn start_game(
+        &mut self,
+        game_length: usize,
+        provenance: &Option<Provenance>,
+        outcome: Option<Color>,
+    ) -> usize {
+        let game_id = self.game_count;
+
+        self.game_start_indices.push(self.position_count as u64);
+        self.game_provenance.push(provenance.clone());
+
+        self.game_count += 1;
+        self.position_count += 1 + game_length;
+
+        self.max_game_length = Some(max(game_length as i32, self.max_game_length.unwrap_or(-1)));
+        self.min_game_length = Some(min(
+            game_length as i32,
+            self.min_game_length.unwrap_or(i32::MAX),
+        ));
+
+        // root_wdl in the metadata is from white's point of view
+        match outcome {
+            Some(Color::White) => self.total_root_wdl[0] += 1,
+            None => self.total_root_wdl[1] += 1,
+            Some(Color::Black) => self.total_root_wdl[2] += 1,
+        }
+
+        game_id
+    }
+
+    fn record_metrics(&mut self, scalars: &Scalars) {
+        self.total_kdl_policy.add(scalars.kdl_policy);
+        self.total_value_surprise_final
+            .add(scalars.value_surprise_final);
+        self.total_value_surprise_zero
+            .add(scalars.value_surprise_zero);
+        self.total_policy_entropy.add(scalars.policy_entropy);
+        if !scalars.policy_entropy.is_nan() {
+            self.total_visited_mv_count
+                .add(scalars.visited_mv_count as f32);
+        }
+    }
+
     fn append_position(
         &mut self,
         board: &BoardStack,
@@ -248,7 +486,7 @@ impl BinaryOutput {
     ) -> io::Result<()> {
         // encode board
 
-        let (board_scalars, board_bools) = board_data(board);
+        let (board_scalars, board_bools) = self.encoder.board_data(board);
         // assert_eq!(self.mapper.input_bool_len(), board_bools.len());
         // assert_eq!(self.mapper.input_scalar_count(), board_scalars.len());
         // assert_eq!(
@@ -257,21 +495,25 @@ impl BinaryOutput {
         // );
 
         // converting bools into u8s (8 bools = 1 u8)
+        let byte_vec = pack_bools(&board_bools);
 
-        assert_eq!(board_bools.len() % 8, 0);
-
-        let mut byte_vec: Vec<u8> = Vec::new();
-
-        for i in 0..board_bools.len() / 8 {
-            let mut byte: u8 = 0;
-            for j in 0..8 {
-                let b = board_bools[i * 8 + j];
-                byte |= (b as u8) << j;
-                // byte[j] = b
-            }
-            byte_vec.push(byte);
-        }
+        self.write_position(
+            scalars,
+            &byte_vec,
+            &board_scalars,
+            policy_indices,
+            policy_values,
+        )
+    }
 
+    fn write_position(
+        &mut self,
+        scalars: &Scalars,
+        board_bools: &[u8],
+        board_scalars: &[f32],
+        policy_indices: &[u32],
+        policy_values: &[f32],
+    ) -> io::Result<()> {
         // check that everything makes sense
         let policy_len = policy_indices.len();
         assert_eq!(policy_len, policy_values.len());
@@ -291,8 +533,8 @@ impl BinaryOutput {
         let scalars = scalars.to_vec();
         let data_to_write: &[&[u8]] = &[
             cast_slice(&scalars),
-            &byte_vec,
-            cast_slice(&board_scalars),
+            board_bools,
+            cast_slice(board_scalars),
             cast_slice(policy_indices),
             cast_slice(policy_values),
         ];
@@ -310,23 +552,7 @@ impl BinaryOutput {
         }
         self.finished = true;
 
-        let meta = MetaData {
-            game: &self.game,
-            scalar_names: Scalars::NAMES,
-            input_bool_shape: &[13, 8, 8], // pieces + EP
-            input_scalar_count: 8,         // move turn, counters, castling
-            policy_shape: &[1880 as usize],
-            game_count: self.game_count,
-            position_count: self.position_count,
-            includes_terminal_positions: true,
-            includes_game_start_indices: true,
-            max_game_length: self.max_game_length.unwrap_or(-1),
-            min_game_length: self.min_game_length.unwrap_or(-1),
-            // root_wdl: (self.total_root_wdl.cast::<f32>() / self.game_count as f32).to_slice(),
-            root_wdl: [0.0, 0.0, 0.0],
-            hit_move_limit: self.hit_move_limit_count as f32 / self.game_count as f32,
-        };
-
+        let meta = self.meta();
         serde_json::to_writer_pretty(&mut self.json_tmp_write, &meta)?;
         self.off_write
             .write_all(cast_slice(&self.game_start_indices))?;
@@ -339,15 +565,445 @@ impl BinaryOutput {
         let path_json = self.path.with_extension("json");
         std::fs::rename(path_json_tmp, path_json)?;
 
+        // the finished file no longer needs to be recovered
+        self.progress_write = None;
+        remove_if_exists(&self.path.with_extension("progress"))?;
+
+        Ok(())
+    }
+
+    /// makes the games written so far survive a crash, call this at game boundaries
+    ///
+    /// the data is synced to disk and a line is appended to the .progress file that records how to
+    /// finish the output, see `recover`
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        assert!(!self.finished, "This output is already finished");
+        if self.game_count == self.checkpointed_games {
+            return Ok(());
+        }
+
+        self.bin_write.flush()?;
+        self.off_write.flush()?;
+        self.bin_write.get_ref().sync_data()?;
+        self.off_write.get_ref().sync_data()?;
+
+        let from = self.checkpointed_games;
+        let progress = Progress {
+            bin_len: self.next_offset,
+            game_start_indices: self.game_start_indices[from..].to_vec(),
+            game_provenance: self.game_provenance[from..].to_vec(),
+            meta: self.meta_with_provenance(Vec::new()),
+        };
+        let mut line = serde_json::to_vec(&progress)?;
+        line.push(b'\n');
+
+        let progress_write = match &mut self.progress_write {
+            Some(file) => file,
+            None => {
+                let file = File::options()
+                    .create(true)
+                    .write(true)
+                    .truncate(false)
+                    .open(self.path.with_extension("progress"))?;
+                file.try_lock().map_err(io::Error::from)?;
+                // left behind by an earlier output at the same path
+                file.set_len(0)?;
+                self.progress_write.insert(file)
+            }
+        };
+        progress_write.write_all(&line)?;
+        progress_write.sync_data()?;
+        self.checkpointed_games = self.game_count;
+
         Ok(())
     }
 
+    /// finishes an output that was not finished because the process died, keeping the games up to the last checkpoint
+    ///
+    /// returns the number of games kept, the .progress file is removed once the output is finished. fails
+    /// with `io::ErrorKind::WouldBlock` without touching the files if the writer is still alive
+    pub fn recover(path: impl AsRef<Path>) -> io::Result<usize> {
+        let path = path.as_ref();
+        let progress_file = File::options()
+            .read(true)
+            .write(true)
+            .open(path.with_extension("progress"))?;
+        // the writer holds the lock until it finishes or dies
+        progress_file.try_lock().map_err(io::Error::from)?;
+
+        let mut game_start_indices = Vec::new();
+        let mut game_provenance = Vec::new();
+        let mut last = None;
+        for line in io::BufRead::lines(BufReader::new(&progress_file)) {
+            // only the last line can be incomplete, cut off by the crash
+            let Ok(progress) = serde_json::from_str::<Progress>(&line?) else {
+                break;
+            };
+            game_start_indices.extend_from_slice(&progress.game_start_indices);
+            game_provenance.extend(progress.game_provenance);
+            last = Some((progress.bin_len, progress.meta));
+        }
+        let Some((bin_len, mut meta)) = last else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the .progress file has no complete checkpoint",
+            ));
+        };
+        meta.game_provenance = game_provenance;
+        let meta = &meta;
+
+        // drop everything written after the checkpoint
+        let bin = File::options()
+            .write(true)
+            .open(path.with_extension("bin"))?;
+        bin.set_len(bin_len)?;
+        bin.sync_data()?;
+        let off = File::options()
+            .write(true)
+            .open(path.with_extension("off"))?;
+        off.set_len(8 * meta.position_count as u64)?;
+        let mut off = BufWriter::new(off);
+        off.seek(io::SeekFrom::End(0))?;
+        off.write_all(cast_slice(&game_start_indices))?;
+        off.flush()?;
+        off.get_ref().sync_data()?;
+
+        let path_json_tmp = path.with_extension("json.tmp");
+        let mut json_write = BufWriter::new(File::create(&path_json_tmp)?);
+        serde_json::to_writer_pretty(&mut json_write, meta)?;
+        json_write.flush()?;
+        json_write.get_ref().sync_data()?;
+        fs::rename(path_json_tmp, path.with_extension("json"))?;
+
+        drop(progress_file);
+        fs::remove_file(path.with_extension("progress"))?;
+        Ok(meta.game_count)
+    }
+
+    /// true if a live writer holds the lock on the .progress file of the output at `path`, which it
+    /// takes at its first checkpoint
+    pub fn is_being_written(path: impl AsRef<Path>) -> bool {
+        match File::open(path.as_ref().with_extension("progress")) {
+            Ok(file) => matches!(file.try_lock(), Err(TryLockError::WouldBlock)),
+            Err(_) => false,
+        }
+    }
+
+    fn meta(&self) -> MetaData {
+        self.meta_with_provenance(self.game_provenance.clone())
+    }
+
+    fn meta_with_provenance(&self, game_provenance: Vec<Option<Provenance>>) -> MetaData {
+        MetaData {
+            game: self.game.clone(),
+            scalar_names: Scalars::NAMES.iter().map(|s| s.to_string()).collect(),
+            input_bool_shape: self.encoder.input_bool_shape(),
+            input_scalar_count: self.encoder.input_scalar_count(),
+            policy_shape: vec![1880],
+            input_encoding: self.encoder,
+            game_count: self.game_count,
+            position_count: self.position_count,
+            includes_terminal_positions: true,
+            includes_game_start_indices: true,
+            max_game_length: self.max_game_length.unwrap_or(-1),
+            min_game_length: self.min_game_length.unwrap_or(-1),
+            root_wdl: self
+                .total_root_wdl
+                .map(|count| count as f32 / self.game_count as f32),
+            hit_move_limit: self.hit_move_limit_count as f32 / self.game_count as f32,
+            mean_kdl_policy: self.total_kdl_policy.mean(),
+            mean_value_surprise_final: self.total_value_surprise_final.mean(),
+            mean_value_surprise_zero: self.total_value_surprise_zero.mean(),
+            mean_policy_entropy: self.total_policy_entropy.mean(),
+            mean_visited_mv_count: self.total_visited_mv_count.mean(),
+            game_provenance,
+        }
+    }
+
     pub fn game_count(&self) -> usize {
         self.game_count
     }
 }
 
-fn collect_policy_indices(board: &BoardStack) -> (usize, Vec<u32>) {
+/// WDL targets are stored from the point of view of the side to move, like the network output
+fn wdl_to_stm(wdl: Wdl, stm: Color) -> [f32; 3] {
+    match stm {
+        Color::White => wdl.to_array(),
+        Color::Black => wdl.flip().to_array(),
+    }
+}
+
+fn value_to_wdl(value: f32) -> [f32; 3] {
+    [
+        (value == 1.0) as u8 as f32,
+        (value == 0.0) as u8 as f32,
+        (value == -1.0) as u8 as f32,
+    ]
+}
+
+/// KL divergence of the network policy from the search policy, NaN if either policy is NaN
+fn kdl_divergence(target: &[f32], actual: &[f32]) -> f32 {
+    target
+        .iter()
+        .zip(actual)
+        .filter(|(&t, _)| t != 0.0)
+        .map(|(&t, &a)| t * (t / a).ln())
+        .sum()
+}
+
+fn entropy(policy: &[f32]) -> f32 {
+    -policy
+        .iter()
+        .filter(|&&p| p != 0.0)
+        .map(|&p| p * p.ln())
+        .sum::<f32>()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn out_of_range(what: &str, index: usize, count: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} {} is out of range, the file has {}", what, index, count),
+    )
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+impl BinaryInput {
+    /// opens `path` without extension, like `BinaryOutput::new`
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let json = BufReader::new(File::open(path.with_extension("json"))?);
+        let meta: MetaData = serde_json::from_reader(json)?;
+        // scalars are looked up by name like the python loader does, older files have fewer of them
+        let scalar_columns: Vec<Option<usize>> = Scalars::NAMES
+            .iter()
+            .map(|name| meta.scalar_names.iter().position(|n| n == name))
+            .collect();
+        for (name, column) in Scalars::NAMES.iter().zip(&scalar_columns) {
+            if column.is_none() && Scalars::REQUIRED.contains(name) {
+                return Err(invalid_data(format!("missing scalar {}", name)));
+            }
+        }
+
+        let bin_file = File::open(path.with_extension("bin"))?;
+        // safety: data files are written once by BinaryOutput and not modified while being read
+        let bin = unsafe { Mmap::map(&bin_file)? };
+
+        let off = fs::read(path.with_extension("off"))?;
+        let game_start_count = if meta.includes_game_start_indices {
+            meta.game_count
+        } else {
+            0
+        };
+        if off.len() != 8 * (meta.position_count + game_start_count) {
+            return Err(invalid_data(format!(
+                "expected {} offsets and {} game starts, .off file has {} bytes",
+                meta.position_count,
+                game_start_count,
+                off.len()
+            )));
+        }
+        let mut offsets: Vec<u64> = off
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        let game_start_indices = offsets.split_off(meta.position_count);
+
+        Ok(BinaryInput {
+            path,
+            meta,
+            scalar_columns,
+            bin,
+            offsets,
+            game_start_indices,
+        })
+    }
+
+    pub fn meta(&self) -> &MetaData {
+        &self.meta
+    }
+
+    /// the path the input was opened with, without extension
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn position_count(&self) -> usize {
+        self.meta.position_count
+    }
+
+    pub fn game_count(&self) -> usize {
+        self.meta.game_count
+    }
+
+    /// position indices of a game, including its final position
+    ///
+    /// files written without game start indices can only be read position by position
+    pub fn game_range(&self, game: usize) -> io::Result<Range<usize>> {
+        if !self.meta.includes_game_start_indices {
+            return Err(invalid_data(
+                "file does not include game start indices".to_string(),
+            ));
+        }
+        let Some(&start) = self.game_start_indices.get(game) else {
+            return Err(out_of_range("game", game, self.game_count()));
+        };
+        let end = match self.game_start_indices.get(game + 1) {
+            Some(&next) => next as usize,
+            None => self.meta.position_count,
+        };
+        Ok(start as usize..end)
+    }
+
+    pub fn game(&self, game: usize) -> io::Result<StoredGame> {
+        if game >= self.game_count() {
+            return Err(out_of_range("game", game, self.game_count()));
+        }
+        let positions = self
+            .game_range(game)?
+            .map(|i| self.position(i))
+            .collect::<io::Result<Vec<_>>>()?;
+        let provenance = self.meta.game_provenance.get(game).cloned().flatten();
+        Ok(StoredGame {
+            positions,
+            provenance,
+        })
+    }
+
+    pub fn position(&self, index: usize) -> io::Result<StoredPosition> {
+        let Some(&start) = self.offsets.get(index) else {
+            return Err(out_of_range("position", index, self.offsets.len()));
+        };
+        let start = start as usize;
+        let end = match self.offsets.get(index + 1) {
+            Some(&next) => next as usize,
+            None => self.bin.len(),
+        };
+        if start > end || end > self.bin.len() {
+            return Err(invalid_data(format!(
+                "position {} has invalid offsets {}..{}",
+                index, start, end
+            )));
+        }
+        let data = &self.bin[start..end];
+
+        let scalar_len = 4 * self.meta.scalar_names.len();
+        let bool_len = self
+            .meta
+            .input_bool_shape
+            .iter()
+            .product::<usize>()
+            .div_ceil(8);
+        let board_scalar_len = 4 * self.meta.input_scalar_count;
+        let header_len = scalar_len + bool_len + board_scalar_len;
+        if data.len() < header_len || !(data.len() - header_len).is_multiple_of(8) {
+            return Err(invalid_data(format!(
+                "position {} has unexpected size {}",
+                index,
+                data.len()
+            )));
+        }
+
+        let scalars = self.read_scalars(&read_f32s(&data[..scalar_len]));
+        let (bools, rest) = data[scalar_len..].split_at(bool_len);
+        let (board_scalars, policy) = rest.split_at(board_scalar_len);
+
+        let policy_len = policy.len() / 8;
+        if policy_len != scalars.available_mv_count {
+            return Err(invalid_data(format!(
+                "position {} has {} policy entries but {} available moves",
+                index, policy_len, scalars.available_mv_count
+            )));
+        }
+        let (policy_indices, policy_values) = policy.split_at(4 * policy_len);
+
+        Ok(StoredPosition {
+            scalars,
+            board_bools: bools.to_vec(),
+            board_scalars: read_f32s(board_scalars),
+            policy_indices: policy_indices
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+            policy_values: read_f32s(policy_values),
+        })
+    }
+
+    // scalars missing from the file are NaN, except for the final WDL which follows from the result
+    fn read_scalars(&self, values: &[f32]) -> Scalars {
+        let values: Vec<f32> = self
+            .scalar_columns
+            .iter()
+            .map(|column| column.map_or(f32::NAN, |i| values[i]))
+            .collect();
+        let mut scalars = Scalars::from_slice(&values);
+        let has_final_wdl = self.meta.scalar_names.iter().any(|n| n == "final_wdl_w");
+        if !has_final_wdl {
+            let v = scalars.final_values;
+            scalars.final_wdl = [v == 1.0, v == 0.0, v == -1.0].map(|x| x as u8 as f32);
+        }
+        scalars
+    }
+}
+
+impl StoredGame {
+    /// the winner of the game, None for a draw
+    pub fn outcome(&self) -> io::Result<Option<Color>> {
+        let final_position = match self.positions.last() {
+            Some(position) if position.scalars.is_final_position => position,
+            _ => {
+                return Err(invalid_data(
+                    "game does not end in a final position".to_string(),
+                ))
+            }
+        };
+
+        // final_v of the final position is from the side to move, board_scalars[1] is set when black is to move
+        let stm = if final_position.board_scalars[1] == 1.0 {
+            Color::Black
+        } else {
+            Color::White
+        };
+        Ok(match final_position.scalars.final_values {
+            1.0 => Some(stm),
+            -1.0 => Some(!stm),
+            _ => None,
+        })
+    }
+
+    /// number of moves played, not counting the final position
+    pub fn length(&self) -> usize {
+        self.positions.len().saturating_sub(1)
+    }
+}
+
+impl StoredPosition {
+    pub fn unpacked_board_bools(&self) -> Vec<bool> {
+        (0..self.board_bools.len() * 8)
+            .map(|i| (self.board_bools[i / 8] >> (i % 8)) & 1 == 1)
+            .collect()
+    }
+}
+
+/// number of legal moves and their policy indices, empty for finished games
+pub fn collect_policy_indices(board: &BoardStack) -> (usize, Vec<u32>) {
     match board.status() {
         GameStatus::Ongoing => {
             let mut policy_indices: Vec<u32> = vec![];
@@ -359,17 +1015,9 @@ fn collect_policy_indices(board: &BoardStack) -> (usize, Vec<u32>) {
                 false
             });
 
-            let mv_l = get_contents();
-            for m in move_list.clone() {
-                let mut fm = m;
-                if board.board().side_to_move() == Color::Black {
-                    fm = Move {
-                        from: m.from.flip_rank(),
-                        to: m.to.flip_rank(),
-                        promotion: m.promotion,
-                    };
-                }
-                policy_indices.push(mv_l.iter().position(|&x| x == fm).unwrap() as u32);
+            let mapper = ChessStdMapper::get();
+            for m in move_list {
+                policy_indices.push(mapper.move_to_index(board.board(), m).unwrap() as u32);
             }
 
             (policy_indices.len(), policy_indices)
@@ -383,7 +1031,42 @@ fn assert_normalized_or_nan(x: f32) {
 }
 
 impl Scalars {
-    const NAMES: &'static [&'static str] = &[
+    pub const NAMES: &'static [&'static str] = &[
+        "game_id",
+        "pos_index",
+        "game_length",
+        "zero_visits",
+        "is_full_search",
+        "is_final_position",
+        "is_terminal",
+        "hit_move_limit",
+        "available_mv_count",
+        "played_mv",
+        "kdl_policy",
+        "final_v",
+        "final_wdl_w",
+        "final_wdl_d",
+        "final_wdl_l",
+        "final_moves_left",
+        "zero_v",
+        "zero_wdl_w",
+        "zero_wdl_d",
+        "zero_wdl_l",
+        "zero_moves_left",
+        "net_v",
+        "net_wdl_w",
+        "net_wdl_d",
+        "net_wdl_l",
+        "net_moves_left",
+        "value_surprise_final",
+        "value_surprise_zero",
+        "policy_entropy",
+        "visited_mv_count",
+        "net_id",
+    ];
+
+    /// the scalars written by every version of `BinaryOutput`
+    pub const REQUIRED: &'static [&'static str] = &[
         "game_id",
         "pos_index",
         "game_length",
@@ -396,23 +1079,42 @@ impl Scalars {
         "played_mv",
         "kdl_policy",
         "final_v",
-        // "final_wdl_w",
-        // "final_wdl_d",
-        // "final_wdl_l",
-        // "final_moves_left",
         "zero_v",
-        // "zero_wdl_w",
-        // "zero_wdl_d",
-        // "zero_wdl_l",
-        // "zero_moves_left",
         "net_v",
-        // "net_wdl_w",
-        // "net_wdl_d",
-        // "net_wdl_l",
-        // "net_moves_left",
     ];
 
-    fn to_vec(&self) -> Vec<f32> {
+    pub fn from_slice(values: &[f32]) -> Self {
+        assert_eq!(values.len(), Self::NAMES.len());
+        Scalars {
+            game_id: values[0] as usize,
+            pos_index: values[1] as usize,
+            game_length: values[2] as usize,
+            zero_visits: values[3] as u64,
+            is_full_search: values[4] != 0.0,
+            is_final_position: values[5] != 0.0,
+            is_terminal: values[6] != 0.0,
+            hit_move_limit: values[7] != 0.0,
+            available_mv_count: values[8] as usize,
+            played_mv: values[9] as isize,
+            kdl_policy: values[10],
+            final_values: values[11],
+            final_wdl: [values[12], values[13], values[14]],
+            final_moves_left: values[15],
+            zero_values: values[16],
+            zero_wdl: [values[17], values[18], values[19]],
+            zero_moves_left: values[20],
+            net_values: values[21],
+            net_wdl: [values[22], values[23], values[24]],
+            net_moves_left: values[25],
+            value_surprise_final: values[26],
+            value_surprise_zero: values[27],
+            policy_entropy: values[28],
+            visited_mv_count: values[29] as usize,
+            net_id: values[30],
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<f32> {
         let mut result = vec![
             self.game_id as f32,
             self.pos_index as f32,
@@ -427,9 +1129,20 @@ impl Scalars {
             self.kdl_policy as f32,
         ];
 
-        result.extend_from_slice(&[self.final_values]);
-        result.extend_from_slice(&[self.zero_values]);
-        result.extend_from_slice(&[self.net_values]);
+        result.push(self.final_values);
+        result.extend_from_slice(&self.final_wdl);
+        result.push(self.final_moves_left);
+        result.push(self.zero_values);
+        result.extend_from_slice(&self.zero_wdl);
+        result.push(self.zero_moves_left);
+        result.push(self.net_values);
+        result.extend_from_slice(&self.net_wdl);
+        result.push(self.net_moves_left);
+        result.push(self.value_surprise_final);
+        result.push(self.value_surprise_zero);
+        result.push(self.policy_entropy);
+        result.push(self.visited_mv_count as f32);
+        result.push(self.net_id);
 
         assert_eq!(result.len(), Self::NAMES.len());
         result
@@ -1,18 +1,33 @@
+pub mod analysis;
+pub mod batchbuffer;
 pub mod boardmanager;
 pub mod dataformat;
+pub mod dataset;
 pub mod decoder;
 pub mod dirichlet;
 pub mod dummyreq;
 pub mod elo;
+pub mod encoder;
+pub mod evalcache;
 pub mod executor;
 pub mod fileformat;
+pub mod mapper;
 pub mod mcts;
 pub mod mcts_trainer;
+pub mod mirror;
 pub mod message_types;
 pub mod mvs;
+pub mod netconfig;
+pub mod netinfo;
+pub mod pgn;
+pub mod polyglot;
+pub mod provenance;
+pub mod scoremodel;
 pub mod selfplay;
 pub mod settings;
 pub mod uci;
+pub mod verify;
+pub mod xboard;
 pub mod superluminal;
 #[cfg(test)]
 
@@ -68,4 +83,1799 @@ mod tests {
         let bs = BoardStack::new(board);
         assert_eq!(bs.status(), GameStatus::Drawn);
     }
+
+    #[test]
+    fn test_score_model_clamp_and_symmetry() {
+        use crate::scoremodel::{ScoreModel, MAX_CP};
+
+        for model in [ScoreModel::Piecewise, ScoreModel::Wdl] {
+            assert_eq!(model.cp(0.0, None), 0);
+            assert_eq!(model.cp(0.3, None), -model.cp(-0.3, None));
+            assert_eq!(model.cp(1.0, None), MAX_CP);
+            assert_eq!(model.cp(-1.0, None), -MAX_CP);
+        }
+    }
+
+    #[test]
+    fn test_fit_calibration() {
+        use crate::scoremodel::{fit_calibration, Calibration};
+
+        // soft outcomes equal to the expected score of a known model, so the fit should recover it exactly
+        let known = Calibration { scale: 1.5, bias: 0.2 };
+        let samples: Vec<(f32, f32)> = (-19..20)
+            .map(|i| {
+                let value = i as f32 / 20.0;
+                let expected = 1.0 / (1.0 + (-known.logit(value)).exp());
+                (value, 2.0 * expected - 1.0)
+            })
+            .collect();
+
+        let fitted = fit_calibration(&samples);
+        assert!((fitted.scale - known.scale).abs() < 1e-3, "{:?}", fitted);
+        assert!((fitted.bias - known.bias).abs() < 1e-3, "{:?}", fitted);
+    }
+
+    #[test]
+    fn test_calibrated_score_side_to_move() {
+        use crate::scoremodel::{Calibration, ScoreModel};
+        use cozy_chess::Color;
+
+        // the bias is the side to move's advantage, so an equal value favours whoever is to move
+        let model = ScoreModel::Calibrated(Calibration { scale: 2.0, bias: 0.5 });
+        assert!(model.white_cp(0.0, None, Color::White) > 0);
+        assert!(model.white_cp(0.0, None, Color::Black) < 0);
+        assert_eq!(
+            model.white_cp(0.3, None, Color::Black),
+            -model.white_cp(-0.3, None, Color::White)
+        );
+        assert_eq!(model.white_cp(0.3, None, Color::White), model.cp(0.3, None));
+    }
+
+    #[test]
+    fn test_polyglot_keys() {
+        use crate::polyglot::polyglot_key;
+
+        // reference keys from the Polyglot book format description
+        let positions = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 0x463b96181691fc9c),
+            ("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1", 0x823c9b50fd114196),
+            ("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2", 0x0756b94461c50fb0),
+            ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", 0x22a48b5a8e47ff78),
+            ("rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4", 0x00fdd303c946bdd9),
+            ("rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4", 0x5c3f9b829b279560),
+        ];
+        for (fen, key) in positions {
+            let board = Board::from_fen(fen, false).unwrap();
+            assert_eq!(polyglot_key(&board), key, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_polyglot_book_round_trip() {
+        use crate::polyglot::{Book, BookBuilder, BookSelection};
+        use cozy_chess::{Color, Move};
+
+        let e4: Move = "e2e4".parse().unwrap();
+        let d4: Move = "d2d4".parse().unwrap();
+        let e5: Move = "e7e5".parse().unwrap();
+        let mut builder = BookBuilder::new(2);
+        builder.add_game(&Board::default(), &[e4, e5], Some(Color::White));
+        builder.add_game(&Board::default(), &[e4, e5], None);
+        builder.add_game(&Board::default(), &[d4], Some(Color::Black));
+
+        let path = std::env::temp_dir().join("tz_test_book.bin");
+        let path = path.to_str().unwrap();
+        builder.build().save(path).unwrap();
+        let mut book = Book::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // d4 only lost, so it is left out
+        assert_eq!(book.probe(&Board::default()), vec![(e4, 3)]);
+        book.selection = BookSelection::Best;
+        let bs = book.play_opening(&mut rand::thread_rng());
+        assert_eq!(bs.board(), &{
+            let mut board = Board::default();
+            board.play(e4);
+            board.play(e5);
+            board
+        });
+    }
+
+    #[test]
+    fn test_san() {
+        use crate::pgn::{parse_san, san};
+
+        let cases = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "g1f3", "Nf3"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1h1", "O-O"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8a8", "O-O-O"),
+            ("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1", "Rad1"),
+            ("4k3/8/8/R7/8/8/4K3/R7 w - - 0 1", "a1a3", "R1a3"),
+            ("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q", "a8=Q+"),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", "exd6"),
+            ("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2", "d8h4", "Qh4#"),
+        ];
+        for (fen, mv, expected) in cases {
+            let board = Board::from_fen(fen, false).unwrap();
+            let mv = mv.parse().unwrap();
+            assert_eq!(san(&board, mv), expected);
+            assert_eq!(parse_san(&board, expected), Some(mv));
+        }
+
+        // other ways of writing moves, and ambiguous or illegal ones
+        let board = Board::from_fen("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", false).unwrap();
+        assert_eq!(parse_san(&board, "Ra1d1"), Some("a1d1".parse().unwrap()));
+        assert_eq!(parse_san(&board, "Rd1"), None);
+        assert_eq!(parse_san(&board, "Rd2"), None);
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", false).unwrap();
+        assert_eq!(parse_san(&board, "0-0-0"), Some("e1a1".parse().unwrap()));
+        let board = Board::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", false).unwrap();
+        assert_eq!(parse_san(&board, "a8N"), Some("a7a8n".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_pgn_import() {
+        use crate::fileformat::{BinaryInput, BinaryOutput};
+        use crate::pgn::{game_to_simulation, parse_pgn, simulation_to_pgn};
+        use cozy_chess::Color;
+
+        // exported games read back to the same moves
+        let sim = test_simulation(&["f2f3", "e7e5", "g2g4", "d8h4"]);
+        let games = parse_pgn(&simulation_to_pgn(&sim, &[]));
+        assert_eq!(games.len(), 1);
+        let game = games[0].as_ref().unwrap();
+        let played: Vec<_> = sim.positions.iter().map(|pos| pos.played_mv).collect();
+        assert_eq!(game.moves, played);
+        assert_eq!(game.outcome(), Ok(Some(Color::Black)));
+
+        let text = "[Event \"test\"]
+[White \"a \\\"quoted\\\" name\"]
+[Result \"1-0\"]
+
+1.e4 e5 {a comment} 2. Nf3 (2. f4 exf4) Nc6 $1 3. Bb5 ; rest of line
+a6 1-0
+
+[FEN \"4k3/8/8/8/8/8/8/R3K3 w Q - 0 1\"]
+
+1. O-O-O Ke7 2. Qh1 *
+
+[Result \"0-1\"]
+1. e4 0-1
+";
+        let games = parse_pgn(text);
+        assert_eq!(games.len(), 3);
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.header("White"), Some("a \"quoted\" name"));
+        assert_eq!(game.moves.len(), 6);
+        assert_eq!(game.moves[5], "a7a6".parse().unwrap());
+        // castling is fine, there is no queen to move
+        assert!(games[1].as_ref().unwrap_err().contains("illegal move Qh1"));
+        assert_eq!(games[2].as_ref().unwrap().result, "0-1");
+
+        // white resigned after 3... a6, policy is smoothed over the legal moves
+        let (sim, outcome) = game_to_simulation(game, 0.1).unwrap();
+        assert_eq!(outcome, Some(Color::White));
+        let path = std::env::temp_dir().join("tz_test_pgn_import");
+        let mut output = BinaryOutput::new(&path, "chess").unwrap();
+        output.append_with_outcome(&sim, outcome).unwrap();
+        output.finish().unwrap();
+
+        let input = BinaryInput::new(&path).unwrap();
+        assert_eq!(input.position_count(), 7);
+        for pi in 0..6 {
+            let position = input.position(pi).unwrap();
+            let policy = &position.policy_values;
+            assert!((policy.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+            let best = policy.iter().cloned().fold(0.0, f32::max);
+            assert!((best - (0.9 + 0.1 / policy.len() as f32)).abs() < 1e-6);
+            let expected = if pi % 2 == 0 { 1.0 } else { -1.0 };
+            assert_eq!(position.scalars.final_values, expected);
+        }
+        assert!(!input.position(6).unwrap().scalars.is_terminal);
+
+        for ext in ["bin", "off", "json"] {
+            std::fs::remove_file(path.with_extension(ext)).unwrap();
+        }
+        // a game played on past a threefold repetition ends at the repetition as a draw
+        let text = "[Result \"1-0\"]
+
+1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 5. Nf3 Nf6 6. e4 Nxe4 1-0
+";
+        let game = parse_pgn(text).remove(0).unwrap();
+        assert_eq!(game.moves.len(), 12);
+        let (sim, outcome) = game_to_simulation(&game, 0.0).unwrap();
+        assert_eq!(outcome, None);
+        assert_eq!(sim.positions.len(), 8);
+        assert!(sim.final_board.is_terminal());
+        let mut output = BinaryOutput::new(&path, "chess").unwrap();
+        output.append(&sim).unwrap();
+        output.finish().unwrap();
+        for ext in ["bin", "off", "json"] {
+            std::fs::remove_file(path.with_extension(ext)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_simulation_to_pgn() {
+        use crate::pgn::simulation_to_pgn;
+
+        let sim = test_simulation(&["f2f3", "e7e5", "g2g4", "d8h4"]);
+        let pgn = simulation_to_pgn(&sim, &[("White", "tz".to_string())]);
+        assert!(pgn.starts_with("[Event \"?\"]\n"));
+        assert!(pgn.contains("[White \"tz\"]\n"));
+        assert!(pgn.contains("[Result \"0-1\"]\n"));
+        assert!(pgn.contains("[NetHash \""));
+        assert!(!pgn.contains("[FEN"));
+
+        let movetext = pgn.split("\n\n").nth(1).unwrap().replace('\n', " ");
+        assert!(movetext.starts_with("1. f3 {N=100 Q=0.250 v=-0.500 top:"));
+        assert!(movetext.contains("} 1... e5 {N=101"));
+        assert!(movetext.contains("} 2. g4 {"));
+        assert!(movetext.contains("} 2... Qh4# {"));
+        assert!(movetext.ends_with("} 0-1"));
+        assert!(pgn.lines().all(|line| line.len() <= 80 || line.starts_with('[')));
+    }
+
+    // a short game with a uniform policy, no network needed
+    fn test_simulation(moves: &[&str]) -> crate::dataformat::Simulation {
+        use crate::dataformat::{Position, Simulation, ZeroEvaluation};
+        use crate::encoder::InputEncoder;
+        use crate::mcts_trainer::{TypeRequest, Wdl};
+        use crate::provenance::{hash_net_bytes, Provenance};
+        use crate::scoremodel::ScoreModel;
+        use crate::settings::SearchSettings;
+        use crate::boardmanager::MAX_HISTORY;
+        use cozy_chess::Move;
+
+        // kept for every encoder, like imported games
+        let mut bs = BoardStack::new(Board::default()).with_history(MAX_HISTORY);
+        let mut positions = Vec::new();
+        for (i, mv) in moves.iter().enumerate() {
+            let mv: Move = mv.parse().unwrap();
+            let mut move_count = 0;
+            bs.board().generate_moves(|moves| {
+                move_count += moves.len();
+                false
+            });
+            let policy = vec![1.0 / move_count as f32; move_count];
+            positions.push(Position {
+                board: bs.clone(),
+                is_full_search: true,
+                played_mv: mv,
+                zero_visits: 100 + i as u64,
+                zero_evaluation: ZeroEvaluation {
+                    values: 0.25,
+                    wdl: Wdl {
+                        w: 0.5,
+                        d: 0.25,
+                        l: 0.25,
+                    },
+                    moves_left: 20.0,
+                    policy: policy.clone(),
+                },
+                net_evaluation: ZeroEvaluation {
+                    values: -0.5,
+                    wdl: Wdl {
+                        w: 0.0,
+                        d: 0.5,
+                        l: 0.5,
+                    },
+                    moves_left: 30.0,
+                    policy,
+                },
+            });
+            bs.play(mv);
+        }
+        let settings = SearchSettings {
+            fpu: 0.0,
+            wdl: None,
+            moves_left: None,
+            c_puct: 2.0,
+            max_nodes: 100,
+            alpha: 0.3,
+            eps: 0.3,
+            search_type: TypeRequest::TrainerSearch(None),
+            pst: 1.2,
+            score_model: ScoreModel::Piecewise,
+            encoder: InputEncoder::default(),
+            mirror: false,
+        };
+        Simulation {
+            positions,
+            final_board: bs,
+            provenance: Some(Provenance {
+                net_hash: Some(hash_net_bytes(b"test net")),
+                settings,
+                seed: moves.len() as u64,
+                start_fen: Board::default().to_string(),
+                net_switches: Vec::new(),
+            }),
+        }
+    }
+
+    // a fake executor thread that sends back whatever `respond` returns for each request
+    fn fake_executor_with(
+        capacity: usize,
+        mut respond: impl FnMut(&crate::executor::EvalRequest) -> Vec<crate::executor::EvalResponse>
+            + Send
+            + 'static,
+    ) -> (
+        flume::Sender<crate::executor::EvalRequest>,
+        std::thread::JoinHandle<()>,
+    ) {
+        let (sender, receiver) = flume::bounded::<crate::executor::EvalRequest>(capacity);
+        let executor = std::thread::spawn(move || {
+            for request in receiver.iter() {
+                for response in respond(&request) {
+                    request.resender.send(response).unwrap();
+                }
+            }
+        });
+        (sender, executor)
+    }
+
+    // a fake executor thread of generation 0, stops once every sender is dropped
+    fn fake_executor(
+        capacity: usize,
+        respond: impl Fn(
+                &crate::executor::EvalRequest,
+            ) -> Result<crate::decoder::NetOutput, crate::executor::EvalError>
+            + Send
+            + 'static,
+    ) -> (
+        flume::Sender<crate::executor::EvalRequest>,
+        std::thread::JoinHandle<()>,
+    ) {
+        fake_executor_with(capacity, move |request| {
+            vec![crate::executor::EvalResponse {
+                id: request.id,
+                generation: 0,
+                result: respond(request),
+            }]
+        })
+    }
+
+    // a draw-ish evaluation with a uniform policy
+    fn uniform_output(
+        request: &crate::executor::EvalRequest,
+    ) -> Result<crate::decoder::NetOutput, crate::executor::EvalError> {
+        let n = request.legal_indices.len();
+        Ok(crate::decoder::NetOutput {
+            value: 0.0,
+            wdl: [0.2, 0.6, 0.2],
+            moves_left: 50.0,
+            policy: vec![1.0 / n as f32; n],
+        })
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        use crate::decoder::board_data;
+        use crate::fileformat::{BinaryInput, BinaryOutput};
+        use crate::provenance::{hash_net_bytes, net_id};
+
+        // fool's mate, the second game ends in a draw by repetition
+        let games = [
+            test_simulation(&["f2f3", "e7e5", "g2g4", "d8h4"]),
+            test_simulation(&[
+                "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8",
+            ]),
+        ];
+
+        let path = std::env::temp_dir().join("tz_test_round_trip");
+        let mut output = BinaryOutput::new(&path, "chess").unwrap();
+        for sim in &games {
+            output.append(sim).unwrap();
+        }
+        output.finish().unwrap();
+
+        let input = BinaryInput::new(&path).unwrap();
+        assert_eq!(input.game_count(), 2);
+        assert_eq!(input.position_count(), 5 + 9);
+        assert_eq!(input.game_range(1).unwrap(), 5..14);
+
+        // indices past the end are an error, not a panic
+        let position_count = input.position_count();
+        assert_eq!(
+            input.position(position_count).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            input.game(games.len()).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+
+        for (game, sim) in games.iter().enumerate() {
+            let range = input.game_range(game).unwrap();
+            let boards = sim
+                .positions
+                .iter()
+                .map(|pos| &pos.board)
+                .chain([&sim.final_board]);
+            for (pi, board) in range.zip(boards) {
+                let position = input.position(pi).unwrap();
+                let (board_scalars, board_bools) = board_data(board);
+                assert_eq!(position.board_scalars, board_scalars);
+                assert_eq!(position.unpacked_board_bools(), board_bools);
+                assert_eq!(position.scalars.game_id, game);
+                assert_eq!(position.policy_indices.len(), position.policy_values.len());
+
+                // one move left at the final position, counting up towards the start of the game
+                let scalars = &position.scalars;
+                assert_eq!(
+                    scalars.final_moves_left,
+                    (scalars.game_length + 1 - scalars.pos_index) as f32
+                );
+
+                if position.scalars.is_final_position {
+                    assert!(position.policy_indices.is_empty());
+                    assert!(position.scalars.zero_values.is_nan());
+                    assert!(scalars.zero_wdl.iter().all(|x| x.is_nan()));
+                } else {
+                    let original = &sim.positions[position.scalars.pos_index];
+                    assert_eq!(position.policy_values, original.zero_evaluation.policy);
+                    assert_eq!(position.scalars.zero_visits, original.zero_visits);
+                    assert_eq!(position.scalars.zero_values, 0.25);
+                    assert_eq!(position.scalars.net_values, -0.5);
+                    assert_eq!(scalars.zero_moves_left, 20.0);
+
+                    // search and network policies are identical and uniform
+                    let mv_count = scalars.available_mv_count;
+                    assert_eq!(scalars.kdl_policy, 0.0);
+                    assert!((scalars.policy_entropy - (mv_count as f32).ln()).abs() < 1e-4);
+                    assert_eq!(scalars.visited_mv_count, mv_count);
+                    assert_eq!(scalars.value_surprise_zero, 0.75);
+                    assert_eq!(scalars.net_moves_left, 30.0);
+
+                    // wdl targets are from the side to move
+                    if board.board().side_to_move() == cozy_chess::Color::White {
+                        assert_eq!(scalars.zero_wdl, [0.5, 0.25, 0.25]);
+                        assert_eq!(scalars.net_wdl, [0.0, 0.5, 0.5]);
+                    } else {
+                        assert_eq!(scalars.zero_wdl, [0.25, 0.25, 0.5]);
+                        assert_eq!(scalars.net_wdl, [0.5, 0.5, 0.0]);
+                    }
+                }
+            }
+        }
+        // black delivered mate, the position before the mate is black to move
+        assert_eq!(input.position(3).unwrap().scalars.final_values, 1.0);
+        assert_eq!(input.position(2).unwrap().scalars.final_values, -1.0);
+        assert_eq!(input.position(13).unwrap().scalars.final_values, 0.0);
+        assert_eq!(input.position(3).unwrap().scalars.final_wdl, [1.0, 0.0, 0.0]);
+        assert_eq!(input.position(2).unwrap().scalars.final_wdl, [0.0, 0.0, 1.0]);
+        assert_eq!(input.position(13).unwrap().scalars.final_wdl, [0.0, 1.0, 0.0]);
+        // provenance is kept per game in the metadata and as a scalar
+        for (game, sim) in games.iter().enumerate() {
+            assert_eq!(input.meta().game_provenance[game], sim.provenance);
+        }
+        assert!(hash_net_bytes(b"").starts_with("e3b0c442"));
+        let net_hash = hash_net_bytes(b"test net");
+        assert_eq!(input.position(0).unwrap().scalars.net_id, net_id(&net_hash));
+        assert_eq!(input.position(4).unwrap().scalars.net_id, net_id(&net_hash));
+
+        // net_v is -0.5 from white's point of view, so the network is half right for both players
+        assert_eq!(input.position(3).unwrap().scalars.value_surprise_final, 0.5);
+        assert_eq!(input.position(2).unwrap().scalars.value_surprise_final, 0.5);
+        assert_eq!(input.meta().mean_kdl_policy, Some(0.0));
+        assert_eq!(input.meta().mean_value_surprise_final, Some(0.5));
+        assert_eq!(input.meta().mean_value_surprise_zero, Some(0.75));
+        assert!(input.meta().mean_policy_entropy.is_some());
+
+        for ext in ["bin", "off", "json"] {
+            std::fs::remove_file(path.with_extension(ext)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_binary_baseline_scalars() {
+        use crate::fileformat::{BinaryInput, BinaryOutput, Scalars};
+
+        let games = [
+            test_simulation(&["f2f3", "e7e5", "g2g4", "d8h4"]),
+            test_simulation(&[
+                "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8",
+            ]),
+        ];
+        let path = std::env::temp_dir().join("tz_test_baseline_scalars");
+        let mut output = BinaryOutput::new(&path, "chess").unwrap();
+        for sim in &games {
+            output.append(sim).unwrap();
+        }
+        output.finish().unwrap();
+        let current = BinaryInput::new(&path).unwrap();
+        let expected = (0..current.position_count())
+            .map(|i| current.position(i).unwrap())
+            .collect::<Vec<_>>();
+        drop(current);
+
+        // rewrite the file with the scalars written before WDL, metrics and provenance were added
+        let baseline = Scalars::REQUIRED;
+        let columns = baseline
+            .iter()
+            .map(|name| Scalars::NAMES.iter().position(|n| n == name).unwrap())
+            .collect::<Vec<_>>();
+        let bin = std::fs::read(path.with_extension("bin")).unwrap();
+        let off = std::fs::read(path.with_extension("off")).unwrap();
+        let offsets = off
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+            .collect::<Vec<_>>();
+        let (starts, game_starts) = offsets.split_at(expected.len());
+        let mut new_bin = Vec::new();
+        let mut new_off = Vec::new();
+        for (i, &start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(bin.len());
+            let data = &bin[start..end];
+            new_off.extend_from_slice(&(new_bin.len() as u64).to_le_bytes());
+            for &column in &columns {
+                new_bin.extend_from_slice(&data[4 * column..4 * column + 4]);
+            }
+            new_bin.extend_from_slice(&data[4 * Scalars::NAMES.len()..]);
+        }
+        for &start in game_starts {
+            new_off.extend_from_slice(&(start as u64).to_le_bytes());
+        }
+        let mut meta: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.with_extension("json")).unwrap()).unwrap();
+        let meta = meta.as_object_mut().unwrap();
+        meta.retain(|key, _| !key.starts_with("mean_") && key != "game_provenance");
+        meta.insert("scalar_names".to_string(), serde_json::json!(baseline));
+        std::fs::write(path.with_extension("bin"), new_bin).unwrap();
+        std::fs::write(path.with_extension("off"), new_off).unwrap();
+        std::fs::write(path.with_extension("json"), serde_json::to_vec(&meta).unwrap()).unwrap();
+
+        // the missing scalars are NaN, the final WDL follows from the result
+        let input = BinaryInput::new(&path).unwrap();
+        assert_eq!(input.game_count(), 2);
+        for (i, expected) in expected.iter().enumerate() {
+            let position = input.position(i).unwrap();
+            let (scalars, original) = (&position.scalars, &expected.scalars);
+            assert_eq!(scalars.pos_index, original.pos_index);
+            assert_eq!(scalars.played_mv, original.played_mv);
+            assert_eq!(scalars.final_values.to_bits(), original.final_values.to_bits());
+            assert_eq!(scalars.net_values.to_bits(), original.net_values.to_bits());
+            assert!(scalars.zero_wdl.iter().all(|x| x.is_nan()));
+            assert!(scalars.net_id.is_nan());
+            assert!(scalars.final_moves_left.is_nan());
+            assert_eq!(position.policy_values, expected.policy_values);
+            assert_eq!(position.board_scalars, expected.board_scalars);
+        }
+        assert_eq!(input.position(3).unwrap().scalars.final_wdl, [1.0, 0.0, 0.0]);
+        assert_eq!(input.position(4).unwrap().scalars.final_wdl, [0.0, 0.0, 1.0]);
+        assert_eq!(input.position(13).unwrap().scalars.final_wdl, [0.0, 1.0, 0.0]);
+        assert!(input.game(0).unwrap().provenance.is_none());
+
+        // a file without one of the original scalars can not be read
+        let mut meta = meta.clone();
+        meta.insert("scalar_names".to_string(), serde_json::json!(baseline[1..]));
+        std::fs::write(path.with_extension("json"), serde_json::to_vec(&meta).unwrap()).unwrap();
+        assert_eq!(
+            BinaryInput::new(&path).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        for ext in ["bin", "off", "json"] {
+            std::fs::remove_file(path.with_extension(ext)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_dataset_rewrite() {
+        use crate::dataset::{rewrite, DatasetOptions};
+        use crate::fileformat::{BinaryInput, BinaryOutput};
+
+        // the first game is repeated, the second ends in a draw by repetition
+        let mate = ["f2f3", "e7e5", "g2g4", "d8h4"];
+        let games = [
+            test_simulation(&mate),
+            test_simulation(&[
+                "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8",
+            ]),
+            test_simulation(&mate),
+        ];
+        let dir = std::env::temp_dir();
+        let path = dir.join("tz_test_dataset_input");
+        let mut output = BinaryOutput::new(&path, "chess").unwrap();
+        for sim in &games {
+            output.append(sim).unwrap();
+        }
+        output.finish().unwrap();
+        let inputs = [BinaryInput::new(&path).unwrap()];
+
+        // deduplicate and split into files of one game each
+        let mut options = DatasetOptions {
+            dedup: true,
+            shuffle_seed: Some(0),
+            games_per_file: Some(1),
+            ..Default::default()
+        };
+        let out = dir.join("tz_test_dataset_split");
+        let summary = rewrite(&inputs, &out, &options).unwrap();
+        assert_eq!(summary.games_read, 3);
+        assert_eq!(summary.games_duplicate, 1);
+        assert_eq!(summary.games_written, 2);
+        assert_eq!(summary.outputs.len(), 2);
+        let mut lengths = vec![];
+        for path in &summary.outputs {
+            let split = BinaryInput::new(path).unwrap();
+            assert_eq!(split.game_count(), 1);
+            assert_eq!(split.game_range(0).unwrap(), 0..split.position_count());
+            let game = split.game(0).unwrap();
+            assert!(game.positions.iter().all(|p| p.scalars.game_id == 0));
+            lengths.push(game.length());
+        }
+        lengths.sort();
+        assert_eq!(lengths, [4, 8]);
+
+        // filter on the result, the copied positions and metadata match the original
+        options.games_per_file = None;
+        options.filter.results = vec![Some(cozy_chess::Color::Black)];
+        let out = dir.join("tz_test_dataset_filter");
+        let summary = rewrite(&inputs, &out, &options).unwrap();
+        assert_eq!(summary.games_filtered, 1);
+        assert_eq!(summary.outputs, std::slice::from_ref(&out));
+        let filtered = BinaryInput::new(&out).unwrap();
+        assert_eq!(filtered.game_count(), 1);
+        assert_eq!(filtered.meta().root_wdl, [0.0, 0.0, 1.0]);
+        assert_eq!(filtered.meta().game_provenance, [games[0].provenance.clone()]);
+        assert_eq!(filtered.meta().mean_kdl_policy, inputs[0].meta().mean_kdl_policy);
+        let original = inputs[0].game(0).unwrap();
+        let copied = filtered.game(0).unwrap();
+        let bits = |p: &crate::fileformat::StoredPosition| -> Vec<u32> {
+            p.scalars.to_vec()[1..].iter().map(|x| x.to_bits()).collect()
+        };
+        for (a, b) in original.positions.iter().zip(&copied.positions) {
+            assert_eq!(bits(a), bits(b));
+            assert_eq!(a.board_bools, b.board_bools);
+            assert_eq!(a.policy_values, b.policy_values);
+        }
+
+        // an output that is also an input is refused before it is truncated
+        let error = rewrite(&inputs, &path, &options).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(inputs[0].game(2).unwrap().length(), 4);
+
+        // games can not be read from files without game start indices
+        let other = dir.join("tz_test_dataset_no_starts");
+        for ext in ["bin", "off", "json"] {
+            std::fs::copy(path.with_extension(ext), other.with_extension(ext)).unwrap();
+        }
+        let off = std::fs::read(other.with_extension("off")).unwrap();
+        std::fs::write(other.with_extension("off"), &off[..8 * inputs[0].position_count()])
+            .unwrap();
+        let mut meta = inputs[0].meta().clone();
+        meta.includes_game_start_indices = false;
+        std::fs::write(other.with_extension("json"), serde_json::to_vec(&meta).unwrap()).unwrap();
+        let no_starts = BinaryInput::new(&other).unwrap();
+        assert_eq!(
+            no_starts.game(0).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        assert!(no_starts.position(0).is_ok());
+        let error = rewrite(&[no_starts], &out, &options).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        for path in summary.outputs.iter().chain([&path, &other]) {
+            for ext in ["bin", "off", "json"] {
+                std::fs::remove_file(path.with_extension(ext)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_board() {
+        use crate::decoder::{board_data, decode_board};
+
+        // castling rights, en passant for both colors and a black promotion
+        let moves = [
+            "e2e4", "d7d5", "e4e5", "f7f5", "e5f6", "d5d4", "c2c4", "d4c3", "f6g7", "c3b2",
+            "g7h8q", "b2a1n",
+        ];
+        let mut bs = BoardStack::new(Board::default());
+        for mv in moves {
+            let (board_scalars, board_bools) = board_data(&bs);
+            let board = decode_board(&board_scalars, &board_bools).unwrap();
+            let (decoded_scalars, decoded_bools) = board_data(&BoardStack::new(board.clone()));
+            assert_eq!(decoded_bools, board_bools);
+            assert_eq!(decoded_scalars, board_scalars);
+            assert_eq!(board.en_passant(), bs.board().en_passant());
+            bs.play(mv.parse().unwrap());
+        }
+        assert!(decode_board(&[0.0; 8], &[false; 64 * 13]).is_none());
+    }
+
+    #[test]
+    fn test_verify_file() {
+        use crate::fileformat::BinaryOutput;
+        use crate::verify::{quarantine, verify_file};
+
+        let dir = std::env::temp_dir();
+        let path = dir.join("tz_test_verify");
+        let mut output = BinaryOutput::new(&path, "chess").unwrap();
+        output
+            .append(&test_simulation(&["f2f3", "e7e5", "g2g4", "d8h4"]))
+            .unwrap();
+        // not finished on the board, with an en passant capture
+        let sim = test_simulation(&["e2e4", "a7a6", "e4e5", "d7d5", "e5d6"]);
+        output.append_with_outcome(&sim, None).unwrap();
+        output.finish().unwrap();
+
+        let report = verify_file(&path);
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.games, 2);
+        assert_eq!(report.positions, 5 + 6);
+
+        // make the second move of the first game a different legal move, breaking the game
+        let offsets = std::fs::read(path.with_extension("off")).unwrap();
+        let offset = u64::from_le_bytes(offsets[8..16].try_into().unwrap()) as usize;
+        let mut bin = std::fs::read(path.with_extension("bin")).unwrap();
+        let played_mv = &mut bin[offset + 4 * 9..offset + 4 * 10];
+        let original = f32::from_le_bytes(played_mv.try_into().unwrap());
+        let legal = crate::fileformat::BinaryInput::new(&path)
+            .unwrap()
+            .position(1)
+            .unwrap()
+            .policy_indices;
+        let other = *legal.iter().find(|&&i| i as f32 != original).unwrap();
+        played_mv.copy_from_slice(&(other as f32).to_le_bytes());
+        std::fs::write(path.with_extension("bin"), &bin).unwrap();
+        let report = verify_file(&path);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].starts_with("game 0: position 2:"));
+
+        // a crashed writer leaves the metadata in .json.tmp
+        std::fs::rename(path.with_extension("json"), path.with_extension("json.tmp")).unwrap();
+        let report = verify_file(&path);
+        assert!(report.errors[0].contains("never finished"));
+
+        let quarantine_dir = dir.join("tz_test_quarantine");
+        quarantine(&path, &quarantine_dir).unwrap();
+        assert!(!path.with_extension("bin").exists());
+        assert!(quarantine_dir.join("tz_test_verify.json.tmp").exists());
+        std::fs::remove_dir_all(quarantine_dir).unwrap();
+    }
+
+    #[test]
+    fn test_binary_recover() {
+        use crate::fileformat::{BinaryInput, BinaryOutput};
+        use crate::verify::{quarantine, verify_file};
+
+        let mate = test_simulation(&["f2f3", "e7e5", "g2g4", "d8h4"]);
+        let path = std::env::temp_dir().join("tz_test_recover");
+        let mut output = BinaryOutput::new(&path, "chess").unwrap();
+        output.append(&mate).unwrap();
+        output.checkpoint().unwrap();
+        output.append(&mate).unwrap();
+        output.append(&mate).unwrap();
+        output.checkpoint().unwrap();
+        // checkpoints only append the games since the previous one
+        let progress = std::fs::read_to_string(path.with_extension("progress")).unwrap();
+        assert_eq!(progress.lines().count(), 2);
+        assert_eq!(progress.matches("\"start_fen\"").count(), 3);
+        // a live writer is left alone
+        assert_eq!(
+            BinaryOutput::recover(&path).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        assert!(BinaryOutput::is_being_written(&path));
+        let report = verify_file(&path);
+        assert!(report.in_progress && report.is_ok());
+        assert_eq!(
+            quarantine(&path, std::env::temp_dir().join("tz_test_recover_quarantine"))
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        assert!(path.with_extension("bin").exists());
+        // the process dies halfway through the next game and its checkpoint
+        output.append(&mate).unwrap();
+        drop(output);
+        let mut progress_file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path.with_extension("progress"))
+            .unwrap();
+        std::io::Write::write_all(&mut progress_file, b"{\"bin_len\": 12").unwrap();
+        drop(progress_file);
+        assert!(BinaryInput::new(&path).is_err());
+        assert!(!BinaryOutput::is_being_written(&path));
+        assert!(verify_file(&path).errors[0].contains("can be recovered"));
+
+        assert_eq!(BinaryOutput::recover(&path).unwrap(), 3);
+        let report = verify_file(&path);
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.games, 3);
+        let input = BinaryInput::new(&path).unwrap();
+        assert_eq!(input.meta().game_provenance.len(), 3);
+        assert!(input.meta().game_provenance.iter().all(|p| p.is_some()));
+        assert!(!path.with_extension("progress").exists());
+        assert!(!path.with_extension("json.tmp").exists());
+        assert!(BinaryOutput::recover(&path).is_err());
+
+        for ext in ["bin", "off", "json"] {
+            std::fs::remove_file(path.with_extension(ext)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_input_encoder() {
+        use crate::decoder::{board_data, piece_planes};
+        use crate::encoder::InputEncoder;
+        use crate::fileformat::{BinaryInput, BinaryOutput};
+        use crate::verify::verify_file;
+
+        let mut bs = BoardStack::new(Board::default()).with_history(3);
+        bs.play("e2e4".parse().unwrap());
+        let after_e4 = bs.board().clone();
+        bs.play("e7e5".parse().unwrap());
+
+        // boards are only kept when asked for, and kept boards don't make a different position
+        let mut plain = BoardStack::new(Board::default());
+        plain.play("e2e4".parse().unwrap());
+        plain.play("e7e5".parse().unwrap());
+        assert!(plain.history().is_empty());
+        assert_eq!(bs.history().len(), 2);
+        assert_eq!(plain, bs);
+        assert_eq!(bs.clone().with_history(1).history(), std::slice::from_ref(&after_e4));
+
+        // v1 is the original encoding
+        assert_eq!(InputEncoder::V1.board_data(&bs), board_data(&bs));
+        assert_eq!(InputEncoder::V1.input_channels(), 21);
+
+        // history planes are seen from the side to move, most recent first and empty before the start
+        let encoder = InputEncoder::V2 { history: 3 };
+        let (_, bools) = encoder.board_data(&bs);
+        assert_eq!(bools.len(), 64 * (13 + 3 * 12));
+        let history = &bools[64 * 13..];
+        assert_eq!(history[..64 * 12], piece_planes(&after_e4, cozy_chess::Color::White));
+        let start = piece_planes(&Board::default(), cozy_chess::Color::White);
+        assert_eq!(history[64 * 12..64 * 24], start);
+        assert!(history[64 * 24..].iter().all(|&b| !b));
+        assert_eq!(encoder.input_channels(), 8 + 13 + 36);
+
+        // the encoding is recorded in the metadata and the files can be read back
+        let path = std::env::temp_dir().join("tz_test_encoder");
+        let mut output = BinaryOutput::with_encoder(&path, "chess", encoder).unwrap();
+        output
+            .append(&test_simulation(&["f2f3", "e7e5", "g2g4", "d8h4"]))
+            .unwrap();
+        output.finish().unwrap();
+        let input = BinaryInput::new(&path).unwrap();
+        assert_eq!(input.meta().input_encoding, encoder);
+        assert_eq!(input.meta().input_bool_shape, [49, 8, 8]);
+        let position = input.position(2).unwrap();
+        assert_eq!(position.unpacked_board_bools().len(), 64 * 49);
+        assert!(verify_file(&path).is_ok());
+
+        for ext in ["bin", "off", "json"] {
+            std::fs::remove_file(path.with_extension(ext)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_move_mapper() {
+        use crate::mapper::{AlphaZeroMapper, ChessStdMapper, MoveMapper};
+        use crate::mvs::get_contents;
+        use cozy_chess::Move;
+
+        let positions = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            // castling both ways and promotions with and without capture, for both colors
+            "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1",
+            "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R b KQkq - 0 1",
+            "rn2k2r/1P6/8/3pP3/8/8/6p1/R3K1NR w KQk d6 0 1",
+        ];
+        let std_mapper = ChessStdMapper::get();
+        let mappers: [&dyn MoveMapper; 2] = [std_mapper, &AlphaZeroMapper];
+        assert_eq!(mappers[0].policy_len(), 1880);
+        assert_eq!(mappers[1].policy_shape(), [73, 8, 8]);
+
+        for fen in positions {
+            let board: Board = fen.parse().unwrap();
+            let mut moves = vec![];
+            board.generate_moves(|m| {
+                moves.extend(m);
+                false
+            });
+
+            for mapper in mappers {
+                let mut indices = vec![];
+                for &mv in &moves {
+                    let index = mapper.move_to_index(&board, mv).unwrap();
+                    assert!(index < mapper.policy_len());
+                    assert_eq!(mapper.index_to_move(&board, index), Some(mv), "{} {}", fen, mv);
+                    indices.push(index);
+                }
+                indices.sort();
+                indices.dedup();
+                assert_eq!(indices.len(), moves.len());
+            }
+
+            // the table gives the same index as searching mvs.rs from the side to move
+            for &mv in &moves {
+                let mut pov = mv;
+                if board.side_to_move() == cozy_chess::Color::Black {
+                    pov = Move {
+                        from: mv.from.flip_rank(),
+                        to: mv.to.flip_rank(),
+                        promotion: mv.promotion,
+                    };
+                }
+                let expected = get_contents().iter().position(|&x| x == pov);
+                assert_eq!(std_mapper.move_to_index(&board, mv), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoded_input() {
+        use crate::encoder::{pack_bools, InputEncoder};
+
+        let mut bs = BoardStack::new(Board::default()).with_history(2);
+        bs.play("e2e4".parse().unwrap());
+        bs.play("c7c5".parse().unwrap());
+
+        for encoder in [InputEncoder::V1, InputEncoder::V2 { history: 2 }] {
+            let input = encoder.encode_packed(&bs);
+            let (scalars, bools) = encoder.board_data(&bs);
+            assert_eq!(input.scalars, scalars);
+            assert_eq!(input.bools, pack_bools(&bools));
+
+            // the second slot of a batch, the first one is left alone
+            let mut batch = vec![-1.0; 2 * encoder.input_len()];
+            input.write_planes(&mut batch[encoder.input_len()..]);
+            assert!(batch[..encoder.input_len()].iter().all(|&v| v == -1.0));
+            let planes = &batch[encoder.input_len()..];
+            for (i, &v) in scalars.iter().enumerate() {
+                assert!(planes[64 * i..64 * (i + 1)].iter().all(|&p| p == v));
+            }
+            let expected: Vec<f32> = bools.iter().map(|&b| b as u8 as f32).collect();
+            assert_eq!(planes[64 * scalars.len()..], expected);
+        }
+
+        assert_eq!(
+            pack_bools(&[true, false, false, true, false, false, false, false]),
+            [0b1001]
+        );
+    }
+
+    #[test]
+    fn test_process_board_output() {
+        use crate::decoder::{legal_policy_indices, process_board_output, NetOutput};
+        use crate::mapper::{ChessStdMapper, MoveMapper};
+        use crate::mcts_trainer::Tree;
+
+        let mut bs = BoardStack::new(Board::default());
+        bs.play("e2e4".parse().unwrap());
+        let (legal_moves, legal_indices) = legal_policy_indices(&bs);
+        assert_eq!(legal_moves.len(), 20);
+        for (&mv, &index) in legal_moves.iter().zip(&legal_indices) {
+            assert_eq!(
+                ChessStdMapper::get().move_to_index(bs.board(), mv),
+                Some(index)
+            );
+        }
+
+        // the executor returns priors in the order of the indices, values from the side to move
+        let policy: Vec<f32> = (0..20).map(|i| (i + 1) as f32 / 210.0).collect();
+        let output = NetOutput {
+            value: 0.5,
+            wdl: [0.6, 0.3, 0.1],
+            moves_left: 40.0,
+            policy: policy.clone(),
+        };
+        let settings = test_simulation(&[]).provenance.unwrap().settings;
+        let mut tree = Tree::new(bs.clone(), settings);
+        process_board_output(&output, &legal_moves, &0, &mut tree, &bs);
+
+        let root = &tree.nodes[0];
+        assert_eq!(root.eval_score, -0.5);
+        assert_eq!(root.moves_left, 40.0);
+        assert_eq!((root.wdl.w, root.wdl.d, root.wdl.l), (0.6, 0.3, 0.1));
+        assert_eq!(root.children, 1..21);
+        for (i, child) in tree.nodes[1..].iter().enumerate() {
+            assert_eq!(child.mv, Some(legal_moves[i]));
+            assert_eq!(child.policy, policy[i]);
+            assert_eq!(child.parent, Some(0));
+        }
+    }
+
+    #[test]
+    fn test_eval_client() {
+        use crate::encoder::InputEncoder;
+        use crate::executor::{EvalClient, EvalError, EvalRequest, EvalResponse};
+
+        // a fake executor that answers with a uniform policy, the second answer has the wrong id
+        // and the third one comes after a late answer to an earlier request
+        let mut i = 0;
+        let (sender, executor) = fake_executor_with(1, move |request| {
+            let start = BoardStack::new(Board::default());
+            assert_eq!(request.input, InputEncoder::V1.encode_packed(&start));
+            i += 1;
+            let response = |id| EvalResponse {
+                id,
+                generation: 0,
+                result: uniform_output(request),
+            };
+            match i {
+                2 => vec![response(request.id + 10)],
+                3 => vec![response(request.id - 1), response(request.id)],
+                _ => vec![response(request.id)],
+            }
+        });
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut client = EvalClient::new(sender, InputEncoder::V1);
+        let bs = BoardStack::new(Board::default());
+        let output = rt.block_on(client.evaluate(&bs)).unwrap();
+        assert_eq!(output.policy.len(), 20);
+        assert_eq!(
+            rt.block_on(client.evaluate(&bs)),
+            Err(EvalError::UnexpectedResponse {
+                expected: 1,
+                got: 11
+            })
+        );
+        // the stale answer is skipped
+        let output = rt.block_on(client.evaluate(&bs)).unwrap();
+        assert_eq!(output.policy.len(), 20);
+        drop(client);
+        executor.join().unwrap();
+
+        let (sender, receiver) = flume::bounded::<EvalRequest>(1);
+        drop(receiver);
+        let mut client = EvalClient::new(sender, InputEncoder::V1);
+        assert_eq!(
+            rt.block_on(client.evaluate(&bs)),
+            Err(EvalError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_search_error() {
+        use crate::encoder::InputEncoder;
+        use crate::executor::{EvalClient, EvalError};
+        use crate::mcts::extend_search;
+        use crate::mcts_trainer::{Tree, TypeRequest};
+        use crate::scoremodel::ScoreModel;
+        use crate::settings::SearchSettings;
+
+        // a fake executor whose network fails on every batch
+        let (sender, executor) =
+            fake_executor(1, |_| Err(EvalError::Network("out of memory".to_string())));
+
+        let settings = SearchSettings {
+            fpu: 0.0,
+            wdl: None,
+            moves_left: None,
+            c_puct: 2.0,
+            max_nodes: 10,
+            alpha: 0.3,
+            eps: 0.3,
+            search_type: TypeRequest::NonTrainerSearch,
+            pst: 1.2,
+            score_model: ScoreModel::Piecewise,
+            encoder: InputEncoder::V1,
+            mirror: false,
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut client = EvalClient::new(sender, InputEncoder::V1);
+        let mut tree = Tree::new(BoardStack::new(Board::default()), settings);
+        let result = rt.block_on(extend_search(
+            &mut tree,
+            &mut client,
+            10,
+            std::time::Instant::now(),
+            1,
+        ));
+        assert_eq!(
+            result,
+            Err(EvalError::Network("out of memory".to_string()))
+        );
+        // the failed step left the tree as it was
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree.nodes[0].visits, 0);
+        drop(client);
+        executor.join().unwrap();
+    }
+
+    #[test]
+    fn test_root_moves() {
+        use crate::encoder::InputEncoder;
+        use crate::executor::EvalClient;
+        use crate::mcts::extend_search;
+        use crate::mcts_trainer::{Tree, TypeRequest};
+        use crate::scoremodel::ScoreModel;
+        use crate::settings::{RootMoves, SearchSettings};
+        use cozy_chess::Move;
+
+        let mv = |s: &str| s.parse::<Move>().unwrap();
+        let only = RootMoves::Only(vec![mv("e2e4"), mv("d2d4")]);
+        assert!(only.allows(mv("e2e4")));
+        assert!(!only.allows(mv("g1f3")));
+        let exclude = RootMoves::Exclude(vec![mv("e2e4")]);
+        assert!(!exclude.allows(mv("e2e4")));
+        assert!(exclude.allows(mv("g1f3")));
+        assert!(RootMoves::All.allows(mv("e2e4")));
+
+        let (sender, executor) = fake_executor(1, uniform_output);
+        let settings = SearchSettings {
+            fpu: 0.0,
+            wdl: None,
+            moves_left: None,
+            c_puct: 2.0,
+            max_nodes: 40,
+            alpha: 0.3,
+            eps: 0.3,
+            search_type: TypeRequest::NonTrainerSearch,
+            pst: 1.0,
+            score_model: ScoreModel::Piecewise,
+            encoder: InputEncoder::V1,
+            mirror: false,
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut client = EvalClient::new(sender, InputEncoder::V1);
+        let mut search = |root_moves: RootMoves| {
+            let mut tree = Tree::new(BoardStack::new(Board::default()), settings);
+            tree.root_moves = root_moves;
+            rt.block_on(extend_search(
+                &mut tree,
+                &mut client,
+                40,
+                std::time::Instant::now(),
+                1,
+            ))
+            .unwrap();
+            tree
+        };
+        let visited = |tree: &Tree| -> Vec<Move> {
+            tree.nodes[0]
+                .children
+                .clone()
+                .filter(|&child| tree.nodes[child].visits > 0)
+                .map(|child| tree.nodes[child].mv.unwrap())
+                .collect()
+        };
+
+        // only the searchmoves are visited, their priors are renormalised and the rest are zeroed
+        let tree = search(only.clone());
+        let moves = visited(&tree);
+        assert_eq!(moves.len(), 2);
+        assert!(moves.contains(&mv("e2e4")) && moves.contains(&mv("d2d4")));
+        for child in tree.nodes[0].children.clone() {
+            let expected = match tree.nodes[child].mv.unwrap() {
+                m if m == mv("e2e4") || m == mv("d2d4") => 0.5,
+                _ => 0.0,
+            };
+            assert!((tree.nodes[child].policy - expected).abs() < 1e-6);
+        }
+        // the network priors still cover every legal move
+        assert_eq!(tree.root_priors.len(), 20);
+
+        // a filter without any legal move is caught before the search, which refuses it
+        let board = BoardStack::new(Board::default());
+        assert!(only.allows_any(&board));
+        assert!(!RootMoves::Only(vec![mv("e2e5")]).allows_any(&board));
+        assert!(RootMoves::Exclude(vec![mv("e2e4")]).allows_any(&board));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            search(RootMoves::Only(vec![mv("e2e5")]))
+        }));
+        assert!(result.is_err());
+
+        drop(client);
+        executor.join().unwrap();
+    }
+
+    #[test]
+    fn test_net_metadata() {
+        use crate::encoder::InputEncoder;
+        use crate::mapper::{AlphaZeroMapper, ChessStdMapper};
+        use crate::netinfo::{check_output_shapes, NetMetadata};
+
+        let metadata = NetMetadata::from_json(
+            r#"{"name": "tz_6515", "input_encoder": {"V2": {"history": 2}}, "policy_len": 1880}"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("tz_6515"));
+        let encoder = InputEncoder::V2 { history: 2 };
+        assert!(metadata.check(encoder, ChessStdMapper::get()).is_ok());
+        assert!(metadata.check(InputEncoder::V1, ChessStdMapper::get()).is_err());
+        assert!(metadata.check(encoder, &AlphaZeroMapper).is_err());
+        // missing fields are not checked
+        assert_eq!(NetMetadata::from_json("{}").unwrap(), NetMetadata::default());
+        assert!(NetMetadata::from_json(r#"{"policy_len": "all"}"#).is_err());
+
+        let path = std::env::temp_dir().join("tz_test_metadata.pt");
+        let path = path.to_str().unwrap();
+        assert_eq!(NetMetadata::read_sidecar(path), Ok(None));
+        std::fs::write(NetMetadata::sidecar_path(path), r#"{"policy_len": 1858}"#).unwrap();
+        let sidecar = NetMetadata::read_sidecar(path).unwrap().unwrap();
+        assert_eq!(sidecar.policy_len, Some(1858));
+        assert!(sidecar.check(InputEncoder::V1, ChessStdMapper::get()).is_err());
+        std::fs::remove_file(NetMetadata::sidecar_path(path)).unwrap();
+
+        assert!(check_output_shapes(&[2, 5], &[2, 1880], 2, 1880).is_ok());
+        assert!(check_output_shapes(&[2, 5], &[2, 73, 8, 8], 2, 73 * 64).is_ok());
+        // a missing batch dimension, a missing moves left output and the wrong policy size
+        assert!(check_output_shapes(&[10], &[2, 1880], 2, 1880).is_err());
+        assert!(check_output_shapes(&[2, 4], &[2, 1880], 2, 1880).is_err());
+        assert!(check_output_shapes(&[2, 5], &[2, 1858], 2, 1880).is_err());
+    }
+
+    #[test]
+    fn test_net_config() {
+        use crate::netconfig::{NetConfig, NetDevice};
+        use tch::{Device, Kind};
+
+        for (s, device) in [
+            ("auto", NetDevice::Auto),
+            ("cpu", NetDevice::Cpu),
+            ("cuda:1", NetDevice::Cuda(1)),
+        ] {
+            assert_eq!(s.parse::<NetDevice>(), Ok(device));
+            assert_eq!(device.to_string(), s);
+        }
+        assert_eq!("CUDA".parse::<NetDevice>(), Ok(NetDevice::Cuda(0)));
+        assert!("cuda:x".parse::<NetDevice>().is_err());
+        assert!("tpu".parse::<NetDevice>().is_err());
+        assert_eq!(NetDevice::Cuda(2).device(), Device::Cuda(2));
+
+        // half precision is only used on cuda
+        let config = NetConfig {
+            half_precision: true,
+            ..NetConfig::default()
+        };
+        assert_eq!(config.kind(Device::Cuda(0)), Kind::Half);
+        assert_eq!(config.kind(Device::Cpu), Kind::Float);
+        assert_eq!(NetConfig::default().kind(Device::Cuda(0)), Kind::Float);
+
+        // the inter-op threads are set once per process
+        let threads = |inter_op_threads| NetConfig {
+            inter_op_threads,
+            ..NetConfig::default()
+        };
+        assert_eq!(threads(Some(2)).apply_threads(), Ok(()));
+        assert_eq!(threads(Some(2)).apply_threads(), Ok(()));
+        assert_eq!(threads(None).apply_threads(), Ok(()));
+        assert!(threads(Some(3)).apply_threads().is_err());
+    }
+
+    #[test]
+    fn test_mirror_eval() {
+        use crate::decoder::{legal_policy_indices, NetOutput};
+        use crate::encoder::InputEncoder;
+        use crate::evalcache::{CacheKey, EvalCache};
+        use crate::executor::EvalClient;
+        use crate::mirror::{can_mirror, mirror_input, mirror_move, mirrored_policy_indices};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // the same positions with the files mirrored
+        let pairs = [
+            (
+                "4k3/8/8/3p4/8/8/1P6/R3K3 w - - 0 1",
+                "3k4/8/8/4p3/8/8/6P1/3K3R w - - 0 1",
+            ),
+            (
+                "4k3/8/8/8/2Pp4/8/8/R3K3 b - c3 0 1",
+                "3k4/8/8/8/4pP2/8/8/3K3R b - f3 0 1",
+            ),
+        ];
+        let encoder = InputEncoder::V2 { history: 1 };
+        for (fen, mirrored_fen) in pairs {
+            let bs = BoardStack::new(Board::from_fen(fen, false).unwrap());
+            let mirrored = BoardStack::new(Board::from_fen(mirrored_fen, false).unwrap());
+            assert!(can_mirror(bs.board()));
+            let input = encoder.encode_packed(&bs);
+            assert_eq!(mirror_input(&input), encoder.encode_packed(&mirrored));
+            assert_eq!(mirror_input(&mirror_input(&input)), input);
+
+            // the mirrored indices are those of the mirrored moves in the mirrored position
+            let (moves, _) = legal_policy_indices(&bs);
+            let (mirrored_moves, mirrored_indices) = legal_policy_indices(&mirrored);
+            let indices = mirrored_policy_indices(bs.board(), &moves);
+            for (&mv, &index) in moves.iter().zip(&indices) {
+                let i = mirrored_moves.iter().position(|&m| m == mirror_move(mv)).unwrap();
+                assert_eq!(mirrored_indices[i], index);
+            }
+        }
+        assert!(!can_mirror(&Board::default()));
+
+        // a position and its mirror share a cache entry, the priors follow the moves
+        let (fen, mirrored_fen) = pairs[0];
+        let bs = BoardStack::new(Board::from_fen(fen, false).unwrap());
+        let mirrored = BoardStack::new(Board::from_fen(mirrored_fen, false).unwrap());
+        let input = InputEncoder::V1.encode_packed(&bs);
+        let mirrored_input = InputEncoder::V1.encode_packed(&mirrored);
+        let (key, is_mirrored) = CacheKey::canonical(&input, Some(&mirrored_input));
+        let (mirrored_key, mirrored_is_mirrored) =
+            CacheKey::canonical(&mirrored_input, Some(&input));
+        assert_eq!(key, mirrored_key);
+        assert_ne!(is_mirrored, mirrored_is_mirrored);
+
+        let (moves, indices) = legal_policy_indices(&bs);
+        let n = moves.len();
+        let output = NetOutput {
+            value: 0.25,
+            wdl: [0.5, 0.25, 0.25],
+            moves_left: 40.0,
+            policy: (0..n).map(|i| i as f32 / n as f32).collect(),
+        };
+        let cache = EvalCache::new(4);
+        let from = match is_mirrored {
+            true => mirrored_policy_indices(bs.board(), &moves),
+            false => indices,
+        };
+        cache.insert(key.clone(), &output, &from, 0);
+        let (mirrored_moves, mirrored_indices) = legal_policy_indices(&mirrored);
+        let to = match mirrored_is_mirrored {
+            true => mirrored_policy_indices(mirrored.board(), &mirrored_moves),
+            false => mirrored_indices,
+        };
+        let hit = cache.get(&key, &to).unwrap();
+        assert_eq!(hit.value, output.value);
+        for (mv, prior) in mirrored_moves.iter().zip(&hit.policy) {
+            let i = moves.iter().position(|&m| mirror_move(m) == *mv).unwrap();
+            assert_eq!(*prior, output.policy[i]);
+        }
+        assert_eq!(cache.len(), 1);
+
+        // the client averages both orientations and answers the mirror image from its cache
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let (sender, executor) = fake_executor(2, move |request| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            let output = uniform_output(request)?;
+            Ok(NetOutput {
+                value: request.id as f32, // 0 and 1 for the two orientations
+                ..output
+            })
+        });
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut client = EvalClient::new(sender, InputEncoder::V1)
+            .with_mirror(true)
+            .with_cache(Arc::new(EvalCache::new(16)));
+        let output = rt.block_on(client.evaluate(&bs)).unwrap();
+        assert_eq!(output.value, 0.5);
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        let cached = rt.block_on(client.evaluate(&mirrored)).unwrap();
+        assert_eq!(cached.value, 0.5);
+        assert_eq!(cached.policy.len(), output.policy.len());
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        // positions with castling rights are evaluated once
+        rt.block_on(client.evaluate(&BoardStack::new(Board::default()))).unwrap();
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+        drop(client);
+        executor.join().unwrap();
+
+        // without mirror evaluation a position and its mirror image are cached apart
+        let (sender, executor) = fake_executor(1, uniform_output);
+        let cache = Arc::new(EvalCache::new(16));
+        let mut client = EvalClient::new(sender, InputEncoder::V1).with_cache(cache.clone());
+        rt.block_on(client.evaluate(&bs)).unwrap();
+        rt.block_on(client.evaluate(&mirrored)).unwrap();
+        rt.block_on(client.evaluate(&bs)).unwrap();
+        assert_eq!(cache.len(), 2);
+        drop(client);
+        executor.join().unwrap();
+    }
+
+    #[test]
+    fn test_executor_pool() {
+        use crate::decoder::NetOutput;
+        use crate::encoder::{EncodedInput, InputEncoder};
+        use crate::executor::{
+            pick_batch, serve_pool, EvalError, EvalRequest, ExecutorPool, NetQueue, PoolBackend,
+        };
+        use crate::netconfig::{NetConfig, NetDevice};
+        use std::sync::{Arc, Mutex};
+
+        // the networks with queued requests take turns, a full batch does not jump ahead
+        let batches = [3, 8, 0, 8];
+        assert_eq!(pick_batch(&batches, 0), Some(0));
+        assert_eq!(pick_batch(&batches, 1), Some(1));
+        assert_eq!(pick_batch(&batches, 2), Some(3));
+        assert_eq!(pick_batch(&[0, 3, 2], 2), Some(2));
+        assert_eq!(pick_batch(&[0, 0], 1), None);
+        assert_eq!(pick_batch(&[], 0), None);
+
+        // fake networks that answer with their own value and record the batches they run
+        struct FakeNet {
+            value: f32,
+            capacity: usize,
+            len: usize,
+            runs: Arc<Mutex<Vec<(f32, usize)>>>,
+        }
+        impl PoolBackend for FakeNet {
+            fn len(&self) -> usize {
+                self.len
+            }
+            fn is_full(&self) -> bool {
+                self.len == self.capacity
+            }
+            fn push(&mut self, _input: &EncodedInput) {
+                self.len += 1;
+            }
+            fn run(&mut self, legal_indices: &[&[usize]]) -> Result<Vec<NetOutput>, EvalError> {
+                self.runs.lock().unwrap().push((self.value, self.len));
+                self.len = 0;
+                Ok(legal_indices
+                    .iter()
+                    .map(|indices| NetOutput {
+                        value: self.value,
+                        wdl: [0.2, 0.6, 0.2],
+                        moves_left: 50.0,
+                        policy: vec![1.0 / indices.len() as f32; indices.len()],
+                    })
+                    .collect())
+            }
+        }
+
+        // network a is busy with three full batches, b has a single request and c did not load
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        let input = InputEncoder::V1.encode_packed(&BoardStack::new(Board::default()));
+        let (response_send, response_recv) = flume::unbounded();
+        let mut queues = Vec::new();
+        for (value, requests) in [(0.0, 12), (1.0, 1), (2.0, 1)] {
+            let (sender, receiver) = flume::unbounded::<EvalRequest>();
+            for i in 0..requests {
+                let request = EvalRequest {
+                    id: value as u64 * 100 + i,
+                    input: input.clone(),
+                    legal_indices: vec![0, 1],
+                    resender: response_send.clone(),
+                };
+                sender.send(request).unwrap();
+            }
+            let backend: Result<Box<dyn PoolBackend>, String> = if value < 2.0 {
+                Ok(Box::new(FakeNet {
+                    value,
+                    capacity: 4,
+                    len: 0,
+                    runs: runs.clone(),
+                }))
+            } else {
+                Err("network c: no such file".to_string())
+            };
+            queues.push(NetQueue::new(receiver, backend));
+        }
+        drop(response_send);
+        let (_ctrl_sender, ctrl_receiver) = flume::bounded(1);
+        // returns once every request is answered, the request senders are gone
+        serve_pool(queues, ctrl_receiver, "test-pool");
+
+        assert_eq!(
+            *runs.lock().unwrap(),
+            vec![(0.0, 4), (1.0, 1), (0.0, 4), (0.0, 4)]
+        );
+        let responses: Vec<_> = response_recv.iter().collect();
+        assert_eq!(responses.len(), 14);
+        for response in responses {
+            match response.id / 100 {
+                2 => assert_eq!(
+                    response.result,
+                    Err(EvalError::NoNetwork("network c: no such file".to_string()))
+                ),
+                network => assert_eq!(response.result.unwrap().value, network as f32),
+            }
+        }
+
+        // ids must be unique
+        let mut pool = ExecutorPool::new(
+            8,
+            NetConfig {
+                device: NetDevice::Cpu,
+                ..NetConfig::default()
+            },
+        );
+        let _sender = pool.add_network("a", "a.pt", InputEncoder::default());
+        let duplicate = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pool.add_network("a", "b.pt", InputEncoder::default())
+        }));
+        assert!(duplicate.is_err());
+
+        // a pool without networks stops when its control channel closes
+        let (ctrl_sender, ctrl_receiver) = flume::bounded(1);
+        let pool = ExecutorPool::new(8, NetConfig::default());
+        let handle = std::thread::spawn(move || pool.run(ctrl_receiver));
+        drop(ctrl_sender);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_network_swap() {
+        use crate::decoder::NetOutput;
+        use crate::encoder::InputEncoder;
+        use crate::evalcache::{CacheKey, EvalCache};
+        use crate::executor::{EvalClient, EvalResponse};
+        use crate::pgn::simulation_to_pgn;
+        use crate::provenance::{NetSwitch, Provenance};
+        use std::sync::Arc;
+
+        // the cache keeps the newest network, late results of an older one are ignored
+        let bs = BoardStack::new(Board::default());
+        let key = CacheKey::new(&InputEncoder::V1.encode_packed(&bs));
+        let output = |value: f32| NetOutput {
+            value,
+            wdl: [0.2, 0.6, 0.2],
+            moves_left: 50.0,
+            policy: vec![0.5, 0.5],
+        };
+        let cache = EvalCache::new(4);
+        cache.insert(key.clone(), &output(0.1), &[0, 1], 1);
+        cache.insert(key.clone(), &output(0.2), &[0, 1], 2);
+        assert_eq!(cache.get(&key, &[0, 1]).unwrap().value, 0.2);
+        cache.insert(
+            CacheKey::new(&InputEncoder::V2 { history: 1 }.encode_packed(&bs)),
+            &output(0.3),
+            &[0, 1],
+            1,
+        );
+        assert_eq!(cache.len(), 1);
+
+        // a mirrored evaluation that straddles a swap only keeps the result of the new network
+        let (sender, executor) = fake_executor_with(2, |request| {
+            let output = uniform_output(request).unwrap();
+            vec![EvalResponse {
+                id: request.id,
+                generation: request.id + 3, // the mirror image is evaluated after the swap
+                result: Ok(NetOutput {
+                    value: request.id as f32,
+                    ..output
+                }),
+            }]
+        });
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let cache = Arc::new(EvalCache::new(16));
+        let mut client = EvalClient::new(sender, InputEncoder::V1)
+            .with_mirror(true)
+            .with_cache(cache.clone());
+        let bs =
+            BoardStack::new(Board::from_fen("4k3/8/8/3p4/8/8/1P6/R3K3 w - - 0 1", false).unwrap());
+        assert_eq!(rt.block_on(client.evaluate(&bs)).unwrap().value, 1.0);
+        assert_eq!(client.generation(), 4);
+        assert_eq!(cache.len(), 1);
+        drop(client);
+        executor.join().unwrap();
+
+        // an executor that has not swapped yet answers after a newer network did, the position is sent again
+        let generations = [2, 1, 2];
+        let (sender, executor) = fake_executor_with(1, move |request| {
+            let output = uniform_output(request).unwrap();
+            vec![EvalResponse {
+                id: request.id,
+                generation: generations[request.id as usize],
+                result: Ok(NetOutput {
+                    value: request.id as f32,
+                    ..output
+                }),
+            }]
+        });
+        let mut client = EvalClient::new(sender, InputEncoder::V1);
+        assert_eq!(rt.block_on(client.evaluate(&bs)).unwrap().value, 0.0);
+        assert_eq!(rt.block_on(client.evaluate(&bs)).unwrap().value, 2.0);
+        assert_eq!(client.generation(), 2);
+        drop(client);
+        executor.join().unwrap();
+
+        // the switch is kept in the provenance and shown in the PGN
+        let mut sim = test_simulation(&["e2e4", "e7e5", "g1f3"]);
+        let provenance = sim.provenance.as_mut().unwrap();
+        provenance.net_switches.push(NetSwitch {
+            ply: 1,
+            generation: 4,
+        });
+        let json = serde_json::to_string(&provenance).unwrap();
+        assert_eq!(
+            &serde_json::from_str::<Provenance>(&json).unwrap(),
+            provenance
+        );
+        let old_json = json.replace(r#","net_switches":[{"ply":1,"generation":4}]"#, "");
+        assert_ne!(old_json, json);
+        assert!(serde_json::from_str::<Provenance>(&old_json)
+            .unwrap()
+            .net_switches
+            .is_empty());
+        let movetext = simulation_to_pgn(&sim, &[])
+            .split("\n\n")
+            .nth(1)
+            .unwrap()
+            .replace('\n', " ");
+        assert!(movetext.contains("1... e5 {network switched to generation 4 N="));
+        assert_eq!(movetext.matches("network switched").count(), 1);
+    }
+
+    #[test]
+    fn test_xboard_time_control() {
+        use crate::xboard::TimeControl;
+
+        let bs = BoardStack::new(Board::default());
+        let mut time_control = TimeControl::new();
+        assert_eq!(time_control.nodes(&bs), 1);
+        // 40 moves in 5 minutes
+        time_control.set_level(&["level", "40", "5", "0"]);
+        assert_eq!(time_control.nodes(&bs), (7500 - 5) / 120);
+        // 2:30 for the game with a 2 second increment, a short command is ignored
+        time_control.set_level(&["level", "0", "2:30", "2"]);
+        time_control.set_level(&["level", "40"]);
+        assert_eq!(time_control.nodes(&bs), (5000 + 1500 - 5) / 120);
+    }
+
+    #[test]
+    fn test_xboard_game() {
+        use crate::xboard::GameState;
+        use cozy_chess::Move;
+
+        let mv = |s: &str| s.parse::<Move>().unwrap();
+        let mut game = GameState::new(Board::default());
+        assert_eq!(game.parse_move("e2e4"), Some(mv("e2e4")));
+        assert_eq!(game.parse_move("e2e5"), None);
+        assert_eq!(game.parse_move("nonsense"), None);
+        game.play(mv("e2e4"));
+        let after_e4 = game.bs.board().clone();
+        game.play(mv("e7e5"));
+        game.undo();
+        assert_eq!(game.bs.board(), &after_e4);
+        game.undo();
+        game.undo(); // nothing left to take back
+        assert_eq!(game.bs.board(), &Board::default());
+
+        // castling is given as the king's move and played as king takes rook
+        let castling = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", false).unwrap();
+        let game = GameState::new(castling);
+        assert_eq!(game.parse_move("e1g1"), Some(mv("e1h1")));
+        assert_eq!(game.parse_move("e1c1"), Some(mv("e1a1")));
+
+        let mut game = GameState::new(Board::default());
+        for m in ["f2f3", "e7e5", "g2g4"] {
+            game.play(mv(m));
+            assert_eq!(game.result(), None);
+        }
+        game.play(mv("d8h4"));
+        assert_eq!(game.result(), Some("0-1 {Black mates}"));
+    }
+
+    #[test]
+    fn test_xboard_commands() {
+        use crate::encoder::InputEncoder;
+        use crate::executor::EvalClient;
+        use crate::xboard::XBoard;
+        use cozy_chess::Color;
+
+        let (sender, executor) = fake_executor(1, uniform_output);
+        let mut xboard = XBoard::new(EvalClient::new(sender, InputEncoder::V1));
+        let fen = |xboard: &XBoard| xboard.game.bs.board().to_string();
+
+        // in force mode the engine only keeps track of the moves
+        assert!(xboard.handle_command("force"));
+        assert_eq!(xboard.engine_color, None);
+        xboard.handle_command("usermove e2e4");
+        xboard.handle_command("e7e5"); // protocol version 1 moves have no prefix
+        xboard.handle_command("usermove e2e5");
+        assert_eq!(
+            fen(&xboard),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+        );
+        xboard.handle_command("undo");
+        assert_eq!(xboard.game.bs.board().side_to_move(), Color::Black);
+        xboard.handle_command("remove");
+        assert_eq!(fen(&xboard), Board::default().to_string());
+        xboard.handle_command("setboard 4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        assert_eq!(fen(&xboard), "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        xboard.handle_command("setboard not a fen");
+        assert_eq!(fen(&xboard), "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+
+        // after `new` the engine plays black and answers the user's move right away
+        xboard.handle_command("new");
+        assert_eq!(xboard.engine_color, Some(Color::Black));
+        xboard.handle_command("st 1");
+        xboard.handle_command("usermove d2d4");
+        assert_eq!(xboard.game.bs.board().side_to_move(), Color::White);
+        assert_eq!(xboard.game.bs.board().fullmove_number(), 2);
+        // `go` makes the engine play the side to move
+        xboard.handle_command("force");
+        xboard.handle_command("usermove g1f3");
+        xboard.handle_command("go");
+        assert_eq!(xboard.engine_color, Some(Color::Black));
+        assert_eq!(xboard.game.bs.board().side_to_move(), Color::White);
+
+        assert!(!xboard.handle_command("quit"));
+        drop(xboard);
+        executor.join().unwrap();
+    }
+
+    #[test]
+    fn test_analysis_requests() {
+        use crate::analysis::{serve_analysis, AnalysisResponse, SearchSummary};
+        use tokio::io::AsyncReadExt;
+
+        let requests = [
+            "not json",
+            r#"{"id": 1, "fen": "not a fen", "nodes": 10}"#,
+            r#"{"id": 2, "moves": ["e2e4", "e2e4"], "nodes": 10}"#,
+            r#"{"id": 3, "nodes": 10, "search_moves": ["e2e4"], "exclude_moves": ["d2d4"]}"#,
+            r#"{"id": 4, "fen": "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3", "nodes": 10}"#,
+            r#"{"id": 5, "moves": ["e2e4"], "nodes": 20, "progress_interval": 10, "search_moves": ["e7e5", "c7c5"]}"#,
+            r#"{"id": 6, "fen": "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "nodes": 20, "exclude_moves": ["e1g1"], "settings": {"c_puct": 2.0}}"#,
+            r#"{"id": 7, "fen": "k7/8/8/7p/7P/8/8/K7 w - - 0 1", "nodes": 10, "exclude_moves": ["a1a2", "a1b1", "a1b2"]}"#,
+            r#"{"id": 8, "nodes": 10, "search_moves": ["e2e4", "e2e5"]}"#,
+            r#"{"id": 9, "nodes": 10, "exclude_moves": ["e7e5"]}"#,
+        ]
+        .join("\n");
+
+        let (sender, executor) = fake_executor(1, uniform_output);
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let output = rt.block_on(async {
+            let (writer, mut reader) = tokio::io::duplex(1 << 16);
+            serve_analysis(requests.as_bytes(), writer, sender).await;
+            let mut output = String::new();
+            reader.read_to_string(&mut output).await.unwrap();
+            output
+        });
+        executor.join().unwrap();
+
+        let responses: Vec<AnalysisResponse> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let error = |id: Option<u64>| -> String {
+            responses
+                .iter()
+                .find_map(|response| match response {
+                    AnalysisResponse::Error { id: i, message } if *i == id => Some(message.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert!(error(None).starts_with("error deserialising request"));
+        assert!(error(Some(1)).starts_with("invalid fen not a fen"));
+        assert_eq!(error(Some(2)), "illegal move e2e4");
+        assert_eq!(
+            error(Some(3)),
+            "search_moves and exclude_moves can not both be given"
+        );
+        assert_eq!(error(Some(4)), "position is already game over");
+        assert_eq!(error(Some(7)), "no legal move is left to search");
+        assert_eq!(error(Some(8)), "illegal move e2e5");
+        assert_eq!(error(Some(9)), "illegal move e7e5");
+
+        let summaries = |id: u64| -> (Vec<&SearchSummary>, &SearchSummary) {
+            let progress = responses
+                .iter()
+                .filter_map(|response| match response {
+                    AnalysisResponse::Progress(summary) if summary.id == id => Some(summary),
+                    _ => None,
+                })
+                .collect();
+            let result = responses
+                .iter()
+                .find_map(|response| match response {
+                    AnalysisResponse::Result(summary) if summary.id == id => Some(summary),
+                    _ => None,
+                })
+                .unwrap();
+            (progress, result)
+        };
+
+        // one progress update halfway, only the searchmoves are visited
+        let (progress, result) = summaries(5);
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].nodes, 10);
+        assert_eq!(result.nodes, 20);
+        assert!(["e7e5", "c7c5"].contains(&result.best_move.as_str()));
+        assert_eq!(result.moves.len(), 20);
+        for stats in &result.moves {
+            if !["e7e5", "c7c5"].contains(&stats.mv.as_str()) {
+                assert_eq!(stats.n, 0);
+            }
+        }
+
+        // castling is given as the king's move in both directions
+        let (progress, result) = summaries(6);
+        assert!(progress.is_empty());
+        assert_eq!(result.nodes, 20);
+        let castling = result.moves.iter().find(|m| m.mv == "e1g1").unwrap();
+        assert_eq!(castling.n, 0);
+        assert!(result.moves.iter().any(|m| m.mv == "e1c1" && m.n > 0));
+    }
 }
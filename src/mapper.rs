@@ -0,0 +1,204 @@
+use cozy_chess::{Board, Color, Move, Piece, Rank, Square};
+use lazy_static::lazy_static;
+
+use crate::mvs::get_contents;
+
+/// converts between legal moves and indices into the policy output of the network
+///
+/// moves are given in board coordinates, for black the board is mirrored before indexing
+/// so the network always sees the position from the side to move
+pub trait MoveMapper: Sync {
+    fn policy_shape(&self) -> Vec<usize>;
+
+    fn policy_len(&self) -> usize {
+        self.policy_shape().iter().product()
+    }
+
+    /// the policy index of `mv`, None if the layout has no entry for it
+    fn move_to_index(&self, board: &Board, mv: Move) -> Option<usize>;
+
+    /// the move at `index`, None if the index is out of range or does not describe a move on this board
+    fn index_to_move(&self, board: &Board, index: usize) -> Option<Move>;
+}
+
+/// mirrors `mv` for black, so moves are seen from the side to move
+fn pov_move(board: &Board, mv: Move) -> Move {
+    match board.side_to_move() {
+        Color::White => mv,
+        Color::Black => Move {
+            from: mv.from.flip_rank(),
+            to: mv.to.flip_rank(),
+            promotion: mv.promotion,
+        },
+    }
+}
+
+/// sentinel for moves that are not part of the layout
+const NO_INDEX: u16 = u16::MAX;
+
+/// the 1880 move layout of `mvs.rs`
+pub struct ChessStdMapper {
+    // indexed by promotion, from and to square, see `slot`
+    indices: Vec<u16>,
+}
+
+lazy_static! {
+    static ref CHESS_STD_MAPPER: ChessStdMapper = ChessStdMapper::build();
+}
+
+impl ChessStdMapper {
+    /// the shared instance, the lookup table is built on first use
+    pub fn get() -> &'static ChessStdMapper {
+        &CHESS_STD_MAPPER
+    }
+
+    fn build() -> ChessStdMapper {
+        let mut indices = vec![NO_INDEX; 6 * 64 * 64];
+        for (index, &mv) in get_contents().iter().enumerate() {
+            // keep the first entry, same as the linear search this replaces
+            let slot = &mut indices[Self::slot(mv)];
+            if *slot == NO_INDEX {
+                *slot = index as u16;
+            }
+        }
+        ChessStdMapper { indices }
+    }
+
+    fn slot(mv: Move) -> usize {
+        let promotion = match mv.promotion {
+            None => 0,
+            Some(Piece::Knight) => 1,
+            Some(Piece::Bishop) => 2,
+            Some(Piece::Rook) => 3,
+            Some(Piece::Queen) => 4,
+            Some(_) => 5, // never legal, always empty
+        };
+        (promotion * 64 + mv.from as usize) * 64 + mv.to as usize
+    }
+}
+
+impl MoveMapper for ChessStdMapper {
+    fn policy_shape(&self) -> Vec<usize> {
+        vec![get_contents().len()]
+    }
+
+    fn move_to_index(&self, board: &Board, mv: Move) -> Option<usize> {
+        match self.indices[Self::slot(pov_move(board, mv))] {
+            NO_INDEX => None,
+            index => Some(index as usize),
+        }
+    }
+
+    fn index_to_move(&self, board: &Board, index: usize) -> Option<Move> {
+        let mv = *get_contents().get(index)?;
+        Some(pov_move(board, mv))
+    }
+}
+
+/// (file, rank) steps of the queen planes, clockwise starting north
+const QUEEN_DIRECTIONS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+const KNIGHT_JUMPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+
+const UNDERPROMOTIONS: [Piece; 3] = [Piece::Knight, Piece::Bishop, Piece::Rook];
+
+const QUEEN_PLANES: usize = 8 * 7;
+const KNIGHT_PLANES: usize = 8;
+const UNDERPROMOTION_PLANES: usize = 3 * 3;
+
+/// the AlphaZero 73x8x8 layout: 56 queen move planes, 8 knight planes and 9 underpromotion planes,
+/// indexed by plane and from square
+///
+/// castling is king-takes-rook like cozy-chess, so it ends up in the queen planes,
+/// queen promotions use the queen planes as well
+pub struct AlphaZeroMapper;
+
+impl AlphaZeroMapper {
+    fn plane(mv: Move) -> Option<usize> {
+        let df = mv.to.file() as i8 - mv.from.file() as i8;
+        let dr = mv.to.rank() as i8 - mv.from.rank() as i8;
+
+        if let Some(piece) = mv.promotion.filter(|&p| p != Piece::Queen) {
+            let piece = UNDERPROMOTIONS.iter().position(|&p| p == piece)?;
+            if dr != 1 || df.abs() > 1 {
+                return None;
+            }
+            return Some(QUEEN_PLANES + KNIGHT_PLANES + piece * 3 + (df + 1) as usize);
+        }
+
+        if let Some(jump) = KNIGHT_JUMPS.iter().position(|&j| j == (df, dr)) {
+            return Some(QUEEN_PLANES + jump);
+        }
+
+        let distance = df.abs().max(dr.abs());
+        if distance == 0 || (df != 0 && dr != 0 && df.abs() != dr.abs()) {
+            return None;
+        }
+        let direction = QUEEN_DIRECTIONS
+            .iter()
+            .position(|&d| d == (df.signum(), dr.signum()))?;
+        Some(direction * 7 + (distance - 1) as usize)
+    }
+}
+
+impl MoveMapper for AlphaZeroMapper {
+    fn policy_shape(&self) -> Vec<usize> {
+        vec![QUEEN_PLANES + KNIGHT_PLANES + UNDERPROMOTION_PLANES, 8, 8]
+    }
+
+    fn move_to_index(&self, board: &Board, mv: Move) -> Option<usize> {
+        let mv = pov_move(board, mv);
+        Some(Self::plane(mv)? * 64 + mv.from as usize)
+    }
+
+    fn index_to_move(&self, board: &Board, index: usize) -> Option<Move> {
+        if index >= self.policy_len() {
+            return None;
+        }
+        let (plane, from) = (index / 64, Square::index(index % 64));
+
+        let (offset, promotion) = if plane < QUEEN_PLANES {
+            let (df, dr) = QUEEN_DIRECTIONS[plane / 7];
+            let distance = (plane % 7) as i8 + 1;
+            ((df * distance, dr * distance), None)
+        } else if plane < QUEEN_PLANES + KNIGHT_PLANES {
+            (KNIGHT_JUMPS[plane - QUEEN_PLANES], None)
+        } else {
+            let plane = plane - QUEEN_PLANES - KNIGHT_PLANES;
+            ((plane as i8 % 3 - 1, 1), Some(UNDERPROMOTIONS[plane / 3]))
+        };
+        let to = from.try_offset(offset.0, offset.1)?;
+
+        // pawns moving to the last rank in a queen plane promote to a queen
+        let mv = pov_move(
+            board,
+            Move {
+                from,
+                to,
+                promotion: None,
+            },
+        );
+        let queen_promotion =
+            to.rank() == Rank::Eighth && board.piece_on(mv.from) == Some(Piece::Pawn);
+        let promotion = promotion.or(queen_promotion.then_some(Piece::Queen));
+        Some(Move { promotion, ..mv })
+    }
+}
@@ -1,47 +1,85 @@
 use std::time::Instant;
 
 use crate::{
-    boardmanager::BoardStack, dataformat::ZeroEvaluation, executor::Packet, mcts_trainer::Tree,
-    settings::SearchSettings,
+    boardmanager::BoardStack,
+    dataformat::ZeroEvaluation,
+    executor::{EvalClient, EvalError, EvalRequest},
+    mcts_trainer::{get_move_with_client, Tree},
+    settings::{RootMoves, SearchSettings},
 };
-use cozy_chess::Move;
+use cozy_chess::{Color, Move};
 
 use flume::Sender;
 
 pub async fn get_move(
     bs: BoardStack,
-    tensor_exe_send: Sender<Packet>,
+    tensor_exe_send: Sender<EvalRequest>,
     settings: SearchSettings,
-) -> (
-    Move,
-    ZeroEvaluation,
-    Option<Vec<usize>>,
-    ZeroEvaluation,
-    u32,
-) {
-    let sw = Instant::now();
-    // non-generator version of mcts_trainer.rs
+) -> Result<
+    (
+        Move,
+        ZeroEvaluation,
+        Option<Vec<usize>>,
+        ZeroEvaluation,
+        u32,
+    ),
+    EvalError,
+> {
+    get_move_with_root_moves(bs, tensor_exe_send, settings, RootMoves::All).await
+}
 
+/// `get_move`, only searching the root moves allowed by `root_moves`
+pub async fn get_move_with_root_moves(
+    bs: BoardStack,
+    tensor_exe_send: Sender<EvalRequest>,
+    settings: SearchSettings,
+    root_moves: RootMoves,
+) -> Result<
+    (
+        Move,
+        ZeroEvaluation,
+        Option<Vec<usize>>,
+        ZeroEvaluation,
+        u32,
+    ),
+    EvalError,
+> {
     // most search code is located in mcts_trainer.rs
+    let mut client = EvalClient::for_search(tensor_exe_send, &settings);
+    get_move_with_client(bs, &mut client, settings, root_moves, 0).await
+}
 
-    let mut tree = Tree::new(bs, settings);
-    if tree.board.is_terminal() {
-        panic!("No valid move!/Board is already game over!");
-    }
-
-    while tree.nodes[0].visits < settings.max_nodes as u32 {
-        let thread_name = std::thread::current()
-            .name()
-            .unwrap_or("unnamed")
-            .to_owned();
+/// runs the search until the root has `max_nodes` visits, calling it again with a larger limit continues the search
+///
+/// `id` is only used for profiling output, which is printed when it is a multiple of 512 and `tree.profile` is set
+///
+/// stops at the first failed evaluation, the tree stays usable and the search can be extended again later
+pub async fn extend_search(
+    tree: &mut Tree,
+    client: &mut EvalClient,
+    max_nodes: u32,
+    sw: Instant,
+    id: usize,
+) -> Result<(), EvalError> {
+    while tree.nodes[0].visits < max_nodes {
         // println!("step {}", tree.nodes[0].visits);
-        // println!("thread {}, step {}",w thread_name, tree.nodes[0].visits);
 
-        tree.step(&tensor_exe_send, sw, 0).await;
+        tree.step(client, sw, id).await?;
         // println!("Elapsed time for step: {}ms", sw.elapsed().as_nanos() as f32 / 1e6);
     }
-    // println!("{}", tree.nodes[0].visits);
+    Ok(())
+}
 
+/// best move and root statistics of a finished search
+pub fn search_result(
+    tree: &Tree,
+) -> (
+    Move,
+    ZeroEvaluation,
+    Option<Vec<usize>>,
+    ZeroEvaluation,
+    u32,
+) {
     let mut child_visits: Vec<u32> = Vec::new();
 
     for child in tree.nodes[0].children.clone() {
@@ -101,21 +139,25 @@ pub async fn get_move(
     }
     // tree.nodes[0].display_full_tree(&tree);
 
-    let mut all_pol = Vec::new();
-
-    for child in tree.nodes[0].clone().children {
-        all_pol.push(tree.nodes[child].policy);
-    }
+    // the network WDL is from the side to move, ZeroEvaluation is from white's point of view
+    let net_wdl = match tree.board.board().side_to_move() {
+        Color::White => tree.nodes[0].wdl,
+        Color::Black => tree.nodes[0].wdl.flip(),
+    };
 
     let v_p = ZeroEvaluation {
         // network evaluation, NOT search/empirical data
         values: tree.nodes[0].eval_score,
-        policy: all_pol,
+        wdl: net_wdl,
+        moves_left: tree.nodes[0].moves_left,
+        policy: tree.root_priors.clone(),
     };
 
     let search_data = ZeroEvaluation {
         // search data
-        values: tree.nodes[0].get_q_val(settings),
+        values: tree.nodes[0].get_q_val(tree.settings),
+        wdl: tree.nodes[0].get_zero_wdl(),
+        moves_left: tree.nodes[0].get_zero_moves_left(),
         policy: pi,
     };
 
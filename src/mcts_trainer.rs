@@ -1,24 +1,27 @@
 use crate::{
-    boardmanager::BoardStack, dataformat::ZeroEvaluation, decoder::{convert_board, process_board_output}, dirichlet::StableDirichlet, executor::{Packet, ReturnMessage}, settings::SearchSettings, superluminal::{CL_GREEN, CL_PINK}, uci::eval_in_cp
+    boardmanager::BoardStack, dataformat::ZeroEvaluation, decoder::{legal_policy_indices, process_board_output}, dirichlet::StableDirichlet, encoder::InputEncoder, executor::{EvalClient, EvalError, EvalRequest}, mapper::ChessStdMapper, mcts::{extend_search, search_result}, netconfig::NetConfig, netinfo::{probe, NetMetadata}, settings::{RootMoves, SearchSettings}
 };
-use superluminal_perf::{begin_event_with_color, end_event};
 use cozy_chess::{Color, GameStatus, Move};
 use flume::Sender;
 use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::{
-    cmp::{max, min},
+    cmp::{max, min, Ordering},
     fmt,
     ops::Range,
     time::{Instant, SystemTime, UNIX_EPOCH},
 };
-use tch::{CModule, Device};
+use tch::{CModule, Device, Kind};
 
 pub struct Net {
     pub net: CModule,
     pub device: Device,
+    pub encoder: InputEncoder,
+    pub metadata: Option<NetMetadata>,
+    pub config: NetConfig,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 
 pub enum TypeRequest {
     TrainerSearch(Option<ExpansionType>),
@@ -27,7 +30,7 @@ pub enum TypeRequest {
 
     UCISearch,
 }
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ExpansionType {
     PolicyExpansion,
     RandomExpansion,
@@ -42,15 +45,60 @@ pub enum EvalMode {
 
 impl Net {
     pub fn new(path: &str) -> Self {
+        Self::with_encoder(path, InputEncoder::default())
+    }
+
+    /// loads a network that takes the inputs of `encoder`, panics if the network does not accept them
+    pub fn with_encoder(path: &str, encoder: InputEncoder) -> Self {
+        Self::try_with_encoder(path, encoder).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// `with_encoder`, returning an error if the network can not be loaded or its inputs or outputs do not match
+    pub fn try_with_encoder(path: &str, encoder: InputEncoder) -> Result<Self, String> {
+        Self::try_with_config(path, encoder, NetConfig::default())
+    }
+
+    /// loads a network on the device and with the precision and threads of `config`
+    ///
+    /// a dummy batch is run through the network to check the output shapes against the move layout
+    pub fn try_with_config(
+        path: &str,
+        encoder: InputEncoder,
+        config: NetConfig,
+    ) -> Result<Self, String> {
         // let path = "tz.pt";
         // // println!("{}", path);
-        let mut net = tch::CModule::load(path).expect("ERROR");
+        let device = config.device.device();
+        let mut net = tch::CModule::load_on_device(path, device)
+            .map_err(|e| format!("{}: can not be loaded on {}: {}", path, config.device, e))?;
         net.set_eval();
-        Self {
-            net: net,
-            // device: Device::Cpu,
-            device: Device::cuda_if_available(),
+        let kind = config.kind(device);
+        if kind != Kind::Float {
+            net.to(device, kind, false);
+        }
+        let mapper = ChessStdMapper::get();
+        let metadata = NetMetadata::read(path, &net).map_err(|e| format!("{}: {}", path, e))?;
+        if let Some(metadata) = &metadata {
+            metadata
+                .check(encoder, mapper)
+                .map_err(|e| format!("{}: {}", path, e))?;
         }
+        encoder
+            .check_network(&net)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        probe(&net, device, kind, encoder, mapper).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Self {
+            net: net,
+            device,
+            encoder,
+            metadata,
+            config,
+        })
+    }
+
+    /// the float type of the network inputs
+    pub fn kind(&self) -> Kind {
+        self.config.kind(self.device)
     }
 }
 
@@ -61,6 +109,12 @@ pub struct Tree {
     pub board: BoardStack,
     pub nodes: Vec<Node>,
     pub settings: SearchSettings,
+    // set before the first step, excluded root moves keep their node but are never visited
+    pub root_moves: RootMoves,
+    // the network priors of the root moves, before the temperature, noise and root move filter
+    pub root_priors: Vec<f32>,
+    // whether the timings of every 512th search id are printed to stdout
+    pub profile: bool,
 }
 
 impl Tree {
@@ -72,10 +126,46 @@ impl Tree {
             board,
             nodes: container,
             settings,
+            root_moves: RootMoves::All,
+            root_priors: Vec::new(),
+            profile: true,
         }
     }
 
-    pub async fn step(&mut self, tensor_exe_send: &Sender<Packet>, sw: Instant, id: usize) {
+    fn profiles(&self, id: usize) -> bool {
+        self.profile && id % 512 == 0
+    }
+
+    /// zeroes the policy of root moves that are not allowed and renormalises the rest
+    ///
+    /// callers check `RootMoves::allows_any` first, a filter without any legal move is a bug
+    fn filter_root_moves(&mut self) {
+        let allowed: Vec<usize> = self.nodes[0]
+            .children
+            .clone()
+            .filter(|&child| self.root_moves.allows(self.nodes[child].mv.expect("Error")))
+            .collect();
+        assert!(!allowed.is_empty(), "the root move filter allows no legal move");
+
+        let sum: f32 = allowed.iter().map(|&child| self.nodes[child].policy).sum();
+        for child in self.nodes[0].children.clone() {
+            self.nodes[child].policy = if !allowed.contains(&child) {
+                0.0
+            } else if sum > 0.0 {
+                self.nodes[child].policy / sum
+            } else {
+                1.0 / allowed.len() as f32
+            };
+        }
+    }
+
+    /// one playout, if the evaluation fails the tree is left unchanged
+    pub async fn step(
+        &mut self,
+        client: &mut EvalClient,
+        sw: Instant,
+        id: usize,
+    ) -> Result<(), EvalError> {
         // let sw = Instant::now();
         let display_str = self.display_node(0);
         // // println!("root node: {}", &display_str);
@@ -107,12 +197,17 @@ impl Tree {
         // check for terminal state
         if !input_b.is_terminal() {
             (selected_node, idx_li) = self
-                .eval_and_expand(selected_node, &input_b, &tensor_exe_send, id)
-                .await;
+                .eval_and_expand(selected_node, &input_b, client, id)
+                .await?;
 
             self.nodes[selected_node].move_idx = Some(idx_li);
             let mut legal_moves: Vec<Move>;
             if selected_node == 0 {
+                self.root_priors = self.nodes[0]
+                    .children
+                    .clone()
+                    .map(|child| self.nodes[child].policy)
+                    .collect();
                 legal_moves = Vec::new();
                 self.board.board().generate_moves(|moves| {
                     // Unpack dense move set into move list
@@ -149,6 +244,9 @@ impl Tree {
                     TypeRequest::SyntheticSearch => {}
                     TypeRequest::UCISearch => {}
                 }
+                if self.root_moves != RootMoves::All {
+                    self.filter_root_moves();
+                }
                 // self.nodes[0].display_full_tree(self);
             }
         } else {
@@ -161,7 +259,21 @@ impl Tree {
                 GameStatus::Ongoing => {
                     unreachable!()
                 }
-            }
+            };
+            // the side to move has lost or drawn
+            self.nodes[selected_node].wdl = match input_b.status() {
+                GameStatus::Won => Wdl {
+                    w: 0.0,
+                    d: 0.0,
+                    l: 1.0,
+                },
+                _ => Wdl {
+                    w: 0.0,
+                    d: 1.0,
+                    l: 0.0,
+                },
+            };
+            self.nodes[selected_node].moves_left = 0.0;
         }
         let now_start_proc = SystemTime::now();
         let since_epoch_proc = now_start_proc
@@ -169,13 +281,13 @@ impl Tree {
             .expect("Time went backwards");
 
         let epoch_seconds_start_proc = since_epoch_proc.as_nanos();
-        self.backpropagate(selected_node);
+        self.backpropagate(selected_node, input_b.board().side_to_move());
         let now_end_proc = SystemTime::now();
         let since_epoch_proc = now_end_proc
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
         let epoch_seconds_end_proc = since_epoch_proc.as_nanos();
-        if id % 512 == 0 {
+        if self.profiles(id) {
             println!(
                 "{} {} {} backprop_tree",
                 epoch_seconds_start_proc, epoch_seconds_end_proc, id
@@ -188,14 +300,19 @@ impl Tree {
         // self.nodes[0].display_full_tree(self);
         match self.settings.search_type {
             TypeRequest::UCISearch => {
-                let cp_eval = eval_in_cp(self.nodes[selected_node].eval_score);
+                // the net WDL is from the side to move, eval_score is from white
+                let cp_eval = self.settings.score_model.white_cp(
+                    self.nodes[selected_node].eval_score,
+                    Some(self.nodes[selected_node].wdl),
+                    input_b.board().side_to_move(),
+                );
                 let elapsed_ms = sw.elapsed().as_nanos() as f32 / 1e6;
                 let nps = self.nodes[0].visits as f32 / (sw.elapsed().as_nanos() as f32 / 1e9);
                 println!(
                     "info depth {} seldepth {} score cp {} nodes {} nps {} time {} pv {}",
                     min_depth,
                     max_depth,
-                    (cp_eval * 100.).round().max(-1000.).min(1000.) as i64,
+                    cp_eval,
                     self.nodes.len(),
                     nps as usize,
                     elapsed_ms as usize,
@@ -204,6 +321,7 @@ impl Tree {
             }
             _ => {}
         }
+        Ok(())
     }
     pub fn depth_range(&self, node: usize) -> (usize, usize) {
         match self.settings.search_type {
@@ -225,6 +343,33 @@ impl Tree {
             _ => (0, 0),
         }
     }
+    /// follows the most visited children from the root, using the policy as tiebreaker
+    pub fn principal_variation(&self) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut curr: usize = 0;
+        while !self.nodes[curr].children.is_empty() {
+            curr = self.nodes[curr]
+                .children
+                .clone()
+                .max_by(|a, b| {
+                    let a_node = &self.nodes[*a];
+                    let b_node = &self.nodes[*b];
+                    a_node.visits.cmp(&b_node.visits).then(
+                        a_node
+                            .policy
+                            .partial_cmp(&b_node.policy)
+                            .unwrap_or(Ordering::Equal),
+                    )
+                })
+                .expect("Error");
+            if self.nodes[curr].visits == 0 {
+                break;
+            }
+            pv.push(self.nodes[curr].mv.expect("Error"));
+        }
+        pv
+    }
+
     fn select(&mut self) -> (usize, BoardStack, String, (usize, usize)) {
         let mut curr: usize = 0;
         // println!("    selection:");
@@ -249,8 +394,12 @@ impl Tree {
                 total_visits += &self.nodes[child].visits;
             }
             (_, max_depth) = self.depth_range(curr);
+            let is_root = curr == 0;
             curr = children
                 .clone()
+                .filter(|&child| {
+                    !is_root || self.root_moves.allows(self.nodes[child].mv.expect("Error"))
+                })
                 .max_by(|a, b| {
                     let a_node = &self.nodes[*a];
                     let b_node = &self.nodes[*b];
@@ -300,104 +449,53 @@ impl Tree {
         &mut self,
         selected_node_idx: usize,
         bs: &BoardStack,
-        tensor_exe_send: &Sender<Packet>,
+        client: &mut EvalClient,
         id: usize,
-    ) -> (usize, Vec<usize>) {
-        let sw = Instant::now();
-        let fenstr = format!("{}", bs.board());
-        // // println!("    board FEN: {}", fenstr);P
-        // // println!("    ran NN:");
-
-        let input_tensor = convert_board(&bs);
-
-        // creating a send/recv pair for executor
-
-        let (resender_send, resender_recv) = flume::bounded::<ReturnMessage>(1); // mcts to executor
-        let thread_name = std::thread::current()
-            .name()
-            .unwrap_or("unnamed-generator")
-            .to_owned();
-        let pack = Packet {
-            job: input_tensor,
-            resender: resender_send,
-            id: thread_name.clone(),
-        };
-        // println!("pre-requesting eval {}", id);
-        let sw = Instant::now();
-        let now_start_send = SystemTime::now();
-        let since_epoch_send = now_start_send
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-
-        let epoch_seconds_start_send = since_epoch_send.as_nanos();
-        begin_event_with_color("send_req", CL_GREEN);
-        tensor_exe_send.send_async(pack).await.unwrap();
-        end_event();
-        let now_end_send = SystemTime::now();
-        let since_epoch_send = now_end_send
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        let epoch_seconds_end_send = since_epoch_send.as_nanos();
+    ) -> Result<(usize, Vec<usize>), EvalError> {
+        let (legal_moves, idx_li) = legal_policy_indices(bs);
 
-        if id % 512 == 0 {
-            println!(
-                "{} {} {} send_request",
-                epoch_seconds_start_send, epoch_seconds_end_send, id
-            );
-        }
-
-        let now_start_recv = SystemTime::now();
-        let since_epoch_recv = now_start_recv
+        let now_start_eval = SystemTime::now();
+        let since_epoch_eval = now_start_eval
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
-
-        let epoch_seconds_start_recv = since_epoch_recv.as_nanos();
-        begin_event_with_color("recv_request", CL_PINK);
-        let output = resender_recv.recv_async().await.unwrap();
-        end_event();
-        // println!("total_waiting_for_gpu_eval {}s", sw.elapsed().as_nanos() as f32 / 1e9);
-        let now_end_recv = SystemTime::now();
-        let since_epoch_recv = now_end_recv
+        let epoch_seconds_start_eval = since_epoch_eval.as_nanos();
+        let output = client.evaluate(bs).await?;
+        let now_end_eval = SystemTime::now();
+        let since_epoch_eval = now_end_eval
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
-        let epoch_seconds_end_recv = since_epoch_recv.as_nanos();
-        if id % 512 == 0 {
+        let epoch_seconds_end_eval = since_epoch_eval.as_nanos();
+        if self.profiles(id) {
             println!(
-                "{} {} {} recv_request",
-                epoch_seconds_start_recv, epoch_seconds_end_recv, id
+                "{} {} {} evaluate",
+                epoch_seconds_start_eval, epoch_seconds_end_eval, id
             );
-
-            // println!("THREAD ID {} CHANNEL_LEN {}", id, tensor_exe_send.len());
         }
-        let output = match output {
-            ReturnMessage::ReturnMessage(Ok(output)) => output,
-            ReturnMessage::ReturnMessage(Err(_)) => panic!("error in returning!"),
-        };
-        // // println!("{},{}", thread_name,output.id);
-        assert!(thread_name == output.id);
+
         let now_start_proc = SystemTime::now();
         let since_epoch_proc = now_start_proc
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
 
         let epoch_seconds_start_proc = since_epoch_proc.as_nanos();
-        let idx_li = process_board_output(output.packet, &selected_node_idx, self, &bs);
+        process_board_output(&output, &legal_moves, &selected_node_idx, self, &bs);
         let now_end_proc = SystemTime::now();
         let since_epoch_proc = now_end_proc
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
         let epoch_seconds_end_proc = since_epoch_proc.as_nanos();
-        if id % 512 == 0 {
+        if self.profiles(id) {
             println!(
                 "{} {} {} proc",
                 epoch_seconds_start_proc, epoch_seconds_end_proc, id
             );
         }
         // let idx_li = eval_board(&bs, &net, self, &selected_node_idx);
-        (selected_node_idx, idx_li)
+        Ok((selected_node_idx, idx_li))
     }
 
-    fn backpropagate(&mut self, node: usize) {
+    /// `stm` is the side to move at `node`, total_wdl is accumulated from white's point of view
+    fn backpropagate(&mut self, node: usize, stm: Color) {
         // println!("    backup:");
         let n: f32 = match self.settings.wdl {
             Some(_) => {
@@ -407,11 +505,21 @@ impl Tree {
             }
             None => self.nodes[node].eval_score,
         };
+        let wdl = match stm {
+            Color::White => self.nodes[node].wdl,
+            Color::Black => self.nodes[node].wdl.flip(),
+        };
+        let mut moves_left = self.nodes[node].moves_left;
         let mut curr: Option<usize> = Some(node); // used to index parent
                                                   // // println!("    curr: {:?}", curr);
         while let Some(current) = curr {
             self.nodes[current].visits += 1;
             self.nodes[current].total_action_value += n;
+            self.nodes[current].total_wdl.w += wdl.w;
+            self.nodes[current].total_wdl.d += wdl.d;
+            self.nodes[current].total_wdl.l += wdl.l;
+            self.nodes[current].moves_left_total += moves_left;
+            moves_left += 1.0; // one more move to go from the parent
             // // println!("    updated total action value: {}", self.nodes[current].total_action_value);
             curr = self.nodes[current].parent;
             // let display_str = self.display_node(current);
@@ -506,18 +614,47 @@ pub struct Node {
     pub moves_left_total: f32,
     pub move_idx: Option<Vec<usize>>,
 }
-#[derive(PartialEq, Clone, Debug, Copy)]
+#[derive(PartialEq, Clone, Debug, Copy, Serialize, Deserialize)]
 pub struct Wdl {
     pub w: f32,
     pub d: f32,
     pub l: f32,
 }
 
+impl Wdl {
+    /// the same result from the point of view of the other player
+    pub fn flip(self) -> Wdl {
+        Wdl {
+            w: self.l,
+            d: self.d,
+            l: self.w,
+        }
+    }
+
+    pub fn to_array(self) -> [f32; 3] {
+        [self.w, self.d, self.l]
+    }
+}
+
 impl Node {
     // fn is_leaf(&self) -> bool {
     //     self.visits == 0
     // }
 
+    /// average WDL of the search below this node, from white's point of view
+    pub fn get_zero_wdl(&self) -> Wdl {
+        let visits = self.visits as f32;
+        Wdl {
+            w: self.total_wdl.w / visits,
+            d: self.total_wdl.d / visits,
+            l: self.total_wdl.l / visits,
+        }
+    }
+
+    pub fn get_zero_moves_left(&self) -> f32 {
+        self.moves_left_total / self.visits as f32
+    }
+
     pub fn get_q_val(&self, settings: SearchSettings) -> f32 {
         let fpu = settings.fpu; // First Player Urgency
         if self.visits > 0 {
@@ -590,7 +727,7 @@ impl Node {
                 l: 0.0,
             },
             moves_left: f32::NAN,
-            moves_left_total: f32::NAN,
+            moves_left_total: 0.0,
         }
     }
 
@@ -618,141 +755,57 @@ impl Node {
     }
 }
 
+/// searches `bs` and returns the best move with the network and search evaluations, fails if an evaluation fails
 pub async fn get_move(
     bs: BoardStack,
-    tensor_exe_send: &Sender<Packet>,
+    tensor_exe_send: &Sender<EvalRequest>,
     settings: SearchSettings,
     id: usize,
-) -> (
-    Move,
-    ZeroEvaluation,
-    Option<Vec<usize>>,
-    ZeroEvaluation,
-    u32,
-) {
-    // equiv to move() in mcts_trainer.py
-
-    // load nn and pass to eval if needed
-
-    // let mut net = Net::new("chess_16x128_gen3634.pt");
-    // net.net.set_eval();
-    // net.net.to(net.device, Kind::Float, true);
-    // // println!("{:?}", &bs);
+) -> Result<
+    (
+        Move,
+        ZeroEvaluation,
+        Option<Vec<usize>>,
+        ZeroEvaluation,
+        u32,
+    ),
+    EvalError,
+> {
+    let mut client = EvalClient::for_search(tensor_exe_send.clone(), &settings);
+    get_move_with_client(bs, &mut client, settings, RootMoves::All, id).await
+}
+
+/// `get_move` evaluating through `client`, which tells afterwards which network answered
+///
+/// only the root moves allowed by `root_moves` are searched, the returned policies still cover every legal move,
+/// panics if `root_moves` allows no legal move
+pub async fn get_move_with_client(
+    bs: BoardStack,
+    client: &mut EvalClient,
+    settings: SearchSettings,
+    root_moves: RootMoves,
+    id: usize,
+) -> Result<
+    (
+        Move,
+        ZeroEvaluation,
+        Option<Vec<usize>>,
+        ZeroEvaluation,
+        u32,
+    ),
+    EvalError,
+> {
     let mut tree = Tree::new(bs, settings);
+    tree.root_moves = root_moves;
     if tree.board.is_terminal() {
         panic!("No valid move!/Board is already game over!");
     }
-
-    let search_type = TypeRequest::TrainerSearch;
+    assert!(
+        tree.root_moves.allows_any(&tree.board),
+        "the root move filter allows no legal move"
+    );
     let sw = Instant::now();
-    while tree.nodes[0].visits < settings.max_nodes as u32 {
-        let thread_name = std::thread::current()
-            .name()
-            .unwrap_or("unnamed")
-            .to_owned();
-        // println!("step {}", tree.nodes[0].visits);
-        // println!("thread {}, step {}", thread_name, tree.nodes[0].visits);
-        let now_start_proc = SystemTime::now();
-        let since_epoch_proc = now_start_proc
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-
-        let epoch_seconds_start_proc = since_epoch_proc.as_nanos();
-        tree.step(&tensor_exe_send, sw, id).await;
-        let now_end_proc = SystemTime::now();
-        let since_epoch_proc = now_end_proc
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        let epoch_seconds_end_proc = since_epoch_proc.as_nanos();
-        //     if id % 512 == 0 {
-        //     println!(
-        //         "{} {} {} step",
-        //         epoch_seconds_start_proc, epoch_seconds_end_proc, id
-        //     );
-        // }
-    }
-
-    let mut child_visits: Vec<u32> = Vec::new();
-
-    for child in tree.nodes[0].children.clone() {
-        child_visits.push(tree.nodes[child].visits);
-    }
-
-    let all_same = child_visits.iter().all(|&x| x == child_visits[0]);
-
-    let best_move_node = if !all_same {
-        // if visits to nodes are the same eg max_nodes=1
-        tree.nodes[0]
-            .children
-            .clone()
-            .max_by_key(|&n| tree.nodes[n].visits)
-            .expect("Error")
-    } else {
-        tree.nodes[0]
-            .children
-            .clone()
-            .max_by(|a, b| {
-                let a_node = &tree.nodes[*a];
-                let b_node = &tree.nodes[*b];
-                let a_policy = a_node.policy;
-                let b_policy = b_node.policy;
-                a_policy.partial_cmp(&b_policy).unwrap()
-            })
-            .expect("Error")
-    };
-    let best_move = tree.nodes[best_move_node].mv;
-    let mut total_visits_list = Vec::new();
-    // // println!("{:#}", best_move.unwrap());
-    for child in tree.nodes[0].children.clone() {
-        total_visits_list.push(tree.nodes[child].visits);
-    }
+    extend_search(&mut tree, client, settings.max_nodes as u32, sw, id).await?;
 
-    // let display_str = tree.display_node(0); // print root node
-    // // println!("{}", display_str);
-    let total_visits: u32 = total_visits_list.iter().sum();
-
-    let mut pi: Vec<f32> = Vec::new();
-
-    // // println!("{:?}", &total_visits_list);
-
-    for &t in &total_visits_list {
-        let prob = t as f32 / total_visits as f32;
-        pi.push(prob);
-    }
-
-    // // println!("{:?}", &pi);
-    // // println!("{}", best_move.expect("Error").to_string());
-    // // println!("best move: {}", best_move.expect("Error").to_string());
-
-    // for child in tree.nodes[0].children.clone() {
-    //     let display_str = tree.display_node(child);
-    //     // println!("{}", display_str);
-    // }
-    // tree.nodes[0].display_full_tree(&tree);
-
-    let mut all_pol = Vec::new();
-
-    for child in tree.nodes[0].clone().children {
-        all_pol.push(tree.nodes[child].policy);
-    }
-
-    let v_p = ZeroEvaluation {
-        // network evaluation, NOT search/empirical data
-        values: tree.nodes[0].eval_score,
-        policy: all_pol,
-    };
-
-    let search_data = ZeroEvaluation {
-        // search data
-        values: tree.nodes[0].get_q_val(tree.settings),
-        policy: pi,
-    };
-
-    (
-        best_move.expect("Error"),
-        v_p,
-        tree.nodes[0].clone().move_idx,
-        search_data,
-        tree.nodes[0].visits,
-    )
+    Ok(search_result(&tree))
 }
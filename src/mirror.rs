@@ -0,0 +1,48 @@
+use cozy_chess::{Board, Color, Move};
+
+use crate::{
+    encoder::EncodedInput,
+    mapper::{ChessStdMapper, MoveMapper},
+};
+
+/// true if no side can castle, then mirroring the files gives an equivalent position
+pub fn can_mirror(board: &Board) -> bool {
+    Color::ALL.iter().all(|&color| {
+        let rights = board.castle_rights(color);
+        rights.short.is_none() && rights.long.is_none()
+    })
+}
+
+/// mirrors the files of a move, a to h and h to a
+pub fn mirror_move(mv: Move) -> Move {
+    Move {
+        from: mv.from.flip_file(),
+        to: mv.to.flip_file(),
+        promotion: mv.promotion,
+    }
+}
+
+/// the input of the file mirrored position, only valid if `can_mirror` holds
+///
+/// every plane stores one rank per byte with the a file in the lowest bit,
+/// so mirroring reverses the bits of each byte, the scalars stay the same
+pub fn mirror_input(input: &EncodedInput) -> EncodedInput {
+    EncodedInput {
+        scalars: input.scalars.clone(),
+        bools: input.bools.iter().map(|byte| byte.reverse_bits()).collect(),
+    }
+}
+
+/// the policy indices of the mirrored `moves` in the mirrored position, in the same order
+pub fn mirrored_policy_indices(board: &Board, moves: &[Move]) -> Vec<usize> {
+    // the mirrored position has the same side to move, which is all the mapper looks at
+    let mapper = ChessStdMapper::get();
+    moves
+        .iter()
+        .map(|&mv| {
+            mapper
+                .move_to_index(board, mirror_move(mv))
+                .expect("legal move missing from the policy layout")
+        })
+        .collect()
+}
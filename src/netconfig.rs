@@ -0,0 +1,128 @@
+use std::{fmt, str::FromStr, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+use tch::{Device, Kind};
+
+/// the device a network runs on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetDevice {
+    /// the first cuda device if there is one, otherwise the cpu
+    #[default]
+    Auto,
+    Cpu,
+    Cuda(usize),
+}
+
+impl NetDevice {
+    pub fn device(&self) -> Device {
+        match self {
+            NetDevice::Auto => Device::cuda_if_available(),
+            NetDevice::Cpu => Device::Cpu,
+            NetDevice::Cuda(index) => Device::Cuda(*index),
+        }
+    }
+}
+
+impl fmt::Display for NetDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetDevice::Auto => write!(f, "auto"),
+            NetDevice::Cpu => write!(f, "cpu"),
+            NetDevice::Cuda(index) => write!(f, "cuda:{}", index),
+        }
+    }
+}
+
+impl FromStr for NetDevice {
+    type Err = String;
+
+    /// `auto`, `cpu`, `cuda` (device 0) or `cuda:<index>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(NetDevice::Auto),
+            "cpu" => Ok(NetDevice::Cpu),
+            "cuda" => Ok(NetDevice::Cuda(0)),
+            other => other
+                .strip_prefix("cuda:")
+                .and_then(|index| index.parse().ok())
+                .map(NetDevice::Cuda)
+                .ok_or_else(|| format!("unknown device {}", s)),
+        }
+    }
+}
+
+/// what an executor does with the requests it has queued when its network is replaced
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapPolicy {
+    /// evaluate the queued requests with the old network as a partial batch, then load the new one
+    #[default]
+    FinishBatch,
+    /// swap right away, the queued requests are evaluated by the new network so no results
+    /// of the old one are returned after the swap
+    EvaluateWithNew,
+}
+
+/// the inter-op thread count of the process, torch only accepts it once
+static INTER_OP_THREADS: OnceLock<usize> = OnceLock::new();
+
+/// how a network is run, used when it is loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetConfig {
+    pub device: NetDevice,
+    pub intra_op_threads: Option<usize>, // None keeps the torch default
+    pub inter_op_threads: Option<usize>, // None keeps the torch default, can only be set before the first evaluation
+    pub half_precision: bool,            // only used on cuda, the cpu always runs in f32
+    pub no_grad: bool,                   // skip the autograd bookkeeping during evaluation
+    pub swap_policy: SwapPolicy,         // only used by executors that replace their network
+}
+
+impl NetConfig {
+    /// the network on the first cuda device if there is one, with the torch default threads
+    pub const DEFAULT: NetConfig = NetConfig {
+        device: NetDevice::Auto,
+        intra_op_threads: None,
+        inter_op_threads: None,
+        half_precision: false,
+        no_grad: true,
+        swap_policy: SwapPolicy::FinishBatch,
+    };
+
+    /// the float type the network and its inputs use on `device`
+    pub fn kind(&self, device: Device) -> Kind {
+        if self.half_precision && device.is_cuda() {
+            Kind::Half
+        } else {
+            Kind::Float
+        }
+    }
+
+    /// sets the torch thread counts, these are global to the process so call this at startup and
+    /// when the counts change, not for every network that is loaded
+    ///
+    /// the inter-op count is only set by the first call that has one, it has to come before the
+    /// first evaluation. asking for a different count later is an error
+    pub fn apply_threads(&self) -> Result<(), String> {
+        if let Some(threads) = self.intra_op_threads {
+            tch::set_num_threads(threads as i32);
+        }
+        if let Some(threads) = self.inter_op_threads {
+            let applied = *INTER_OP_THREADS.get_or_init(|| {
+                tch::set_num_interop_threads(threads as i32);
+                threads
+            });
+            if applied != threads {
+                return Err(format!(
+                    "torch inter-op threads are already set to {}, restart to change them",
+                    applied
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
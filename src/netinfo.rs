@@ -0,0 +1,140 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tch::{CModule, Device, IValue, Kind, Tensor};
+
+use crate::{
+    decoder::{split_outputs, BOARD_EVAL_LEN},
+    encoder::InputEncoder,
+    mapper::MoveMapper,
+};
+
+/// optional description of a network, every field that is set is checked when the network is loaded
+///
+/// read from a json file next to the network (`tz.pt` -> `tz.json`) or from a `metadata` method
+/// of the TorchScript module returning the same json, the file wins if both exist
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct NetMetadata {
+    pub name: Option<String>,
+    pub input_encoder: Option<InputEncoder>,
+    pub policy_len: Option<usize>,
+}
+
+impl NetMetadata {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("invalid network metadata: {}", e))
+    }
+
+    /// the metadata file of the network at `path`
+    pub fn sidecar_path(path: &str) -> PathBuf {
+        PathBuf::from(path).with_extension("json")
+    }
+
+    /// reads the metadata file next to the network, None if there is none
+    pub fn read_sidecar(path: &str) -> Result<Option<Self>, String> {
+        let sidecar = Self::sidecar_path(path);
+        match fs::read_to_string(&sidecar) {
+            Ok(json) => Self::from_json(&json)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", sidecar.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", sidecar.display(), e)),
+        }
+    }
+
+    /// the metadata of the network at `path`, None if it has none
+    pub fn read(path: &str, net: &CModule) -> Result<Option<Self>, String> {
+        if let Some(metadata) = Self::read_sidecar(path)? {
+            return Ok(Some(metadata));
+        }
+        match net.method_is::<IValue>("metadata", &[]) {
+            Ok(IValue::String(json)) => Self::from_json(&json).map(Some),
+            Ok(other) => Err(format!(
+                "metadata method returned {:?}, not a string",
+                other
+            )),
+            Err(_) => Ok(None), // the network has no metadata method
+        }
+    }
+
+    /// checks the declared inputs and outputs against the encoder and move layout we use
+    pub fn check(&self, encoder: InputEncoder, mapper: &dyn MoveMapper) -> Result<(), String> {
+        if let Some(expected) = self.input_encoder {
+            if expected != encoder {
+                return Err(format!(
+                    "network expects input encoding {}, the encoder is {}",
+                    expected, encoder
+                ));
+            }
+        }
+        if let Some(policy_len) = self.policy_len {
+            if policy_len != mapper.policy_len() {
+                return Err(format!(
+                    "network declares a policy of {} entries, the move layout has {}",
+                    policy_len,
+                    mapper.policy_len()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// number of positions in the probe batch, more than one so a missing batch dimension is caught
+const PROBE_BATCH: usize = 2;
+
+/// runs a batch of empty positions through `net` and checks the output shapes
+pub fn probe(
+    net: &CModule,
+    device: Device,
+    kind: Kind,
+    encoder: InputEncoder,
+    mapper: &dyn MoveMapper,
+) -> Result<(), String> {
+    let input = Tensor::zeros(
+        [PROBE_BATCH as i64, encoder.input_channels() as i64, 8, 8],
+        (kind, device),
+    );
+    let output = net.forward_is(&[IValue::Tensor(input)]).map_err(|e| {
+        format!(
+            "network does not accept {} inputs of {} planes: {}",
+            encoder,
+            encoder.input_channels(),
+            e
+        )
+    })?;
+    let (board_eval, policy) = split_outputs(output).map_err(|e| e.to_string())?;
+    check_output_shapes(
+        &board_eval.size(),
+        &policy.size(),
+        PROBE_BATCH,
+        mapper.policy_len(),
+    )
+}
+
+/// checks the output shapes of a batch of `batch_size` positions
+pub fn check_output_shapes(
+    board_eval: &[i64],
+    policy: &[i64],
+    batch_size: usize,
+    policy_len: usize,
+) -> Result<(), String> {
+    let per_position = |shape: &[i64]| match shape.first() {
+        Some(&n) if n as usize == batch_size => Some(shape[1..].iter().product::<i64>() as usize),
+        _ => None,
+    };
+    if per_position(board_eval) != Some(BOARD_EVAL_LEN) {
+        return Err(format!(
+            "expected {} board evaluation outputs per position, got shape {:?} for {} positions",
+            BOARD_EVAL_LEN, board_eval, batch_size
+        ));
+    }
+    match per_position(policy) {
+        Some(n) if n == policy_len => Ok(()),
+        _ => Err(format!(
+            "expected a policy of {} entries per position, got shape {:?} for {} positions",
+            policy_len, policy, batch_size
+        )),
+    }
+}
@@ -0,0 +1,620 @@
+// PGN import and export of games, see https://www.saremba.de/chessgml/standards/pgn/pgn-complete.htm
+use crate::{
+    boardmanager::{BoardStack, MAX_HISTORY},
+    dataformat::{Position, Simulation, ZeroEvaluation},
+    mcts_trainer::Wdl,
+};
+use cozy_chess::{Board, Color, File, GameStatus, Move, Piece, Rank, Square};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+};
+
+const MAX_LINE_LENGTH: usize = 80;
+const TOP_POLICY_MOVES: usize = 3;
+
+/// a move and the comment written after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnMove {
+    pub mv: Move,
+    pub comment: Option<String>,
+}
+
+/// standard algebraic notation of a legal move, `mv` uses cozy-chess castling (king takes rook)
+pub fn san(board: &Board, mv: Move) -> String {
+    let piece = board.piece_on(mv.from).expect("Error");
+    let is_castle = piece == Piece::King && board.colors(board.side_to_move()).has(mv.to);
+
+    let mut result = if is_castle {
+        if mv.to.file() > mv.from.file() {
+            "O-O".to_string()
+        } else {
+            "O-O-O".to_string()
+        }
+    } else if piece == Piece::Pawn {
+        // a pawn changing file always captures, this includes en passant
+        let mut result = String::new();
+        if mv.from.file() != mv.to.file() {
+            result.push(char::from(mv.from.file()));
+            result.push('x');
+        }
+        result += &mv.to.to_string();
+        if let Some(promotion) = mv.promotion {
+            result.push('=');
+            result.push(char::from(promotion).to_ascii_uppercase());
+        }
+        result
+    } else {
+        let mut result = char::from(piece).to_ascii_uppercase().to_string();
+        result += &disambiguation(board, mv, piece);
+        if board.occupied().has(mv.to) {
+            result.push('x');
+        }
+        result += &mv.to.to_string();
+        result
+    };
+
+    let mut after = board.clone();
+    after.play_unchecked(mv);
+    if !after.checkers().is_empty() {
+        match after.status() {
+            GameStatus::Won => result.push('#'),
+            _ => result.push('+'),
+        }
+    }
+    result
+}
+
+// the file and/or rank needed to tell `mv` apart from moves of other pieces of the same type
+fn disambiguation(board: &Board, mv: Move, piece: Piece) -> String {
+    let mut others = Vec::new();
+    board.generate_moves(|moves| {
+        if moves.piece == piece && moves.from != mv.from && moves.to.has(mv.to) {
+            others.push(moves.from);
+        }
+        false
+    });
+
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|sq| sq.file() != mv.from.file()) {
+        char::from(mv.from.file()).to_string()
+    } else if others.iter().all(|sq| sq.rank() != mv.from.rank()) {
+        char::from(mv.from.rank()).to_string()
+    } else {
+        mv.from.to_string()
+    }
+}
+
+/// the PGN result tag of a finished or stopped game
+pub fn result_string(bs: &BoardStack) -> &'static str {
+    match bs.status() {
+        GameStatus::Won => match bs.board().side_to_move() {
+            Color::White => "0-1",
+            Color::Black => "1-0",
+        },
+        GameStatus::Drawn => "1/2-1/2",
+        GameStatus::Ongoing => "*",
+    }
+}
+
+/// comment with the visits, search value, network value and the moves the search liked most
+///
+/// values are from white's point of view, like `ZeroEvaluation`
+pub fn search_comment(
+    board: &Board,
+    zero_visits: u64,
+    zero_evaluation: &ZeroEvaluation,
+    net_evaluation: &ZeroEvaluation,
+) -> String {
+    let mut comment = format!(
+        "N={} Q={:.3} v={:.3}",
+        zero_visits, zero_evaluation.values, net_evaluation.values
+    );
+
+    // the policy is in move generation order
+    let mut legal_moves = Vec::new();
+    board.generate_moves(|moves| {
+        legal_moves.extend(moves);
+        false
+    });
+    let mut policy: Vec<(Move, f32)> = legal_moves
+        .into_iter()
+        .zip(zero_evaluation.policy.iter().copied())
+        .filter(|(_, p)| !p.is_nan())
+        .collect();
+    policy.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+    if !policy.is_empty() {
+        comment += " top:";
+        for &(mv, p) in policy.iter().take(TOP_POLICY_MOVES) {
+            comment += &format!(" {} {:.2}", san(board, mv), p);
+        }
+    }
+    comment
+}
+
+/// writes a single game, `headers` come first and should include the seven tag roster
+pub fn write_pgn(
+    headers: &[(&str, String)],
+    start: &Board,
+    moves: &[PgnMove],
+    result: &str,
+) -> String {
+    let mut pgn = String::new();
+    for (name, value) in headers {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        pgn += &format!("[{} \"{}\"]\n", name, value);
+    }
+    pgn.push('\n');
+
+    let mut tokens: Vec<String> = Vec::new();
+    let mut board = start.clone();
+    for (i, pgn_move) in moves.iter().enumerate() {
+        let move_number = board.fullmove_number();
+        match board.side_to_move() {
+            Color::White => tokens.push(format!("{}.", move_number)),
+            // black moves only need a number at the start or after a comment
+            Color::Black if i == 0 || moves[i - 1].comment.is_some() => {
+                tokens.push(format!("{}...", move_number))
+            }
+            Color::Black => {}
+        }
+        tokens.push(san(&board, pgn_move.mv));
+        if let Some(comment) = &pgn_move.comment {
+            // braces end a comment, so they can't appear in it
+            let comment = comment.replace(['{', '}'], "");
+            tokens.extend(format!("{{{}}}", comment).split(' ').map(String::from));
+        }
+        board.play(pgn_move.mv);
+    }
+    tokens.push(result.to_string());
+
+    // wrap the movetext
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+            pgn += &line;
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line += &token;
+    }
+    pgn += &line;
+    pgn += "\n\n";
+    pgn
+}
+
+/// PGN of a self-play game with every move annotated by `search_comment`, moves searched with
+/// a newly swapped in network also say so
+///
+/// `headers` override the defaults, the network, seed and settings are added from the provenance
+pub fn simulation_to_pgn(sim: &Simulation, headers: &[(&str, String)]) -> String {
+    let result = result_string(&sim.final_board);
+    let start = sim.start_board().board().clone();
+
+    let mut all_headers: Vec<(&str, String)> = vec![
+        ("Event", "?".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", "????.??.??".to_string()),
+        ("Round", "?".to_string()),
+        ("White", "?".to_string()),
+        ("Black", "?".to_string()),
+        ("Result", result.to_string()),
+    ];
+    for (name, value) in headers {
+        match all_headers.iter_mut().find(|(n, _)| n == name) {
+            Some(header) => header.1 = value.clone(),
+            None => all_headers.push((name, value.clone())),
+        }
+    }
+    if start != Board::default() {
+        all_headers.push(("SetUp", "1".to_string()));
+        all_headers.push(("FEN", start.to_string()));
+    }
+    if let Some(provenance) = &sim.provenance {
+        if let Some(net_hash) = &provenance.net_hash {
+            all_headers.push(("NetHash", net_hash.clone()));
+        }
+        all_headers.push(("Seed", provenance.seed.to_string()));
+        all_headers.push((
+            "Settings",
+            serde_json::to_string(&provenance.settings).expect("Error"),
+        ));
+    }
+
+    let net_switches = match &sim.provenance {
+        Some(provenance) => &provenance.net_switches[..],
+        None => &[],
+    };
+    let moves: Vec<PgnMove> = sim
+        .positions
+        .iter()
+        .enumerate()
+        .map(|(ply, pos)| {
+            let mut comment = search_comment(
+                pos.board.board(),
+                pos.zero_visits,
+                &pos.zero_evaluation,
+                &pos.net_evaluation,
+            );
+            if let Some(switch) = net_switches.iter().find(|switch| switch.ply == ply) {
+                comment = format!(
+                    "network switched to generation {} {}",
+                    switch.generation, comment
+                );
+            }
+            PgnMove {
+                mv: pos.played_mv,
+                comment: Some(comment),
+            }
+        })
+        .collect();
+
+    write_pgn(&all_headers, &start, &moves, result)
+}
+
+/// appends games to a PGN file, creating it if needed
+pub fn append_pgn(path: &str, pgn: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(pgn.as_bytes())
+}
+
+/// a game read from a PGN file
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnGame {
+    pub headers: Vec<(String, String)>,
+    pub start: Board,
+    pub moves: Vec<Move>,
+    pub result: String,
+}
+
+impl PgnGame {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// the winner, None for a draw and Err for an unfinished game
+    pub fn outcome(&self) -> Result<Option<Color>, String> {
+        match self.result.as_str() {
+            "1-0" => Ok(Some(Color::White)),
+            "0-1" => Ok(Some(Color::Black)),
+            "1/2-1/2" => Ok(None),
+            result => Err(format!("game has no result ({})", result)),
+        }
+    }
+}
+
+/// the legal move written as `text` in standard algebraic notation, also accepts long forms like `Ng1f3`
+pub fn parse_san(board: &Board, text: &str) -> Option<Move> {
+    let text = text.trim_end_matches(['+', '#', '!', '?']);
+    let king_side = match text {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None,
+    };
+
+    let mut chars: Vec<char> = text
+        .chars()
+        .filter(|&c| c != 'x' && c != '-' && c != '=')
+        .collect();
+    let piece = match chars.first() {
+        Some(&c) if c.is_ascii_uppercase() && king_side.is_none() => {
+            chars.remove(0);
+            Piece::try_from(c.to_ascii_lowercase()).ok()?
+        }
+        _ => Piece::Pawn,
+    };
+    let promotion = match chars.last() {
+        Some(&c) if c.is_ascii_alphabetic() && piece == Piece::Pawn && king_side.is_none() => {
+            chars.pop();
+            Some(Piece::try_from(c.to_ascii_lowercase()).ok()?)
+        }
+        _ => None,
+    };
+
+    // the last two characters are the target square, anything before it narrows down the origin
+    let (to, from_file, from_rank) = if king_side.is_some() {
+        (None, None, None)
+    } else {
+        if chars.len() < 2 {
+            return None;
+        }
+        let to: String = chars[chars.len() - 2..].iter().collect();
+        let to: Square = to.parse().ok()?;
+        let mut from_file = None;
+        let mut from_rank = None;
+        for &c in &chars[..chars.len() - 2] {
+            if let Ok(file) = File::try_from(c) {
+                from_file = Some(file);
+            } else if let Ok(rank) = Rank::try_from(c) {
+                from_rank = Some(rank);
+            } else {
+                return None;
+            }
+        }
+        (Some(to), from_file, from_rank)
+    };
+
+    let mut candidates = Vec::new();
+    board.generate_moves(|moves| {
+        for mv in moves {
+            let is_castle =
+                moves.piece == Piece::King && board.colors(board.side_to_move()).has(mv.to);
+            let matches = match king_side {
+                Some(king_side) => is_castle && (mv.to.file() > mv.from.file()) == king_side,
+                None => {
+                    !is_castle
+                        && moves.piece == piece
+                        && Some(mv.to) == to
+                        && mv.promotion == promotion
+                        && from_file.is_none_or(|file| mv.from.file() == file)
+                        && from_rank.is_none_or(|rank| mv.from.rank() == rank)
+                }
+            };
+            if matches {
+                candidates.push(mv);
+            }
+        }
+        false
+    });
+
+    match candidates.as_slice() {
+        &[mv] => Some(mv),
+        _ => None,
+    }
+}
+
+// the tags and the moves of the main line, comments, variations and annotations are dropped
+enum Token {
+    Tag(String, String),
+    Move(String),
+    Result(String),
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut variation_depth = 0;
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line_start = true;
+                continue;
+            }
+            // escaped lines and rest of line comments, the newline is consumed as well
+            c if c == ';' || (c == '%' && line_start) => {
+                chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+                line_start = true;
+                continue;
+            }
+            '{' => {
+                chars.by_ref().take_while(|&c| c != '}').for_each(drop);
+            }
+            '(' => variation_depth += 1,
+            ')' => variation_depth -= 1,
+            '[' => {
+                let mut tag = String::new();
+                let mut in_string = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' if in_string => tag.extend(chars.next()),
+                        '"' => in_string = !in_string,
+                        ']' if !in_string => break,
+                        c => tag.push(c),
+                    }
+                }
+                if let Some((name, value)) = tag.trim().split_once(char::is_whitespace) {
+                    tokens.push(Token::Tag(name.to_string(), value.trim().to_string()));
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "[]{}();".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if variation_depth == 0 {
+                    push_word(&mut tokens, &word);
+                }
+            }
+        }
+        line_start = false;
+    }
+    tokens
+}
+
+fn push_word(tokens: &mut Vec<Token>, word: &str) {
+    if matches!(word, "1-0" | "0-1" | "1/2-1/2" | "*") {
+        tokens.push(Token::Result(word.to_string()));
+        return;
+    }
+    if word.starts_with('$') {
+        return; // numeric annotation glyph
+    }
+    // move numbers, possibly attached to the move like 1.e4
+    let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let word = if digits > 0 && word[digits..].starts_with('.') {
+        word[digits..].trim_start_matches('.')
+    } else {
+        word
+    };
+    if !word.is_empty() {
+        tokens.push(Token::Move(word.to_string()));
+    }
+}
+
+/// parses every game in `text`, games with an illegal or unreadable move are returned as errors
+pub fn parse_pgn(text: &str) -> Vec<Result<PgnGame, String>> {
+    let mut games = Vec::new();
+    let mut headers = Vec::new();
+    let mut moves = Vec::new();
+
+    let finish = |headers: Vec<(String, String)>, moves: Vec<String>, result: String| {
+        let start = match headers.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => {
+                Board::from_fen(fen, false).map_err(|_| format!("invalid FEN {}", fen))?
+            }
+            None => Board::default(),
+        };
+        let mut board = start.clone();
+        let mut parsed = Vec::new();
+        for text in moves {
+            let mv = parse_san(&board, &text)
+                .ok_or_else(|| format!("illegal move {} in position {}", text, board))?;
+            board.play(mv);
+            parsed.push(mv);
+        }
+        Ok(PgnGame {
+            headers,
+            start,
+            moves: parsed,
+            result,
+        })
+    };
+
+    for token in tokenize(text) {
+        match token {
+            Token::Tag(name, value) => {
+                // a game without a result ends at the next tag section
+                if !moves.is_empty() {
+                    games.push(finish(headers, moves, "*".to_string()));
+                    headers = Vec::new();
+                    moves = Vec::new();
+                }
+                headers.push((name, value));
+            }
+            Token::Move(text) => moves.push(text),
+            Token::Result(result) => {
+                games.push(finish(headers, moves, result));
+                headers = Vec::new();
+                moves = Vec::new();
+            }
+        }
+    }
+    if !moves.is_empty() || !headers.is_empty() {
+        games.push(finish(headers, moves, "*".to_string()));
+    }
+    games
+}
+
+/// supervised training data from a finished game, the policy target is the played move and the value target the result
+///
+/// `policy_smoothing` of the policy is spread evenly over all legal moves, 0.0 gives a one-hot policy
+///
+/// games played on past a draw nobody claimed (repetition, 50 moves, insufficient material) are cut
+/// off at the first position that is over, and count as a draw
+pub fn game_to_simulation(
+    game: &PgnGame,
+    policy_smoothing: f32,
+) -> Result<(Simulation, Option<Color>), String> {
+    let mut outcome = game.outcome()?;
+    let mut bs = BoardStack::new(game.start.clone());
+    let mut played = 0;
+    for &mv in &game.moves {
+        if bs.is_terminal() {
+            break;
+        }
+        bs.play(mv);
+        played += 1;
+    }
+    match bs.status() {
+        GameStatus::Ongoing => {}
+        GameStatus::Drawn => outcome = None,
+        GameStatus::Won => outcome = Some(!bs.board().side_to_move()),
+    }
+    // ZeroEvaluation is from white's point of view
+    let (value, wdl) = match outcome {
+        Some(Color::White) => (
+            1.0,
+            Wdl {
+                w: 1.0,
+                d: 0.0,
+                l: 0.0,
+            },
+        ),
+        Some(Color::Black) => (
+            -1.0,
+            Wdl {
+                w: 0.0,
+                d: 0.0,
+                l: 1.0,
+            },
+        ),
+        None => (
+            0.0,
+            Wdl {
+                w: 0.0,
+                d: 1.0,
+                l: 0.0,
+            },
+        ),
+    };
+    let nan_wdl = Wdl {
+        w: f32::NAN,
+        d: f32::NAN,
+        l: f32::NAN,
+    };
+
+    // the encoder of the file the game ends up in is not known here, so every history board is kept
+    let mut bs = BoardStack::new(game.start.clone()).with_history(MAX_HISTORY);
+    let mut positions = Vec::new();
+    for (ply, &mv) in game.moves[..played].iter().enumerate() {
+        let mut legal_moves = Vec::new();
+        bs.board().generate_moves(|moves| {
+            legal_moves.extend(moves);
+            false
+        });
+        let uniform = policy_smoothing / legal_moves.len() as f32;
+        let policy: Vec<f32> = legal_moves
+            .iter()
+            .map(|&legal| {
+                if legal == mv {
+                    1.0 - policy_smoothing + uniform
+                } else {
+                    uniform
+                }
+            })
+            .collect();
+
+        positions.push(Position {
+            board: bs.clone(),
+            is_full_search: true,
+            played_mv: mv,
+            zero_visits: 0,
+            zero_evaluation: ZeroEvaluation {
+                values: value,
+                wdl,
+                moves_left: (played - ply) as f32,
+                policy,
+            },
+            // there is no network evaluation
+            net_evaluation: ZeroEvaluation {
+                values: f32::NAN,
+                wdl: nan_wdl,
+                moves_left: f32::NAN,
+                policy: vec![f32::NAN; legal_moves.len()],
+            },
+        });
+        bs.play(mv);
+    }
+
+    let sim = Simulation {
+        positions,
+        final_board: bs,
+        provenance: None,
+    };
+    Ok((sim, outcome))
+}
@@ -0,0 +1,41 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::settings::SearchSettings;
+
+/// where a generated game came from, stored in the metadata of the data file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Provenance {
+    pub net_hash: Option<String>, // sha256 of the .pt file, None if it is not known
+    pub settings: SearchSettings,
+    pub seed: u64, // seeds the opening and the move sampling, not enough to replay the game as the Dirichlet noise is not seeded
+    pub start_fen: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub net_switches: Vec<NetSwitch>, // networks swapped in while the game was played
+}
+
+/// a network swap during a game, the search of the move at `ply` was the first to be answered by the new network
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetSwitch {
+    pub ply: usize,      // index into the positions of the game
+    pub generation: u64, // the `NetUpdate::generation` of the new network
+}
+
+/// content hash of a network file, as lowercase hex
+pub fn hash_net_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn hash_net_file(path: impl AsRef<Path>) -> io::Result<String> {
+    Ok(hash_net_bytes(&fs::read(path)?))
+}
+
+/// the first 24 bits of a network hash, small enough to be stored exactly as an f32 scalar
+pub fn net_id(net_hash: &str) -> f32 {
+    u32::from_str_radix(&net_hash[..6], 16).expect("Error") as f32
+}
@@ -0,0 +1,156 @@
+use std::{
+    f32::consts::LN_10,
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    str::FromStr,
+};
+
+use cozy_chess::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::{mcts_trainer::Wdl, uci::eval_in_cp};
+
+/// logit of the expected score that is worth one pawn (a pawn is roughly worth 100 Elo)
+const PAWN_LOGIT: f32 = 100.0 * LN_10 / 400.0;
+
+/// largest score (in centipawns) reported to GUIs
+pub const MAX_CP: i64 = 1000;
+
+// keep atanh/logit finite for decisive evaluations
+const MAX_ABS_Q: f32 = 0.999;
+
+/// how a value in [-1, 1] (and optionally a WDL) is turned into a score in pawns
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ScoreModel {
+    #[default]
+    Piecewise, // hand-tuned piecewise linear map, see `eval_in_cp`
+    Wdl, // logit of the expected score (w + d/2), using the value if WDL is not available
+    Calibrated(Calibration), // logistic fit of game outcomes, see `fit_calibration`
+}
+
+/// fitted model for the expected score, `sigmoid(scale * atanh(q) + bias)`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub scale: f32,
+    pub bias: f32,
+}
+
+impl FromStr for ScoreModel {
+    type Err = String;
+
+    /// parses the UCI option value, a calibration is loaded separately with `Calibration::load`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "piecewise" => Ok(ScoreModel::Piecewise),
+            "wdl" => Ok(ScoreModel::Wdl),
+            _ => Err(format!("unknown score model {}", s)),
+        }
+    }
+}
+
+impl ScoreModel {
+    /// score in pawns from the point of view of the evaluation
+    pub fn pawns(&self, value: f32, wdl: Option<Wdl>) -> f32 {
+        match self {
+            ScoreModel::Piecewise => eval_in_cp(value),
+            ScoreModel::Wdl => {
+                let q = match wdl {
+                    Some(wdl) if !wdl.w.is_nan() => wdl.w - wdl.l,
+                    _ => value,
+                };
+                2.0 * clamped_atanh(q) / PAWN_LOGIT // logit((1 + q) / 2) == 2 * atanh(q)
+            }
+            ScoreModel::Calibrated(calibration) => calibration.logit(value) / PAWN_LOGIT,
+        }
+    }
+
+    /// score in centipawns, clamped to `MAX_CP`
+    pub fn cp(&self, value: f32, wdl: Option<Wdl>) -> i64 {
+        let cp = (self.pawns(value, wdl) * 100.).round();
+        if cp.is_nan() {
+            return 0;
+        }
+        (cp as i64).clamp(-MAX_CP, MAX_CP)
+    }
+
+    /// score in centipawns from white's point of view, for a white POV value and a side to move WDL
+    ///
+    /// the score is computed from the side to move because `Calibration::bias` is the side to move's advantage
+    pub fn white_cp(&self, value: f32, wdl: Option<Wdl>, side_to_move: Color) -> i64 {
+        match side_to_move {
+            Color::White => self.cp(value, wdl),
+            Color::Black => -self.cp(-value, wdl),
+        }
+    }
+}
+
+impl Calibration {
+    pub fn logit(&self, value: f32) -> f32 {
+        self.scale * clamped_atanh(value) + self.bias
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+fn clamped_atanh(q: f32) -> f32 {
+    q.clamp(-MAX_ABS_Q, MAX_ABS_Q).atanh()
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// fits `Calibration` to `(value, outcome)` pairs, both from the same point of view and in [-1, 1]
+///
+/// minimises the cross-entropy between the predicted and the actual expected score using Newton's method,
+/// draws count as half a win so they don't need a separate class
+pub fn fit_calibration(samples: &[(f32, f32)]) -> Calibration {
+    let mut scale = 2.0; // same as the `Wdl` model
+    let mut bias = 0.0;
+
+    for _ in 0..50 {
+        // gradient and hessian of the summed cross-entropy
+        let (mut g_s, mut g_b) = (0.0, 0.0);
+        let (mut h_ss, mut h_sb, mut h_bb) = (0.0, 0.0, 0.0);
+        for &(value, outcome) in samples {
+            let x = clamped_atanh(value) as f64;
+            let target = (outcome as f64 + 1.0) / 2.0;
+            let p = sigmoid(scale * x + bias);
+            let w = p * (1.0 - p);
+
+            g_s += (p - target) * x;
+            g_b += p - target;
+            h_ss += w * x * x;
+            h_sb += w * x;
+            h_bb += w;
+        }
+
+        let det = h_ss * h_bb - h_sb * h_sb;
+        if det.abs() < 1e-12 {
+            break;
+        }
+        let d_s = (h_bb * g_s - h_sb * g_b) / det;
+        let d_b = (h_ss * g_b - h_sb * g_s) / det;
+        scale -= d_s;
+        bias -= d_b;
+
+        if d_s.abs() < 1e-6 && d_b.abs() < 1e-6 {
+            break;
+        }
+    }
+
+    Calibration {
+        scale: scale as f32,
+        bias: bias as f32,
+    }
+}
@@ -1,10 +1,11 @@
 use crate::{
     boardmanager::BoardStack,
     dataformat::{Position, Simulation, ZeroEvaluation},
-    decoder::{convert_board, extract_from_tensor},
-    executor::{Packet, ReturnMessage},
-    mcts_trainer::{get_move, ExpansionType, TypeRequest},
-    settings::SearchSettings,
+    decoder::{extract_from_tensor, legal_policy_indices},
+    executor::{EvalClient, EvalError, EvalRequest},
+    mcts_trainer::{get_move_with_client, ExpansionType, TypeRequest, Wdl},
+    provenance::{NetSwitch, Provenance},
+    settings::{RootMoves, SearchSettings},
 };
 use cozy_chess::{Board, Color, GameStatus, Move};
 use flume::Sender;
@@ -14,11 +15,12 @@ use std::time::{Instant, SystemTime, UNIX_EPOCH};
 // selfplay code
 #[derive(Clone, Debug)]
 pub enum CollectorMessage {
-    FinishedGame(Simulation),
+    FinishedGame(Box<Simulation>), // boxed, the other messages are small
     GeneratorStatistics(f32),
     ExecutorStatistics(f32),
 
     GameResult(Option<Color>),
+    GamePgn(String), // a finished match game to be saved
 }
 
 #[derive(PartialEq, Clone, Debug, Copy)]
@@ -30,13 +32,47 @@ pub struct DataGen {
 impl DataGen {
     pub async fn play_game(
         &self,
-        tensor_exe_send: &Sender<Packet>,
+        tensor_exe_send: &Sender<EvalRequest>,
         nps_sender: &Sender<CollectorMessage>,
         settings: &SearchSettings,
         id: usize,
-    ) -> Simulation {
+    ) -> Result<Simulation, EvalError> {
+        let bs = BoardStack::new(Board::default());
+        let seed = thread_rng().gen();
+        self.play_game_from(bs, seed, tensor_exe_send, nps_sender, settings, id)
+            .await
+    }
+
+    /// plays a game starting from `bs`, for example the end of a book opening
+    ///
+    /// fails if an evaluation fails, the game is unfinished then and should be dropped
+    ///
+    /// `seed` is used to sample the played moves and is recorded in the provenance of the game,
+    /// as are the moves at which the executor answered with a new network. the Dirichlet noise
+    /// and the playout caps are not seeded, so the same seed does not give the same game
+    ///
+    /// `bs` keeps as many history boards as the encoder of `settings` needs during the game
+    pub async fn play_game_from(
+        &self,
+        bs: BoardStack,
+        seed: u64,
+        tensor_exe_send: &Sender<EvalRequest>,
+        nps_sender: &Sender<CollectorMessage>,
+        settings: &SearchSettings,
+        id: usize,
+    ) -> Result<Simulation, EvalError> {
         let sw = Instant::now();
-        let mut bs = BoardStack::new(Board::default());
+        let mut bs = bs.with_history(settings.encoder.history_len());
+        let mut provenance = Provenance {
+            net_hash: None,
+            settings: *settings,
+            seed,
+            start_fen: bs.board().to_string(),
+            net_switches: Vec::new(),
+        };
+        // the newest network generation that answered the previous search
+        let mut net_generation: Option<u64> = None;
+        let mut rng = StdRng::seed_from_u64(seed);
         // let mut value: Vec<f32> = Vec::new();
         let mut positions: Vec<Position> = Vec::new();
         // let thread_name = std::thread::current()
@@ -45,9 +81,19 @@ impl DataGen {
         //     .to_owned();
         while bs.status() == GameStatus::Ongoing {
             let sw = Instant::now();
+            // a client per search, so its generation tells which network answered this search
+            let mut client = EvalClient::for_search(tensor_exe_send.clone(), settings);
             let (mv, v_p, move_idx_piece, search_data, visits) =
-                get_move(bs.clone(), &tensor_exe_send, settings.clone(), id).await;
+                get_move_with_client(bs.clone(), &mut client, *settings, RootMoves::All, id).await?;
             let elapsed = sw.elapsed().as_nanos() as f32 / 1e9;
+            let current = client.generation();
+            if net_generation.is_some_and(|previous| previous != current) {
+                provenance.net_switches.push(NetSwitch {
+                    ply: positions.len(),
+                    generation: current,
+                });
+            }
+            net_generation = Some(current);
             let final_mv = if positions.len() > 30 {
                 // when tau is "infinitesimally small", pick the best move
                 // or if search nodes = 1, since search_data.policy would return a vec of NANs
@@ -55,7 +101,6 @@ impl DataGen {
             } else {
                 let weighted_index = WeightedIndex::new(&search_data.policy).unwrap();
 
-                let mut rng = StdRng::from_entropy();
                 let sampled_idx = weighted_index.sample(&mut rng);
                 let mut legal_moves: Vec<Move> = Vec::new();
                 bs.board().generate_moves(|moves| {
@@ -91,63 +136,52 @@ impl DataGen {
         let tz = Simulation {
             positions,
             final_board: bs,
+            provenance: Some(provenance),
         };
         let elapsed_ms = sw.elapsed().as_nanos() as f32 / 1e9;
         println!("one done {}s", elapsed_ms);
         // println!("one done!");
-        tz
+        Ok(tz)
     }
 
     pub async fn fast_data(
         &self,
-        tensor_exe_send: &Sender<Packet>,
+        tensor_exe_send: &Sender<EvalRequest>,
         nps_sender: &Sender<CollectorMessage>,
         settings: &SearchSettings,
         id: usize,
-    ) -> Simulation {
+    ) -> Result<Simulation, EvalError> {
         let sw = Instant::now();
-        let mut bs = BoardStack::new(Board::default());
+        let mut bs =
+            BoardStack::new(Board::default()).with_history(settings.encoder.history_len());
+        let seed = thread_rng().gen();
+        let provenance = Provenance {
+            net_hash: None,
+            settings: *settings,
+            seed,
+            start_fen: bs.board().to_string(),
+            net_switches: Vec::new(),
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
         // let mut value: Vec<f32> = Vec::new();
         let mut positions: Vec<Position> = Vec::new();
         // let thread_name = std::thread::current()
         //     .name()
         //     .unwrap_or("unnamed")
         //     .to_owned();
+        let mut client =
+            EvalClient::new(tensor_exe_send.clone(), settings.encoder).with_mirror(settings.mirror);
         while bs.status() == GameStatus::Ongoing {
-            let mut legal_moves: Vec<Move> = Vec::new();
-            bs.board().generate_moves(|moves| {
-                // Unpack dense move set into move list
-                legal_moves.extend(moves);
-                false
-            });
+            let (legal_moves, _) = legal_policy_indices(&bs);
             // reannotate with net
 
-            let input_tensor = convert_board(&bs);
-
-            // creating a send/recv pair for executor
-
-            let (resender_send, resender_recv) = flume::bounded::<ReturnMessage>(1); // mcts to executor
-                                                                                     // let thread_name = std::thread::current()
-                                                                                     //     .name()
-                                                                                     //     .unwrap_or("unnamed-generator")
-                                                                                     //     .to_owned();
-            let thread_name = std::thread::current()
-                .name()
-                .unwrap_or("unnamed-generator")
-                .to_owned();
-            let pack = Packet {
-                job: input_tensor,
-                resender: resender_send,
-                id: thread_name.clone(),
-            };
-            tensor_exe_send.send_async(pack).await.unwrap();
             let now_start_proc = SystemTime::now();
             let since_epoch_proc = now_start_proc
                 .duration_since(UNIX_EPOCH)
                 .expect("Time went backwards");
 
             let epoch_seconds_start_proc = since_epoch_proc.as_nanos();
-            let output = resender_recv.recv_async().await.unwrap();
+            client.evaluate(&bs).await?;
             let now_end_proc = SystemTime::now();
             let since_epoch_proc = now_end_proc
                 .duration_since(UNIX_EPOCH)
@@ -160,17 +194,11 @@ impl DataGen {
                 );
             }
 
-            let output = match output {
-                ReturnMessage::ReturnMessage(Ok(output)) => output,
-                ReturnMessage::ReturnMessage(Err(_)) => panic!("error in returning!"),
-            };
-            assert!(thread_name == output.id);
             // let (_, _, value, _, _, pol_list) = extract_from_tensor(output.packet, &bs);
 
             // let pol_list_rand = policy_modification(&pol_list);
 
             // let weighted_index = WeightedIndex::new(&pol_list_rand).unwrap();
-            let mut rng = StdRng::from_entropy();
 
             // Generate random floats and calculate their sum
             let sum: f32 = (0..settings.max_nodes - 1)
@@ -178,6 +206,11 @@ impl DataGen {
                 .sum();
 
             let value = 0.0; // dummy
+            let nan_wdl = Wdl {
+                w: f32::NAN,
+                d: f32::NAN,
+                l: f32::NAN,
+            };
             let pol_list: Vec<f32> = vec![f32::NAN; legal_moves.len()];
             let pol_list_rand = vec![f32::NAN; legal_moves.len()];
             // Calculate the average
@@ -194,10 +227,14 @@ impl DataGen {
                 zero_visits: 1,
                 zero_evaluation: ZeroEvaluation {
                     values: value,
+                    wdl: nan_wdl,
+                    moves_left: f32::NAN,
                     policy: pol_list,
                 }, // q
                 net_evaluation: ZeroEvaluation {
                     values: q,
+                    wdl: nan_wdl,
+                    moves_left: f32::NAN,
                     policy: pol_list_rand,
                 }, // v
             };
@@ -213,10 +250,11 @@ impl DataGen {
         let tz = Simulation {
             positions,
             final_board: bs,
+            provenance: Some(provenance),
         };
         let elapsed_ms = sw.elapsed().as_nanos() as f32 / 1e9;
         println!("one done {}s", elapsed_ms);
-        tz
+        Ok(tz)
     }
 }
 
@@ -1,6 +1,13 @@
-use crate::mcts_trainer::{TypeRequest, Wdl};
+use crate::{
+    boardmanager::BoardStack,
+    encoder::InputEncoder,
+    mcts_trainer::{TypeRequest, Wdl},
+    scoremodel::ScoreModel,
+};
+use cozy_chess::Move;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, Copy)]
+#[derive(Clone, Debug, PartialEq, Copy, Serialize, Deserialize)]
 
 pub struct SearchSettings {
     pub fpu: f32,
@@ -13,6 +20,60 @@ pub struct SearchSettings {
     pub search_type: TypeRequest,
     pub pst: f32,
     // pub cap_randomisation: Option<PlayoutCapSettings>, // "playout cap randomisation", TODO use option<playoutcapsettings>
+    pub score_model: ScoreModel, // only used to report scores, does not affect the search
+    #[serde(default)] // games recorded before encoders were versioned used v1
+    pub encoder: InputEncoder, // has to match the network
+    #[serde(default)]
+    pub mirror: bool, // average with the mirrored position when no side can castle, see `mirror.rs`
+}
+
+impl SearchSettings {
+    /// settings for playing and analysing as an engine, shared by uci, xboard and the analysis server
+    pub fn engine(max_nodes: u128, search_type: TypeRequest, score_model: ScoreModel) -> Self {
+        Self {
+            fpu: 0.0,
+            wdl: None,
+            moves_left: None,
+            c_puct: 0.0,
+            max_nodes,
+            alpha: 0.0,
+            eps: 0.0,
+            search_type,
+            pst: 0.0,
+            score_model,
+            encoder: InputEncoder::default(),
+            mirror: false,
+        }
+    }
+}
+
+/// restricts which moves the search may consider at the root, for `go searchmoves` and "what if" analysis
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RootMoves {
+    #[default]
+    All,
+    Only(Vec<Move>),    // only these moves are searched
+    Exclude(Vec<Move>), // every move except these is searched
+}
+
+impl RootMoves {
+    pub fn allows(&self, mv: Move) -> bool {
+        match self {
+            RootMoves::All => true,
+            RootMoves::Only(moves) => moves.contains(&mv),
+            RootMoves::Exclude(moves) => !moves.contains(&mv),
+        }
+    }
+
+    /// whether at least one legal move of `bs` passes the filter, a search needs one to start
+    pub fn allows_any(&self, bs: &BoardStack) -> bool {
+        let mut any = false;
+        bs.board().generate_moves(|moves| {
+            any = moves.into_iter().any(|mv| self.allows(mv));
+            any
+        });
+        any
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Copy)]
@@ -34,8 +95,9 @@ impl PlayoutCapSettings {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Copy)]
+#[derive(Clone, Debug, PartialEq, Copy, Serialize, Deserialize)]
 pub struct MovesLeftSettings {
+    #[serde(skip, default = "nan")] // only set during the search
     pub moves_left: f32,
     pub moves_left_weight: f32,
     pub moves_left_clip: f32,
@@ -52,3 +114,7 @@ impl MovesLeftSettings {
         }
     }
 }
+
+fn nan() -> f32 {
+    f32::NAN
+}
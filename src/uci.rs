@@ -1,10 +1,13 @@
 use crate::{
-    boardmanager::BoardStack,
-    decoder::{convert_board, eval_state},
-    executor::{executor_static, Message, Packet},
-    mcts::get_move,
-    mcts_trainer::{Net, TypeRequest::UCISearch},
-    settings::SearchSettings,
+    boardmanager::{BoardStack, MAX_HISTORY},
+    decoder::eval_state,
+    executor::{executor_static_with_config, EvalRequest, Message},
+    mcts::get_move_with_root_moves,
+    mcts_trainer::{Net, TypeRequest::UCISearch, Wdl},
+    netconfig::NetConfig,
+    polyglot::{Book, BookSelection, DEFAULT_MAX_PLY},
+    scoremodel::{Calibration, ScoreModel},
+    settings::{RootMoves, SearchSettings},
 };
 use cozy_chess::{Board, Color, Move, Piece, Square};
 use crossbeam::thread;
@@ -12,6 +15,40 @@ use std::{cmp::max, io, panic, process, str::FromStr};
 use tokio::runtime::Runtime;
 const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
+/// engine options changed through `setoption`
+struct UciOptions {
+    score_model: ScoreModel,
+    calibration: Option<Calibration>,
+    own_book: bool,
+    book: Option<Book>,
+    book_depth: u32,
+    book_selection: BookSelection,
+    net_config: NetConfig,
+}
+
+impl Default for UciOptions {
+    fn default() -> Self {
+        Self {
+            score_model: ScoreModel::default(),
+            calibration: None,
+            own_book: false,
+            book: None,
+            book_depth: DEFAULT_MAX_PLY,
+            book_selection: BookSelection::default(),
+            net_config: NetConfig::default(),
+        }
+    }
+}
+
+impl UciOptions {
+    fn book_move(&self, bs: &BoardStack) -> Option<Move> {
+        match &self.book {
+            Some(book) if self.own_book => book.pick_move(bs.board(), &mut rand::thread_rng()),
+            _ => None,
+        }
+    }
+}
+
 pub fn eval_in_cp(eval: f32) -> f32 {
     let cps = if eval > 0.5 {
         18. * (eval - 0.5) + 1.
@@ -32,13 +69,15 @@ pub fn run_uci(net_path: &str) {
     }));
 
     // initialise engine
+    // the network and with it the encoder can change with setoption, so every history board is kept
     let board = Board::default();
-    let mut bs = BoardStack::new(board);
+    let mut bs = BoardStack::new(board).with_history(MAX_HISTORY);
     let mut stack = Vec::new();
     let mut threads = 1;
 
     let mut stored_message: Option<String> = None;
-    let net = Net::new(net_path);
+    let mut options = UciOptions::default();
+    let mut net = Net::new(net_path);
     // main uci loop
     loop {
         let input = if let Some(msg) = stored_message {
@@ -63,19 +102,43 @@ pub fn run_uci(net_path: &str) {
             "uci" => preamble(),
             "isready" => println!("readyok"),
             "ucinewgame" => {}
-            "go" => handle_go(&commands, &bs, &net_path),
+            "go" => match options.book_move(&bs) {
+                Some(mv) => println!("bestmove {}", standard_castling_move(&bs, mv)),
+                None => handle_go(
+                    &commands,
+                    &bs,
+                    &net_path,
+                    options.score_model,
+                    options.net_config,
+                ),
+            },
             "position" => set_position(commands, &mut bs, &mut stack),
+            "setoption" => {
+                let previous = options.net_config;
+                set_option(&commands, &mut options);
+                if let Err(e) = options.net_config.apply_threads() {
+                    println!("info string {}", e);
+                    options.net_config.inter_op_threads = previous.inter_op_threads;
+                }
+                if options.net_config != net.config {
+                    // reload so a bad device shows up now and not at the next search
+                    match Net::try_with_config(net_path, net.encoder, options.net_config) {
+                        Ok(reloaded) => net = reloaded,
+                        Err(e) => {
+                            println!("info string {}", e);
+                            options.net_config = net.config;
+                        }
+                    }
+                }
+            }
             "quit" => process::exit(0),
             "eval" => {
-                let (value, _) = eval_state(convert_board(&bs), &net).unwrap();
+                let (value, _) = eval_state(net.encoder.encode(&bs), &net).unwrap();
                 let value = value.squeeze();
                 let value_raw: Vec<f32> = Vec::try_from(value).expect("Error");
                 let value: f32 = value_raw[0].tanh();
-                let cps = eval_in_cp(value);
-                println!(
-                    "eval: {}",
-                    (cps * 100.).round().max(-1000.).min(1000.) as i64
-                );
+                let wdl = softmax_wdl(&value_raw[1..4]);
+                println!("eval: {}", options.score_model.cp(value, Some(wdl)));
             }
             _ => {}
         }
@@ -85,10 +148,120 @@ pub fn run_uci(net_path: &str) {
 fn preamble() {
     println!("id name TrueZero-latest {}", env!("CARGO_PKG_VERSION"));
     println!("id author Andreas Lam");
+    println!("option name ScoreModel type combo default piecewise var piecewise var wdl var calibrated");
+    println!("option name ScoreCalibrationFile type string default <empty>");
+    println!("option name OwnBook type check default false");
+    println!("option name BookFile type string default <empty>");
+    println!(
+        "option name BookDepth type spin default {} min 0 max 1000",
+        DEFAULT_MAX_PLY
+    );
+    println!("option name BookSelection type combo default weighted var weighted var best");
+    let config = NetConfig::default();
+    println!("option name Device type string default {}", config.device);
+    // 0 keeps the torch default
+    println!("option name TorchThreads type spin default 0 min 0 max 1024");
+    println!("option name TorchInteropThreads type spin default 0 min 0 max 1024");
+    println!(
+        "option name HalfPrecision type check default {}",
+        config.half_precision
+    );
+    println!("option name NoGrad type check default {}", config.no_grad);
     println!("uciok");
 }
 
-fn check_castling_move(bs: &BoardStack, mut mv: Move) -> Move {
+fn softmax_wdl(logits: &[f32]) -> Wdl {
+    let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = logits.iter().map(|&x| (x - max).exp()).collect();
+    let sum: f32 = exps.iter().sum();
+    Wdl {
+        w: exps[0] / sum,
+        d: exps[1] / sum,
+        l: exps[2] / sum,
+    }
+}
+
+// `setoption name <name> value <value>`
+fn set_option(commands: &[&str], options: &mut UciOptions) {
+    let value_idx = commands.iter().position(|&x| x == "value");
+    let name = commands[2.min(commands.len())..value_idx.unwrap_or(commands.len())].join(" ");
+    let value = match value_idx {
+        Some(idx) => commands[idx + 1..].join(" "),
+        None => String::new(),
+    };
+
+    match name.to_lowercase().as_str() {
+        "scoremodel" => {
+            if value.eq_ignore_ascii_case("calibrated") {
+                match options.calibration {
+                    Some(calibration) => options.score_model = ScoreModel::Calibrated(calibration),
+                    None => println!("info string set ScoreCalibrationFile first"),
+                }
+            } else {
+                match value.parse::<ScoreModel>() {
+                    Ok(model) => options.score_model = model,
+                    Err(e) => println!("info string {}", e),
+                }
+            }
+        }
+        "scorecalibrationfile" => match Calibration::load(&value) {
+            Ok(loaded) => {
+                options.calibration = Some(loaded);
+                if let ScoreModel::Calibrated(_) = options.score_model {
+                    options.score_model = ScoreModel::Calibrated(loaded);
+                }
+            }
+            Err(e) => println!("info string could not load calibration {}: {}", value, e),
+        },
+        "ownbook" => options.own_book = value.eq_ignore_ascii_case("true"),
+        "bookfile" => match Book::load(&value) {
+            Ok(mut book) => {
+                book.max_ply = options.book_depth;
+                book.selection = options.book_selection;
+                println!("info string loaded {} book entries", book.len());
+                options.book = Some(book);
+            }
+            Err(e) => println!("info string could not load book {}: {}", value, e),
+        },
+        "bookdepth" => match value.parse() {
+            Ok(depth) => {
+                options.book_depth = depth;
+                if let Some(book) = options.book.as_mut() {
+                    book.max_ply = depth;
+                }
+            }
+            Err(_) => println!("info string invalid BookDepth {}", value),
+        },
+        "bookselection" => match value.parse() {
+            Ok(selection) => {
+                options.book_selection = selection;
+                if let Some(book) = options.book.as_mut() {
+                    book.selection = selection;
+                }
+            }
+            Err(e) => println!("info string {}", e),
+        },
+        "device" => match value.parse() {
+            Ok(device) => options.net_config.device = device,
+            Err(e) => println!("info string {}", e),
+        },
+        "torchthreads" => match value.parse() {
+            Ok(threads) => options.net_config.intra_op_threads = Some(threads).filter(|&t| t > 0),
+            Err(_) => println!("info string invalid TorchThreads {}", value),
+        },
+        // torch only accepts this once, before the first evaluation
+        "torchinteropthreads" => match value.parse() {
+            Ok(threads) => options.net_config.inter_op_threads = Some(threads).filter(|&t| t > 0),
+            Err(_) => println!("info string invalid TorchInteropThreads {}", value),
+        },
+        "halfprecision" => options.net_config.half_precision = value.eq_ignore_ascii_case("true"),
+        "nograd" => options.net_config.no_grad = value.eq_ignore_ascii_case("true"),
+        _ => {}
+    }
+}
+
+/// converts standard castling notation (e1g1) into the king-takes-rook notation (e1h1) used by cozy-chess
+pub fn check_castling_move(bs: &BoardStack, mut mv: Move) -> Move {
     if bs.board().piece_on(mv.from) == Some(Piece::King) {
         mv.to = match (mv.from, mv.to) {
             (Square::E1, Square::G1) => Square::H1,
@@ -101,6 +274,20 @@ fn check_castling_move(bs: &BoardStack, mut mv: Move) -> Move {
     mv
 }
 
+/// inverse of `check_castling_move`, for printing moves to GUIs
+pub fn standard_castling_move(bs: &BoardStack, mut mv: Move) -> Move {
+    if bs.board().piece_on(mv.from) == Some(Piece::King) {
+        mv.to = match (mv.from, mv.to) {
+            (Square::E1, Square::H1) => Square::G1,
+            (Square::E8, Square::H8) => Square::G8,
+            (Square::E1, Square::A1) => Square::C1,
+            (Square::E8, Square::A8) => Square::C8,
+            _ => mv.to,
+        };
+    }
+    mv
+}
+
 fn set_position(commands: Vec<&str>, bs: &mut BoardStack, stack: &mut Vec<u64>) {
     let mut fen = String::new();
     let mut move_list = Vec::new();
@@ -125,7 +312,7 @@ fn set_position(commands: Vec<&str>, bs: &mut BoardStack, stack: &mut Vec<u64>)
         &fen.trim()
     };
     let board = Board::from_fen(fenstr, false).unwrap();
-    *bs = BoardStack::new(board.clone());
+    *bs = BoardStack::new(board.clone()).with_history(MAX_HISTORY);
     stack.clear();
 
     for m in move_list {
@@ -146,7 +333,13 @@ fn set_position(commands: Vec<&str>, bs: &mut BoardStack, stack: &mut Vec<u64>)
     }
 }
 
-pub fn handle_go(commands: &[&str], bs: &BoardStack, net_path: &str) {
+pub fn handle_go(
+    commands: &[&str],
+    bs: &BoardStack,
+    net_path: &str,
+    score_model: ScoreModel,
+    net_config: NetConfig,
+) {
     let mut nodes = 1600;
     let mut max_time = None;
     let mut max_depth = 256;
@@ -154,6 +347,7 @@ pub fn handle_go(commands: &[&str], bs: &BoardStack, net_path: &str) {
     let mut times = [None; 2];
     let mut incs = [None; 2];
     let mut movestogo = 30;
+    let mut search_moves = Vec::new();
 
     let mut mode = "";
 
@@ -167,6 +361,7 @@ pub fn handle_go(commands: &[&str], bs: &BoardStack, net_path: &str) {
             "winc" => mode = "winc",
             "binc" => mode = "binc",
             "movestogo" => mode = "movestogo",
+            "searchmoves" => mode = "searchmoves",
             _ => match mode {
                 "nodes" => nodes = cmd.parse().unwrap_or(nodes),
                 "movetime" => max_time = cmd.parse().ok(),
@@ -176,6 +371,10 @@ pub fn handle_go(commands: &[&str], bs: &BoardStack, net_path: &str) {
                 "winc" => incs[0] = Some(cmd.parse().unwrap_or(0)),
                 "binc" => incs[1] = Some(cmd.parse().unwrap_or(0)),
                 "movestogo" => movestogo = cmd.parse().unwrap_or(30),
+                "searchmoves" => match Move::from_str(cmd) {
+                    Ok(mv) => search_moves.push(check_castling_move(bs, mv)),
+                    Err(_) => mode = "none",
+                },
                 _ => mode = "none",
             },
         }
@@ -210,32 +409,52 @@ pub fn handle_go(commands: &[&str], bs: &BoardStack, net_path: &str) {
     if let Some(t) = time.as_mut() {
         *t = t.saturating_sub(5);
     }
-    let settings: SearchSettings = SearchSettings {
-        fpu: 0.0,
-        wdl: None,
-        moves_left: None,
-        c_puct: 0.0,
-        max_nodes: nodes,
-        alpha: 0.0,
-        eps: 0.0,
-        search_type: UCISearch,
-        pst: 0.0,
-        // cap_randomisation: None,
-    };
+    let settings = SearchSettings::engine(nodes, UCISearch, score_model);
     let rt = Runtime::new().unwrap();
-    let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<Packet>(1);
+    let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<EvalRequest>(1);
     let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
     thread::scope(|s| {
         s.builder()
             .name("executor".to_string())
-            .spawn(move |_| executor_static(net_path.to_string(), tensor_exe_recv, ctrl_recv, 1))
+            .spawn(move |_| {
+                executor_static_with_config(
+                    net_path.to_string(),
+                    tensor_exe_recv,
+                    ctrl_recv,
+                    1,
+                    settings.encoder,
+                    net_config,
+                )
+            })
             .unwrap();
 
-        let (best_move, _, _, _, _) = rt.block_on(async {
-            get_move(bs.clone(), tensor_exe_send.clone(), settings.clone()).await
+        let root_moves = if search_moves.is_empty() {
+            RootMoves::All
+        } else {
+            RootMoves::Only(search_moves)
+        };
+        if !root_moves.allows_any(bs) {
+            // searching the moves the gui ruled out would not answer its question
+            println!("info string none of the searchmoves is legal");
+            println!("bestmove 0000");
+            let _ = ctrl_sender.send(Message::StopServer());
+            return;
+        }
+        let result = rt.block_on(async {
+            get_move_with_root_moves(bs.clone(), tensor_exe_send.clone(), settings, root_moves)
+                .await
         });
 
-        println!("bestmove {:#}", best_move);
+        match result {
+            Ok((best_move, _, _, _, _)) => {
+                println!("bestmove {}", standard_castling_move(bs, best_move))
+            }
+            Err(err) => {
+                // the gui still expects a bestmove, the null move tells it we have none
+                println!("info string evaluation failed: {}", err);
+                println!("bestmove 0000");
+            }
+        }
         let _ = ctrl_sender.send(Message::StopServer());
     })
     .unwrap();
@@ -0,0 +1,320 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use cozy_chess::{Color, GameStatus};
+
+use crate::{
+    boardmanager::BoardStack,
+    decoder::{board_data, decode_board},
+    fileformat::{collect_policy_indices, BinaryInput, BinaryOutput, StoredGame},
+    mapper::{ChessStdMapper, MoveMapper},
+};
+
+/// the extensions of the files that make up one data file
+const EXTENSIONS: [&str; 5] = ["bin", "off", "json", "json.tmp", "progress"];
+
+/// result of checking a single data file
+#[derive(Debug, Clone, Default)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub games: usize,
+    pub positions: usize,
+    pub errors: Vec<String>,
+    pub in_progress: bool, // still being written, nothing was checked
+}
+
+impl FileReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// re-decodes every position of the data file at `path` (without extension) and replays each game
+pub fn verify_file(path: impl AsRef<Path>) -> FileReport {
+    let path = path.as_ref().to_path_buf();
+    let mut report = FileReport {
+        path: path.clone(),
+        ..Default::default()
+    };
+
+    // the metadata is written to .json.tmp and renamed when the output is finished
+    if path.with_extension("json.tmp").exists() {
+        if path.with_extension("json").exists() {
+            report.errors.push("leftover .json.tmp file".to_string());
+        } else if BinaryOutput::is_being_written(&path) {
+            report.in_progress = true;
+            return report;
+        } else {
+            let mut error = "metadata was never finished, the writer probably crashed".to_string();
+            if path.with_extension("progress").exists() {
+                error += ", the games up to the last checkpoint can be recovered";
+            }
+            report.errors.push(error);
+            return report;
+        }
+    }
+
+    let input = match BinaryInput::new(&path) {
+        Ok(input) => input,
+        Err(e) => {
+            report.errors.push(format!("can not be opened: {}", e));
+            return report;
+        }
+    };
+    if let Err(e) = check_game_starts(&input) {
+        report.errors.push(e);
+        return report;
+    }
+
+    let mut lengths = vec![];
+    let mut results = [0; 3];
+    for game in 0..input.game_count() {
+        match verify_game(&input, game) {
+            Ok(stored) => {
+                lengths.push(stored.length());
+                match stored.outcome() {
+                    Ok(Some(Color::White)) => results[0] += 1,
+                    Ok(None) => results[1] += 1,
+                    Ok(Some(Color::Black)) => results[2] += 1,
+                    Err(e) => report.errors.push(format!("game {}: {}", game, e)),
+                }
+                report.positions += stored.positions.len();
+            }
+            Err(e) => report.errors.push(format!("game {}: {}", game, e)),
+        }
+        report.games += 1;
+    }
+
+    // the statistics in the metadata can only be checked if every game could be read
+    if lengths.len() == input.game_count() {
+        report.errors.extend(check_meta(&input, &lengths, results));
+    }
+
+    report
+}
+
+fn check_game_starts(input: &BinaryInput) -> Result<(), String> {
+    let meta = input.meta();
+    if !meta.includes_game_start_indices {
+        return Err("file does not include game start indices".to_string());
+    }
+    if meta.game_count == 0 {
+        return match meta.position_count {
+            0 => Ok(()),
+            n => Err(format!("{} positions but no games", n)),
+        };
+    }
+    let starts: Vec<usize> = (0..meta.game_count)
+        .map(|game| input.game_range(game).map(|range| range.start))
+        .collect::<io::Result<_>>()
+        .map_err(|e| e.to_string())?;
+    if starts[0] != 0 {
+        return Err(format!("first game starts at position {}", starts[0]));
+    }
+    // every game has at least its final position
+    for (game, pair) in starts.windows(2).enumerate() {
+        if pair[1] <= pair[0] {
+            return Err(format!(
+                "game {} starts at position {}, after game {} at {}",
+                game,
+                pair[0],
+                game + 1,
+                pair[1]
+            ));
+        }
+    }
+    if starts[starts.len() - 1] >= meta.position_count {
+        return Err(format!(
+            "last game starts at position {}, but there are only {} positions",
+            starts[starts.len() - 1],
+            meta.position_count
+        ));
+    }
+    Ok(())
+}
+
+fn check_meta(input: &BinaryInput, lengths: &[usize], results: [usize; 3]) -> Vec<String> {
+    let meta = input.meta();
+    let mut errors = vec![];
+
+    let position_count: usize = lengths.iter().map(|&length| length + 1).sum();
+    if position_count != meta.position_count {
+        errors.push(format!(
+            "metadata has {} positions, games have {}",
+            meta.position_count, position_count
+        ));
+    }
+    let min_length = lengths.iter().min().map_or(-1, |&l| l as i32);
+    let max_length = lengths.iter().max().map_or(-1, |&l| l as i32);
+    if (min_length, max_length) != (meta.min_game_length, meta.max_game_length) {
+        errors.push(format!(
+            "metadata has game lengths {}..={}, games have {}..={}",
+            meta.min_game_length, meta.max_game_length, min_length, max_length
+        ));
+    }
+    if !lengths.is_empty() {
+        let root_wdl = results.map(|count| count as f32 / lengths.len() as f32);
+        if root_wdl
+            .iter()
+            .zip(meta.root_wdl)
+            .any(|(a, b)| (a - b).abs() > 1e-4)
+        {
+            errors.push(format!(
+                "metadata has root wdl {:?}, game results give {:?}",
+                meta.root_wdl, root_wdl
+            ));
+        }
+    }
+    if !meta.game_provenance.is_empty() && meta.game_provenance.len() != meta.game_count {
+        errors.push(format!(
+            "metadata has provenance for {} of {} games",
+            meta.game_provenance.len(),
+            meta.game_count
+        ));
+    }
+
+    errors
+}
+
+/// replays a game through `BoardStack`, checking that every position follows from the previous played move
+fn verify_game(input: &BinaryInput, game: usize) -> Result<StoredGame, String> {
+    let stored = input.game(game).map_err(|e| e.to_string())?;
+    let meta = input.meta();
+    let bool_count: usize = meta.input_bool_shape.iter().product();
+    let policy_size: usize = meta.policy_shape.iter().product();
+    let game_length = stored.length();
+
+    let mut bs: Option<BoardStack> = None;
+    for (pos_index, position) in stored.positions.iter().enumerate() {
+        let scalars = &position.scalars;
+        let is_final = pos_index == game_length;
+        let error = |message: String| format!("position {}: {}", pos_index, message);
+
+        if scalars.game_id != game
+            || scalars.pos_index != pos_index
+            || scalars.game_length != game_length
+        {
+            return Err(error(format!(
+                "stored as game {} position {} of {}",
+                scalars.game_id, scalars.pos_index, scalars.game_length
+            )));
+        }
+        if scalars.is_final_position != is_final {
+            return Err(error(format!(
+                "is_final_position is {}",
+                scalars.is_final_position
+            )));
+        }
+
+        // the first position is decoded, all later ones have to match the replayed game
+        let bools = position.unpacked_board_bools();
+        let bools = &bools[..bool_count.min(bools.len())];
+        let bs = match &mut bs {
+            Some(bs) => {
+                let (board_scalars, board_bools) = board_data(bs);
+                // the repetition counter and history planes depend on moves before the start of the game,
+                // so only the current board is compared
+                let same_scalars = (0..board_scalars.len())
+                    .all(|i| i == 6 || board_scalars[i] == position.board_scalars[i]);
+                if !same_scalars || board_bools[..] != bools[..board_bools.len()] {
+                    return Err(error(
+                        "board does not follow from the previous played move".to_string(),
+                    ));
+                }
+                bs
+            }
+            None => match decode_board(&position.board_scalars, bools) {
+                Some(board) => bs.insert(BoardStack::new(board)),
+                None => return Err(error("board can not be decoded".to_string())),
+            },
+        };
+
+        if let Some(&index) = position
+            .policy_indices
+            .iter()
+            .find(|&&i| i as usize >= policy_size)
+        {
+            return Err(error(format!("policy index {} is out of range", index)));
+        }
+
+        if is_final {
+            if scalars.is_terminal != bs.is_terminal() {
+                return Err(error(format!("is_terminal is {}", scalars.is_terminal)));
+            }
+            if !position.policy_indices.is_empty() {
+                return Err(error("final position has a policy".to_string()));
+            }
+            continue;
+        }
+
+        if bs.status() != GameStatus::Ongoing {
+            return Err(error("game continues after it has ended".to_string()));
+        }
+        let (_, legal_indices) = collect_policy_indices(bs);
+        if position.policy_indices != legal_indices {
+            return Err(error("policy indices are not the legal moves".to_string()));
+        }
+        let policy_sum: f32 = position.policy_values.iter().sum();
+        if position.policy_values.iter().any(|&p| p < 0.0)
+            || !(policy_sum.is_nan() || (1.0 - policy_sum).abs() < 0.001)
+        {
+            return Err(error(format!(
+                "policy is not normalized, sum {}",
+                policy_sum
+            )));
+        }
+
+        let played = match usize::try_from(scalars.played_mv) {
+            Ok(index) if legal_indices.contains(&(index as u32)) => ChessStdMapper::get()
+                .index_to_move(bs.board(), index)
+                .unwrap(),
+            _ => {
+                return Err(error(format!(
+                    "played move {} is not legal",
+                    scalars.played_mv
+                )))
+            }
+        };
+        bs.play(played);
+    }
+
+    Ok(stored)
+}
+
+/// moves all files of the data file at `path` (without extension) into `dir`
+pub fn quarantine(path: impl AsRef<Path>, dir: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let dir = dir.as_ref();
+    if BinaryOutput::is_being_written(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "the file is still being written",
+        ));
+    }
+    fs::create_dir_all(dir)?;
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    for ext in EXTENSIONS {
+        let file = path.with_extension(ext);
+        if file.exists() {
+            fs::rename(&file, dir.join(name).with_extension(ext))?;
+        }
+    }
+    Ok(())
+}
+
+/// the data files in `dir`, as paths without extension
+pub fn find_data_files(dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "bin") {
+            paths.push(path.with_extension(""));
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
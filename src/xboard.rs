@@ -0,0 +1,416 @@
+use crate::{
+    boardmanager::BoardStack,
+    executor::{executor_static, EvalClient, EvalRequest, Message},
+    mcts::{extend_search, search_result},
+    mcts_trainer::{Tree, TypeRequest::NonTrainerSearch},
+    scoremodel::ScoreModel,
+    settings::SearchSettings,
+    uci::{check_castling_move, standard_castling_move},
+};
+use cozy_chess::{Board, Color, GameStatus, Move};
+use flume::TryRecvError;
+use std::{cmp::max, io, str::FromStr, thread, time::Instant};
+use tokio::runtime::Runtime;
+
+// same node rate estimate as `uci::handle_go`
+const MS_PER_NODE: u128 = 120;
+// nodes searched between two thinking outputs
+const THINKING_CHUNK: u32 = 100;
+// analysis stops growing the tree after this many nodes
+const MAX_ANALYSIS_NODES: u32 = 1_000_000;
+
+pub(crate) struct TimeControl {
+    moves_per_session: u32, // 0 means the base time is for the whole game
+    base_ms: u128,
+    inc_ms: u128,
+    fixed_ms: Option<u128>,  // `st`, overrides the clocks
+    engine_ms: Option<u128>, // `time`
+}
+
+impl TimeControl {
+    pub(crate) fn new() -> Self {
+        Self {
+            moves_per_session: 0,
+            base_ms: 0,
+            inc_ms: 0,
+            fixed_ms: None,
+            engine_ms: None,
+        }
+    }
+
+    // `level MPS BASE INC`, where BASE is either minutes or minutes:seconds
+    pub(crate) fn set_level(&mut self, commands: &[&str]) {
+        if commands.len() < 4 {
+            return;
+        }
+        self.moves_per_session = commands[1].parse().unwrap_or(0);
+        self.base_ms = match commands[2].split_once(':') {
+            Some((min, sec)) => (min.parse().unwrap_or(0) * 60 + sec.parse().unwrap_or(0)) * 1000,
+            None => commands[2].parse::<u128>().unwrap_or(0) * 60 * 1000,
+        };
+        self.inc_ms = (commands[3].parse::<f32>().unwrap_or(0.0) * 1000.0) as u128;
+        self.fixed_ms = None;
+    }
+
+    pub(crate) fn nodes(&self, bs: &BoardStack) -> u128 {
+        let time = match self.fixed_ms {
+            Some(fixed) => fixed,
+            None => {
+                let remaining = self.engine_ms.unwrap_or(self.base_ms);
+                let moves_to_go = if self.moves_per_session > 0 {
+                    let played = (bs.board().fullmove_number() as u32).saturating_sub(1)
+                        % self.moves_per_session;
+                    self.moves_per_session - played
+                } else {
+                    30
+                };
+                remaining / moves_to_go as u128 + self.inc_ms * 3 / 4
+            }
+        };
+        // 5ms move overhead
+        max(1, time.saturating_sub(5) / MS_PER_NODE)
+    }
+}
+
+pub(crate) struct GameState {
+    start: Board,
+    moves: Vec<Move>,
+    pub(crate) bs: BoardStack,
+}
+
+impl GameState {
+    pub(crate) fn new(start: Board) -> Self {
+        Self {
+            bs: BoardStack::new(start.clone()),
+            start,
+            moves: Vec::new(),
+        }
+    }
+
+    pub(crate) fn play(&mut self, mv: Move) {
+        self.moves.push(mv);
+        self.bs.play(mv);
+    }
+
+    // BoardStack can't take back moves, so replay the game without the last one
+    pub(crate) fn undo(&mut self) {
+        if self.moves.pop().is_some() {
+            self.bs = BoardStack::new(self.start.clone());
+            for &mv in &self.moves {
+                self.bs.play(mv);
+            }
+        }
+    }
+
+    pub(crate) fn parse_move(&self, mv: &str) -> Option<Move> {
+        let mv = check_castling_move(&self.bs, Move::from_str(mv).ok()?);
+        let mut legal_moves = Vec::new();
+        self.bs.board().generate_moves(|moves| {
+            // Unpack dense move set into move list
+            legal_moves.extend(moves);
+            false
+        });
+        legal_moves.into_iter().find(|&x| x == mv)
+    }
+
+    pub(crate) fn result(&self) -> Option<&'static str> {
+        match self.bs.status() {
+            GameStatus::Ongoing => None,
+            GameStatus::Drawn => Some("1/2-1/2 {Draw}"),
+            GameStatus::Won => match !self.bs.board().side_to_move() {
+                Color::White => Some("1-0 {White mates}"),
+                Color::Black => Some("0-1 {Black mates}"),
+            },
+        }
+    }
+}
+
+pub(crate) struct XBoard {
+    pub(crate) game: GameState,
+    pub(crate) engine_color: Option<Color>, // None in force mode
+    post: bool,
+    analyzing: bool,
+    analysis: Option<(Tree, Instant)>,
+    time_control: TimeControl,
+    score_model: ScoreModel,
+    client: EvalClient,
+    rt: Runtime,
+}
+
+// UCISearch would print UCI info lines
+fn search_settings(max_nodes: u128, score_model: ScoreModel) -> SearchSettings {
+    SearchSettings::engine(max_nodes, NonTrainerSearch, score_model)
+}
+
+impl XBoard {
+    pub(crate) fn new(client: EvalClient) -> Self {
+        Self {
+            game: GameState::new(Board::default()),
+            engine_color: Some(Color::Black),
+            post: false,
+            analyzing: false,
+            analysis: None,
+            time_control: TimeControl::new(),
+            score_model: ScoreModel::default(),
+            client,
+            rt: Runtime::new().unwrap(),
+        }
+    }
+
+    // returns false when the engine should exit
+    pub(crate) fn handle_command(&mut self, input: &str) -> bool {
+        let commands = input.split_whitespace().collect::<Vec<_>>();
+
+        match *commands.first().unwrap_or(&"oops") {
+            "protover" => {
+                println!(
+                    "feature myname=\"TrueZero-latest {}\" setboard=1 usermove=1 analyze=1 ping=1 sigint=0 sigterm=0 colors=0 done=1",
+                    env!("CARGO_PKG_VERSION")
+                );
+            }
+            "new" => {
+                self.set_board(Board::default());
+                self.engine_color = Some(Color::Black);
+                self.time_control.fixed_ms = None;
+            }
+            "force" => self.engine_color = None,
+            "go" => self.engine_color = Some(self.game.bs.board().side_to_move()),
+            "usermove" => match commands.get(1) {
+                Some(mv) => self.user_move(mv),
+                None => println!("Error (no move): {}", input.trim()),
+            },
+            "level" => self.time_control.set_level(&commands),
+            "st" => {
+                if let Some(Ok(secs)) = commands.get(1).map(|x| x.parse::<u128>()) {
+                    self.time_control.fixed_ms = Some(secs * 1000);
+                }
+            }
+            // clocks are given in centiseconds
+            "time" => {
+                self.time_control.engine_ms = commands
+                    .get(1)
+                    .and_then(|x| x.parse().ok())
+                    .map(|x: u128| x * 10)
+            }
+            "otim" => {} // the opponent's clock doesn't change how long we search
+            "setboard" => match Board::from_fen(&commands[1..].join(" "), false) {
+                Ok(board) => self.set_board(board),
+                Err(_) => println!("tellusererror Illegal position"),
+            },
+            "undo" => {
+                self.game.undo();
+                self.analysis = None;
+            }
+            "remove" => {
+                self.game.undo();
+                self.game.undo();
+                self.analysis = None;
+            }
+            "result" => self.engine_color = None,
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "analyze" => {
+                self.analyzing = true;
+                self.engine_color = None;
+                self.analysis = None;
+            }
+            "exit" => {
+                self.analyzing = false;
+                self.analysis = None;
+            }
+            "ping" => println!("pong {}", commands.get(1).unwrap_or(&"")),
+            "quit" => return false,
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer"
+            | "name" | "rating" | "ics" | "." | "?" | "oops" => {}
+            // protocol version 1 sends moves without the usermove prefix
+            cmd => {
+                if self.game.parse_move(cmd).is_some() {
+                    self.user_move(cmd);
+                } else {
+                    println!("Error (unknown command): {}", cmd);
+                }
+            }
+        }
+
+        if self.engine_color == Some(self.game.bs.board().side_to_move())
+            && !self.game.bs.is_terminal()
+        {
+            self.think_and_move();
+        }
+        true
+    }
+
+    fn set_board(&mut self, board: Board) {
+        self.game = GameState::new(board);
+        self.analysis = None;
+    }
+
+    fn user_move(&mut self, mv: &str) {
+        match self.game.parse_move(mv) {
+            Some(legal) if !self.game.bs.is_terminal() => {
+                self.game.play(legal);
+                self.analysis = None;
+                if self.engine_color.is_some() {
+                    if let Some(result) = self.game.result() {
+                        println!("{}", result);
+                    }
+                }
+            }
+            _ => println!("Illegal move: {}", mv),
+        }
+    }
+
+    fn think_and_move(&mut self) {
+        let nodes = self.time_control.nodes(&self.game.bs);
+        let settings = search_settings(nodes, self.score_model);
+        let sw = Instant::now();
+        let mut tree = Tree::new(self.game.bs.clone(), settings);
+
+        // search in chunks so that thinking output can be posted while searching
+        while (tree.nodes[0].visits as u128) < nodes {
+            let target = (tree.nodes[0].visits + THINKING_CHUNK).min(nodes as u32);
+            let client = &mut self.client;
+            let result = self
+                .rt
+                .block_on(async { extend_search(&mut tree, client, target, sw, 1).await });
+            if let Err(err) = result {
+                println!("tellusererror Evaluation failed: {}", err);
+                break;
+            }
+            if self.post {
+                self.print_thinking(&tree, sw);
+            }
+        }
+
+        // a failed search can leave the root unexpanded, then there is no move to play
+        if tree.nodes[0].children.is_empty() {
+            return;
+        }
+        let (mv, _, _, _, _) = search_result(&tree);
+        println!("move {}", standard_castling_move(&self.game.bs, mv));
+        self.game.play(mv);
+        if let Some(result) = self.game.result() {
+            println!("{}", result);
+        }
+    }
+
+    // grow the analysis tree a bit, returns false once there is nothing left to do
+    fn analyse_step(&mut self) -> bool {
+        if self.game.bs.is_terminal() {
+            return false;
+        }
+        let (mut tree, sw) = match self.analysis.take() {
+            Some(analysis) => analysis,
+            None => (
+                Tree::new(
+                    self.game.bs.clone(),
+                    search_settings(MAX_ANALYSIS_NODES as u128, self.score_model),
+                ),
+                Instant::now(),
+            ),
+        };
+        if tree.nodes[0].visits >= MAX_ANALYSIS_NODES {
+            self.analysis = Some((tree, sw));
+            return false;
+        }
+
+        let target = (tree.nodes[0].visits + THINKING_CHUNK).min(MAX_ANALYSIS_NODES);
+        let client = &mut self.client;
+        let result = self
+            .rt
+            .block_on(async { extend_search(&mut tree, client, target, sw, 1).await });
+        if let Err(err) = result {
+            println!("tellusererror Evaluation failed: {}", err);
+            return false;
+        }
+        // analysis output is always shown, regardless of post
+        self.print_thinking(&tree, sw);
+        self.analysis = Some((tree, sw));
+        true
+    }
+
+    // `ply score time nodes pv`, with the score in centipawns for the side to move and the time in centiseconds
+    fn print_thinking(&self, tree: &Tree, sw: Instant) {
+        let pv = tree.principal_variation();
+        let q = tree.nodes[0].get_q_val(tree.settings); // white's point of view
+        let q = match tree.board.board().side_to_move() {
+            Color::White => q,
+            Color::Black => -q,
+        };
+
+        let mut bs = tree.board.clone();
+        let mut pv_str = String::new();
+        for mv in &pv {
+            pv_str.push_str(&format!("{} ", standard_castling_move(&bs, *mv)));
+            bs.play(*mv);
+        }
+
+        println!(
+            "{} {} {} {} {}",
+            pv.len(),
+            self.score_model.cp(q, None),
+            sw.elapsed().as_millis() / 10,
+            tree.nodes[0].visits,
+            pv_str.trim_end(),
+        );
+    }
+}
+
+pub fn run_xboard(net_path: &str) {
+    // the executor keeps the network loaded for the whole session
+    let (tensor_exe_send, tensor_exe_recv) = flume::bounded::<EvalRequest>(1);
+    let (ctrl_sender, ctrl_recv) = flume::bounded::<Message>(1);
+    let net_path = net_path.to_string();
+    let encoder = search_settings(0, ScoreModel::default()).encoder;
+    let executor = thread::Builder::new()
+        .name("executor".to_string())
+        .spawn(move || executor_static(net_path, tensor_exe_recv, ctrl_recv, 1, encoder))
+        .unwrap();
+
+    // read stdin on a separate thread so that analysis can be interrupted by new commands
+    let (input_send, input_recv) = flume::unbounded::<String>();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            match line {
+                Ok(line) => {
+                    if input_send.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    let mut xboard = XBoard::new(EvalClient::new(tensor_exe_send, encoder));
+
+    let mut idle = false;
+    loop {
+        let input = if xboard.analyzing && !idle {
+            match input_recv.try_recv() {
+                Ok(input) => Some(input),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            // got EOF, exit
+            match input_recv.recv() {
+                Ok(input) => Some(input),
+                Err(_) => break,
+            }
+        };
+
+        match input {
+            Some(input) => {
+                idle = false;
+                if !xboard.handle_command(&input) {
+                    break;
+                }
+            }
+            None => idle = !xboard.analyse_step(),
+        }
+    }
+
+    let _ = ctrl_sender.send(Message::StopServer());
+    let _ = executor.join();
+}